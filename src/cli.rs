@@ -1,6 +1,10 @@
 use std::sync::OnceLock;
+use std::path::PathBuf;
 use clap::{
+    Command,
+    CommandFactory,
     Parser,
+    Subcommand,
     builder::styling::{
         AnsiColor,
         Effects,
@@ -8,9 +12,11 @@ use clap::{
     },
 };
 use enum_dispatch::enum_dispatch;
+use log::warn;
 
 use crate::{
     types::Result,
+    Settings,
     commands::{
         rlx::Rlx,
         vib::Vib,
@@ -19,6 +25,10 @@ use crate::{
         pot::Pot,
         chgdiff::Chgdiff,
         chgsum::Chgsum,
+        chgscale::Chgscale,
+        dipole::Dipole,
+        lineprofile::LineProfile,
+        planar_average::PlanarAverage,
         workfunc::Workfunc,
         dos::Dos,
         band::Band,
@@ -28,6 +38,8 @@ use crate::{
         gap::Gap,
         uc::Uc, 
         modelnac::ModelNac,
+        config::Config,
+        completions::Completions,
     },
 };
 
@@ -49,10 +61,16 @@ pub fn get_style() -> Styles {
 #[enum_dispatch]
 pub trait OptProcess {
     fn process(&self) -> Result<()>;
+
+    /// Same as `process`, but the results are printed to stdout as JSON for the scripts driving
+    /// rsgrad. The commands not supporting it fall back to `process`.
+    fn process_json(&self) -> Result<()> {
+        warn!("JSON output is not supported by this command, falling back to the text output.");
+        self.process()
+    }
 }
 
 
-#[enum_dispatch(OptProcess)]
 #[derive(Debug, Parser)]
 #[command(name = "rsgrad",
             about = r"A command-line tool to help VASP players play better with VASP.
@@ -61,6 +79,24 @@ If you want more detailed documentation, just visit https://ionizing.github.io/r
             author = "@Ionizing github.com/Ionizing/rsgrad",
             styles = get_style()
             )]
+struct Cli {
+    #[arg(long, value_name = "PATH", global = true)]
+    /// Specify the rsgrad configuration file, it takes precedence over the `RSGRAD_CONFIG`
+    /// environment variable and `.rsgrad.toml` at your home dir.
+    config: Option<PathBuf>,
+
+    #[arg(long, global = true)]
+    /// Print the results as JSON to stdout for the scripts driving rsgrad, the logs still go to
+    /// stderr. Supported by `uc` and `chgsum`, the other commands print text as usual.
+    json: bool,
+
+    #[command(subcommand)]
+    command: Opt,
+}
+
+
+#[enum_dispatch(OptProcess)]
+#[derive(Debug, Subcommand)]
 enum Opt {
     Rlx,
 
@@ -76,6 +112,14 @@ enum Opt {
 
     Chgsum,
 
+    Chgscale,
+
+    Dipole,
+
+    LineProfile,
+
+    PlanarAverage,
+
     Workfunc,
 
     Dos,
@@ -95,9 +139,48 @@ enum Opt {
     Uc,
 
     ModelNac,
+
+    Config,
+
+    #[command(hide = true)]
+    Completions,
+}
+
+
+/// The whole command tree of rsgrad, e.g. to generate the shell completions.
+pub fn command() -> Command {
+    Cli::command()
 }
 
 
 pub fn run() -> Result<()> {
-    Opt::parse().process()
+    let cli = Cli::parse();
+    if let Some(path) = cli.config {
+        Settings::set_config_path(path);
+    }
+    if cli.json {
+        cli.command.process_json()
+    } else {
+        cli.command.process()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_config() {
+        for args in [
+            vec!["rsgrad", "--config", "x.toml", "uc", "1eV"],
+            vec!["rsgrad", "uc", "1eV", "--config", "x.toml"],
+            vec!["rsgrad", "config", "list-potcars", "--config", "x.toml"],
+            vec!["rsgrad", "pot", "--config", "x.toml"],
+            vec!["rsgrad", "--config", "x.toml", "pot", "lda"],
+        ] {
+            let cli = Cli::try_parse_from(&args).unwrap();
+            assert_eq!(cli.config, Some(PathBuf::from("x.toml")), "{:?}", args);
+        }
+    }
 }
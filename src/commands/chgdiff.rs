@@ -1,71 +1,101 @@
-use std::path::PathBuf;
+use std::path::{
+    Path,
+    PathBuf,
+};
 use clap::Args;
 use log::info;
-use anyhow::{
-    anyhow,
-    Context,
-};
-use rayon;
+use anyhow::Context;
 use crate::{
     types::Result,
     ChargeDensity,
     ChargeType,
     OptProcess,
+    SymmetryOp,
+    commands::chgsum::{
+        Reduce,
+        apply_weights,
+        check_compatibility,
+        load_charge_densities,
+        reduce_charge_densities,
+    },
 };
 
 
+/// Difference of the charge densities: the later ones are subtracted from the first, e.g.
+/// `AB - A - B`. They are checked to be compatible in advance, `paths` are the files `chgs` are
+/// read from and only used in the error message. The structure and title of the first one are
+/// kept, the same as `AB - (A + B)`.
+pub fn charge_difference<P: AsRef<Path>>(paths: &[P], chgs: Vec<ChargeDensity>) -> Result<ChargeDensity> {
+    check_compatibility(paths, &chgs)?;
+    let pos = chgs.first().context("No charge density to subtract from.")?.pos.clone();
+    let weights = (0 .. chgs.len())
+        .map(|i| if i == 0 { 1.0 } else { -1.0 })
+        .collect::<Vec<_>>();
+
+    // `Add` appends the atoms of each operand, only the grids are wanted here
+    let mut diff = reduce_charge_densities(apply_weights(chgs, &weights)?, Reduce::Sum)?;
+    diff.pos = pos;
+    Ok(diff)
+}
+
+
 #[derive(Debug, Args)]
 /// Calculate charge density difference. 
 ///
-/// The operation is performed by `chgdiff = chgcar_ab - (chgcar_a + chgcar_b)`.
+/// The later CHGCARs are subtracted from the first one, i.e. `chgdiff = chgcar_ab - chgcar_a -
+/// chgcar_b` for three inputs or `chgdiff = chgcar_ab - chgcar_a` for two inputs. The integrated
+/// difference `∫ Δρ dV` and the integrated absolute difference `∫ |Δρ| dV` are reported.
 pub struct Chgdiff {
-    /// The CHGCAR of A+B system
-    chgcar_ab: PathBuf,
-
-    /// The CHGCAR of A system
-    chgcar_a: PathBuf,
-
-    /// The CHGCAR of B system
-    chgcar_b: PathBuf,
+    #[arg(required = true, num_args = 2..=3, value_name = "CHGCAR")]
+    /// The CHGCAR of A+B system, followed by the CHGCARs of A and optionally B system
+    inputs: Vec<PathBuf>,
 
     #[arg(short, long, default_value = "CHGDIFF.vasp")]
     /// The output charge density difference file path
     output: PathBuf,
+
+    #[arg(long, allow_hyphen_values = true)]
+    /// Enforce a known symmetry to suppress the numerical noise, e.g. "-1 0 0 0 1 0 0 0 1 0 0 0"
+    /// for the mirror x -> -x.
+    ///
+    /// The operation acts on fractional coordinates as `f' = R·f + t`, given by 12 numbers: the
+    /// 9 integer elements of R in row-major order followed by the 3 elements of t. The density is
+    /// averaged with its image under the operation, trilinear interpolation is used if the
+    /// operation does not map grid points onto grid points.
+    symmetrize: Option<SymmetryOp>,
+
+    #[arg(long)]
+    /// Write the absolute value of the difference `|chgdiff|` instead, a quick look at where the
+    /// density changed.
+    abs: bool,
 }
 
 
 impl OptProcess for Chgdiff {
     fn process(&self) -> Result<()> {
-        let mut chgcar_ab: Result<ChargeDensity> = Err(anyhow!(""));
-        let mut chgcar_a: Result<ChargeDensity>  = Err(anyhow!(""));
-        let mut chgcar_b: Result<ChargeDensity>  = Err(anyhow!(""));
-
-        rayon::scope(|s| {
-            s.spawn(|_| {
-                info!("Reading charge density from {:?}", self.chgcar_ab);
-                chgcar_ab = ChargeDensity::from_file(&self.chgcar_ab, ChargeType::Chgcar)
-                    .context(format!("Failed to read charge density from {:?}", self.chgcar_ab))
-            });
-            s.spawn(|_| {
-                info!("Reading charge density from {:?}", self.chgcar_a);
-                chgcar_a = ChargeDensity::from_file(&self.chgcar_a, ChargeType::Chgcar)
-                    .context(format!("Failed to read charge density from {:?}", self.chgcar_a))
-            });
-            s.spawn(|_| {
-                info!("Reading charge density from {:?}", self.chgcar_b);
-                chgcar_b = ChargeDensity::from_file(&self.chgcar_b, ChargeType::Chgcar)
-                    .context(format!("Failed to read charge density from {:?}", self.chgcar_b))
-            });
-        });
-
-        let chgcar_ab = chgcar_ab?;
-        let chgcar_a = chgcar_a?;
-        let chgcar_b = chgcar_b?;
-
-        info!("Calculating charge density difference by `CHGDIFF = {:?} - ({:?} + {:?})`", 
-              self.chgcar_ab, self.chgcar_a, self.chgcar_b);
-
-        let chgdiff = (chgcar_ab - (chgcar_a + chgcar_b)?)?;
+        let chgcars = load_charge_densities(&self.inputs, ChargeType::Chgcar)?;
+
+        info!("Calculating charge density difference by `CHGDIFF = {}`",
+              self.inputs.iter().map(|p| format!("{:?}", p)).collect::<Vec<_>>().join(" - "));
+        let chgdiff = charge_difference(&self.inputs, chgcars)?;
+
+        // Symmetrization is linear, applying it to the difference is the same as applying it to
+        // each input before differencing.
+        let chgdiff = if let Some(op) = self.symmetrize.as_ref() {
+            info!("Symmetrizing charge density difference with {:?}", op);
+            chgdiff.symmetrize_grid(op)?
+        } else {
+            chgdiff
+        };
+
+        println!("Integrated difference: {:.6} e", chgdiff.integrate());
+        println!("Integrated absolute difference: {:.6} e", chgdiff.integrate_abs());
+
+        let chgdiff = if self.abs {
+            chgdiff.abs()
+        } else {
+            chgdiff
+        };
 
         info!("Writing charge difference to {:?}", self.output);
 
@@ -74,3 +104,40 @@ impl OptProcess for Chgdiff {
         Ok(())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array3;
+    use crate::vasp_parsers::chg::cubic_density;
+
+    #[test]
+    fn test_charge_difference() {
+        let density = |chg| cubic_density(2.0, chg);
+        let a = Array3::from_shape_fn((2, 2, 2), |(i, j, k)| (i + 2 * j + 4 * k) as f64 * 0.1);
+        let b = Array3::from_shape_fn((2, 2, 2), |(i, _, _)| 0.5 + i as f64);
+        let transfer = Array3::from_shape_fn((2, 2, 2), |(i, j, k)| if (i + j + k) % 2 == 0 { 0.25 } else { -0.25 });
+        let ab = &a + &b + &transfer;
+
+        let paths = ["AB/CHGCAR", "A/CHGCAR", "B/CHGCAR"];
+        let diff = charge_difference(&paths, vec![density(ab.clone()), density(a.clone()), density(b.clone())]).unwrap();
+        assert_eq!(diff.pos.comment, "cubic cell");
+        assert_eq!(diff.pos.ions_per_type, vec![1]);
+        assert_eq!(diff.pos.pos_frac.len(), 1);
+        for (x, y) in diff.chg[0].iter().zip(transfer.iter()) {
+            assert!((x - y).abs() < 1E-12);
+        }
+        // The transfer cancels out, while its magnitude is 0.25 e/A^3 everywhere in the 8 A^3 cell
+        assert!(diff.integrate().abs() < 1E-12);
+        assert!((diff.integrate_abs() - 2.0).abs() < 1E-12);
+
+        let diff = charge_difference(&paths[.. 2], vec![density(ab.clone()), density(a.clone())]).unwrap();
+        for (x, y) in diff.chg[0].iter().zip((&b + &transfer).iter()) {
+            assert!((x - y).abs() < 1E-12);
+        }
+
+        let err = charge_difference(&paths, vec![density(ab), density(a), density(Array3::zeros((2, 2, 3)))]).unwrap_err();
+        assert!(format!("{:#}", err).contains("\"AB/CHGCAR\" and \"B/CHGCAR\""));
+    }
+}
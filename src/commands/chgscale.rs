@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use anyhow::bail;
+use crate::{
+    types::Result,
+    ChargeDensity,
+    ChargeType,
+    OptProcess,
+};
+
+
+#[derive(Debug, Args)]
+/// Multiply a charge density by a constant factor and write the result.
+///
+/// Every grid point is multiplied, e.g. to rescale the occupancy of a PARCHG. The lattice, atom
+/// positions and augmentation part are written unchanged.
+pub struct Chgscale {
+    /// Input CHGCAR file, gzip compressed file (e.g. `CHGCAR.gz`) is read directly.
+    input: PathBuf,
+
+    #[arg(long, allow_hyphen_values = true)]
+    /// The factor multiplied to every grid point, e.g. `--factor 2` or `--factor -1`.
+    factor: f64,
+
+    #[arg(short, long, default_value = "CHGSCALE.vasp")]
+    /// Output file path, the CHGCAR is gzip compressed if it ends with `.gz`.
+    output: PathBuf,
+}
+
+
+impl OptProcess for Chgscale {
+    fn process(&self) -> Result<()> {
+        if !self.factor.is_finite() {
+            bail!("The factor should be a finite number, got {}.", self.factor);
+        }
+
+        info!("Reading charge density from {:?}", self.input);
+        let chg = ChargeDensity::from_file(&self.input, ChargeType::Chgcar)?;
+        info!("Integrated charge of input: {:.6} e", chg.integrate());
+
+        info!("Scaling charge density by {}", self.factor);
+        let chg = chg.scale(self.factor);
+        info!("Integrated charge of result: {:.6} e", chg.integrate());
+
+        info!("Writing scaled charge density to {:?}", self.output);
+        chg.to_file(&self.output)?;
+
+        Ok(())
+    }
+}
@@ -1,56 +1,1316 @@
-use std::path::PathBuf;
-use clap::Args;
-use log::info;
-use anyhow::{anyhow, Context};
+//! Summation of volumetric data.
+//!
+//! Besides the `chgsum` command, the steps it is made of are public so that your own binary can
+//! chain them with other operations on [`ChargeDensity`] without writing intermediate files:
+//!
+//! ```no_run
+//! use rsgrad::{ChargeType, types::Axis};
+//! use rsgrad::commands::chgsum::{load_charge_densities, sum_charge_densities};
+//!
+//! let chgs = load_charge_densities(&["CHGCAR_1", "CHGCAR_2"], ChargeType::Chgcar)?;
+//! let sum = sum_charge_densities(chgs)?;
+//! let profile = sum.planar_average(Axis::Z);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Data-flow contract between chained steps:
+//!
+//! - Each step takes its input `ChargeDensity` by value or by reference and never touches the
+//!   disk; only the first step reads files and only the last one calls `to_file`.
+//! - For `ChargeType::Chgcar` the in-memory data is the density (already divided by the cell
+//!   volume), it is multiplied back when written. Steps must keep this normalization.
+//! - A step producing a new `ChargeDensity` keeps `ngrid`, `pos` and `chgtype` of its input
+//!   unless it documents otherwise, binary operations require both operands to share the grid
+//!   and the lattice and return an error instead of panicking.
+//!
+//! When the inputs don't fit in memory together, [`stream_reduce`] fuses the reading and the
+//! combining steps and holds only the running result.
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use clap::{
+    Args,
+    ValueEnum,
+};
+use log::{
+    info,
+    warn,
+};
+use anyhow::{anyhow, bail, Context};
 use rayon::prelude::*;
+use serde_json::json;
 use crate::{
-    types::Result,
+    types::{Result, Mat33},
     ChargeDensity,
     ChargeType,
+    GridFormat,
     OptProcess,
+    Poscar,
+    SpinChannel,
+    vasp_parsers::chg_cache::default_cache_dir,
+    commands::common::{
+        levenshtein,
+        ProgressBar,
+    },
 };
 
+
+/// Read multiple volumetric data files in parallel, the order of `paths` is preserved.
+pub fn load_charge_densities<P>(paths: &[P], chgtype: ChargeType) -> Result<Vec<ChargeDensity>>
+where P: AsRef<Path> + Sync {
+    load_charge_densities_with_progress(paths, chgtype, &ProgressBar::hidden())
+}
+
+
+/// Same as `load_charge_densities`, `progress` ticks as each file finishes reading.
+pub fn load_charge_densities_with_progress<P>(paths: &[P], chgtype: ChargeType, progress: &ProgressBar) -> Result<Vec<ChargeDensity>>
+where P: AsRef<Path> + Sync {
+    load_charge_densities_cached(paths, chgtype, None, progress)
+}
+
+
+/// Same as `load_charge_densities_with_progress`, the parsed files are cached in `cache_dir` if
+/// it's given, see [`ChargeDensity::from_file_cached`].
+pub fn load_charge_densities_cached<P>(paths: &[P], chgtype: ChargeType, cache_dir: Option<&Path>, progress: &ProgressBar) -> Result<Vec<ChargeDensity>>
+where P: AsRef<Path> + Sync {
+    paths.par_iter()
+        .map(|path| {
+            let chg = read_charge_density(path.as_ref(), chgtype, cache_dir);
+            progress.inc();
+            chg
+        })
+        .collect()
+}
+
+
+fn read_charge_density(path: &Path, chgtype: ChargeType, cache_dir: Option<&Path>) -> Result<ChargeDensity> {
+    info!("Reading charge density from {:?}", path);
+    let chg = match cache_dir {
+        Some(dir) => ChargeDensity::from_file_cached(path, chgtype, dir),
+        None      => ChargeDensity::from_file(path, chgtype),
+    };
+    chg.with_context(|| format!("Failed to read charge density from {:?}", path))
+}
+
+
+/// Sum all the charge densities, they must share the same grid and lattice.
+pub fn sum_charge_densities(chgs: Vec<ChargeDensity>) -> Result<ChargeDensity> {
+    reduce_charge_densities(chgs, Reduce::Sum)
+}
+
+
+/// Check that all the charge densities can be combined with the first one, the error tells which
+/// two files are incompatible and how they differ. `paths` are the files `chgs` read from.
+pub fn check_compatibility<P: AsRef<Path>>(paths: &[P], chgs: &[ChargeDensity]) -> Result<()> {
+    let (first_path, first) = match (paths.first(), chgs.first()) {
+        (Some(p), Some(c)) => (p.as_ref(), c),
+        _ => return Ok(()),
+    };
+
+    for (path, chg) in paths.iter().zip(chgs.iter()).skip(1) {
+        first.check_compatible(chg)
+            .with_context(|| format!("{:?} and {:?} cannot be combined.", first_path, path.as_ref()))?;
+    }
+    Ok(())
+}
+
+
+/// Check that the inputs can be combined with the first one by reading their headers only, thus
+/// the incompatible inputs are rejected before the heavy reading of the grid data. The lattice
+/// vectors must agree within `tolerance` in Å, and the grids must be the same if `check_grid`.
+pub fn check_headers<P: AsRef<Path>>(paths: &[P], tolerance: f64, check_grid: bool) -> Result<()> {
+    let headers = paths.par_iter()
+        .map(|p| ChargeDensity::read_header(p))
+        .collect::<Result<Vec<_>>>()?;
+
+    let (first_path, (first_pos, first_grid)) = match (paths.first(), headers.first()) {
+        (Some(p), Some(h)) => (p.as_ref(), h),
+        _ => return Ok(()),
+    };
+    let first_cell = first_pos.clone().normalize().cell;
+
+    for (path, (pos, grid)) in paths.iter().zip(headers.iter()).skip(1) {
+        let path = path.as_ref();
+        let deviation = lattice_deviation(&first_cell, &pos.clone().normalize().cell);
+        if deviation > tolerance {
+            bail!("{:?} and {:?} cannot be combined: their lattice vectors differ by up to {:.3E} A, beyond the tolerance {:.3E} A.",
+                  first_path, path, deviation, tolerance);
+        }
+        if check_grid && grid != first_grid {
+            bail!("{:?} and {:?} cannot be combined: different grid dimensions {:?} != {:?}. Consider `--resample`.",
+                  first_path, path, first_grid, grid);
+        }
+    }
+    Ok(())
+}
+
+
+// Same as `ChargeDensity::grid_spacing`, from the header only.
+fn grid_spacing(pos: &Poscar, ngrid: [usize; 3]) -> [f64; 3] {
+    let (lengths, _) = pos.get_cell_params();
+    [0, 1, 2].map(|i| lengths[i] / ngrid[i] as f64)
+}
+
+
+// Largest difference between the elements of two lattices.
+fn lattice_deviation(a: &Mat33<f64>, b: &Mat33<f64>) -> f64 {
+    a.iter().flatten()
+        .zip(b.iter().flatten())
+        .map(|(x, y)| (x - y).abs())
+        .fold(0.0, f64::max)
+}
+
+
+/// Multiply each charge density by its weight, e.g. weights `[1, -1]` turn a sum into a
+/// difference. The count of weights must be the same as charge densities.
+pub fn apply_weights(chgs: Vec<ChargeDensity>, weights: &[f64]) -> Result<Vec<ChargeDensity>> {
+    if chgs.len() != weights.len() {
+        bail!("The count of weights ({}) doesn't match the count of inputs ({}).", weights.len(), chgs.len());
+    }
+    Ok(chgs.into_iter().zip(weights).map(|(c, w)| c * *w).collect())
+}
+
+
+/// Combine all the charge densities into one according to `mode`, they must share the same grid
+/// and lattice.
+pub fn reduce_charge_densities<I>(chgs: I, mode: Reduce) -> Result<ChargeDensity>
+where I: IntoIterator<Item = ChargeDensity> {
+    let mut chgs = chgs.into_iter();
+    let first = chgs.next().context("No charge density to combine.")?;
+    let mut n = 1usize;
+
+    match mode {
+        Reduce::Sum     => chgs.try_fold(first, |acc, x| acc + x),
+        Reduce::MaxAbs  => chgs.try_fold(first, |acc, x| acc.max_abs(x)),
+        Reduce::Mean    => {
+            let sum = chgs.try_fold(first, |acc, x| {
+                n += 1;
+                accumulate(acc, x)
+            })?;
+            Ok(sum * (1.0 / n as f64))
+        },
+    }
+}
+
+
+// Add the grids of `x` to `acc` for `Reduce::Mean`. Unlike `Add`, the structure of `acc` is kept
+// instead of merging the atoms, since the inputs are snapshots of the same system. The
+// augmentation part is dropped, the same as `Add`.
+fn accumulate(mut acc: ChargeDensity, x: ChargeDensity) -> Result<ChargeDensity> {
+    acc.check_compatible(&x)?;
+    for (a, b) in acc.chg.iter_mut().zip(x.chg.iter()) {
+        *a += b;
+    }
+    acc.pos.comment = "Averaged charge density. Produced by rsgrad".to_string();
+    acc.aug.clear();
+    Ok(acc)
+}
+
+
+/// Read the files one by one and combine them into a running result according to `mode`, thus at
+/// most two charge densities are held in memory at a time. `prepare` is applied to each charge
+/// density right after it's read, then it's multiplied by its weight if `weights` is not empty.
+/// The result is the same as `reduce_charge_densities` on all the prepared inputs.
+pub fn stream_reduce<P, F>(paths: &[P], chgtype: ChargeType, mode: Reduce, weights: &[f64], prepare: F) -> Result<ChargeDensity>
+where P: AsRef<Path>,
+      F: FnMut(&Path, ChargeDensity) -> Result<ChargeDensity> {
+    stream_reduce_cached(paths, chgtype, None, mode, weights, prepare)
+}
+
+
+/// Same as `stream_reduce`, the parsed files are cached in `cache_dir` if it's given, see
+/// [`ChargeDensity::from_file_cached`].
+pub fn stream_reduce_cached<P, F>(paths: &[P], chgtype: ChargeType, cache_dir: Option<&Path>, mode: Reduce, weights: &[f64], mut prepare: F) -> Result<ChargeDensity>
+where P: AsRef<Path>,
+      F: FnMut(&Path, ChargeDensity) -> Result<ChargeDensity> {
+    if !weights.is_empty() && weights.len() != paths.len() {
+        bail!("The count of weights ({}) doesn't match the count of inputs ({}).", weights.len(), paths.len());
+    }
+
+    let mut acc: Option<ChargeDensity> = None;
+    for (i, path) in paths.iter().enumerate() {
+        let path = path.as_ref();
+        let chg = read_charge_density(path, chgtype, cache_dir)?;
+        let mut chg = prepare(path, chg)?;
+        if let Some(w) = weights.get(i) {
+            chg = chg * *w;
+        }
+
+        acc = Some(match acc {
+            None => chg,
+            Some(acc) => {
+                acc.check_compatible(&chg)
+                    .with_context(|| format!("{:?} and {:?} cannot be combined.", paths[0].as_ref(), path))?;
+                match mode {
+                    Reduce::Sum     => (acc + chg)?,
+                    Reduce::Mean    => accumulate(acc, chg)?,
+                    Reduce::MaxAbs  => acc.max_abs(chg)?,
+                }
+            },
+        });
+    }
+
+    let acc = acc.context("No charge density to combine.")?;
+    match mode {
+        Reduce::Mean => Ok(acc * (1.0 / paths.len() as f64)),
+        _ => Ok(acc),
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+/// How the charge densities are combined into one.
+pub enum Reduce {
+    /// Sum of all the inputs
+    Sum,
+
+    /// At each grid point, the value with the largest magnitude among the inputs
+    MaxAbs,
+
+    /// Average of all the inputs
+    Mean,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+/// Type of the input files.
+pub enum InputType {
+    /// CHGCAR or CHG
+    Chgcar,
+
+    /// LOCPOT, the data is taken as is without augmentation part
+    Locpot,
+
+    /// ELFCAR, the data is taken as is. ELF is not additive, consider `--reduce mean` or
+    /// `--reduce max-abs` instead of the sum.
+    Elfcar,
+
+    /// Band decomposed charge density, the output title summarizes the combined bands and
+    /// k-points, and no augmentation part is written.
+    Parchg,
+}
+
+
+impl InputType {
+    /// How the data in the files is read.
+    pub fn charge_type(self) -> ChargeType {
+        match self {
+            Self::Chgcar | Self::Parchg => ChargeType::Chgcar,
+            Self::Locpot | Self::Elfcar => ChargeType::Locpot,
+        }
+    }
+
+    /// Unit of the values in the files after reading, empty for the dimensionless ELF.
+    pub fn unit(self) -> &'static str {
+        match self {
+            Self::Chgcar | Self::Parchg => "e/A^3",
+            Self::Locpot                => "eV",
+            Self::Elfcar                => "",
+        }
+    }
+}
+
+
 #[derive(Debug, Args)]
 /// Calculate charge density sum from multiple CHGCAR files.
 ///
 /// All CHGCARs must have the same grid and lattice.
 pub struct Chgsum {
-    /// Input CHGCAR files to sum
+    /// Input CHGCAR files to sum, gzip compressed files (e.g. `CHGCAR.gz`) are read directly.
     #[arg(required = true)]
     input: Vec<PathBuf>,
 
     /// Output file name (default: CHGSUM.vasp)
-    #[arg(short, long, default_value = "CHGSUM.vasp")]
-    output: PathBuf,
+    ///
+    /// If the file name ends with `.npy`, the result is written as a C ordered float64 NumPy
+    /// array of shape (NX, NY, NZ) in e/A^3, with a companion `.json` file holding the lattice
+    /// and composition. If it ends with `.cube`, the result is written in Gaussian cube format,
+    /// the same as `--ase-compat`. Otherwise it is written in VASP format. A trailing `.gz`
+    /// compresses the VASP or cube output, e.g. `-o CHGSUM.cube.gz`.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// How the inputs are combined. `max-abs` gives the envelope of the inputs, e.g. the union
+    /// of several band decomposed charge densities.
+    #[arg(long, value_enum, default_value = "sum")]
+    reduce: Reduce,
+
+    /// Type of the input files.
+    ///
+    /// For `parchg`, the band and k-point indices are parsed from the file names written by VASP
+    /// with LSEPB/LSEPK (e.g. `PARCHG.0010.ALLK`, `PARCHG.0010.0003`, `PARCHG.ALLB.0003`) and
+    /// summarized in the title of output, e.g. "sum of bands 10-15, all k-points".
+    #[arg(long = "type", value_enum, default_value = "chgcar")]
+    input_type: InputType,
+
+    /// Spin channel of spin-polarized (ISPIN = 2) inputs to combine, `up` and `down` are derived
+    /// from the total and magnetization densities. All data sets are combined if not specified.
+    #[arg(long, value_enum)]
+    spin: Option<SpinChannel>,
+
+    /// Coefficients of the inputs, separated by `,`, e.g. `rsgrad chgsum a b --weights 1,-1`
+    /// gives `a - b`. Each input is multiplied by its weight before being combined, the count of
+    /// weights must be the same as inputs.
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true, conflicts_with_all = ["info", "endpoints"])]
+    weights: Vec<f64>,
+
+    /// Divide the result by the count of inputs, e.g. to average the charge densities of the
+    /// snapshots of an MD trajectory. The same as `--reduce mean`.
+    #[arg(long, conflicts_with_all = ["info", "endpoints", "reduce"])]
+    average: bool,
+
+    /// Interpolate all inputs onto a common grid before combining them, thus inputs with
+    /// different FFT grids (NGXF/NGYF/NGZF) can be combined. The lattices must still be the same.
+    ///
+    /// The grid of the first input is used unless `--grid` is specified. Trilinear interpolation
+    /// with periodic boundary condition is used.
+    #[arg(long, conflicts_with = "info")]
+    resample: bool,
+
+    /// The common grid of `--resample`, e.g. `--grid 96,96,120`.
+    #[arg(long, value_delimiter = ',', num_args = 3, requires = "resample")]
+    grid: Option<Vec<usize>>,
+
+    /// Template of output file name, used when `--output` is not specified.
+    ///
+    /// Available placeholders: `{count}` the number of inputs, `{first}` the file name of the
+    /// first input, `{basename}` the file name of the first input without extension and `{dir}`
+    /// the common parent directory of all inputs. E.g. `{dir}/sum_of_{count}_files.vasp`.
+    #[arg(long)]
+    output_template: Option<String>,
+
+    /// Print the grid dimensions, lattice vectors, cell volume and real space grid spacing of
+    /// each input instead of summing them. Works with a single file too.
+    #[arg(long)]
+    info: bool,
+
+    /// Write `last - first` of the inputs instead of their sum, the intermediate inputs are
+    /// ignored. This is useful for an ordered series of charge densities, e.g. from relaxation
+    /// or AIMD, to see the net change.
+    #[arg(long, conflicts_with = "info")]
+    endpoints: bool,
+
+    /// Report the positions of maximum and minimum of the result, and the nearest atom to each
+    /// of them.
+    #[arg(long)]
+    report_extrema: bool,
+
+    /// Write the result in Gaussian cube format that ASE reads without manual fixups (default
+    /// output: CHGSUM.cube).
+    ///
+    /// The origin is at (0, 0, 0), lengths are in Bohr, atoms keep the order of the first input
+    /// and the density is in e/Bohr^3. Only the total density is written for spin-polarized
+    /// inputs.
+    #[arg(long, conflicts_with = "info")]
+    ase_compat: bool,
+
+    /// Print the integrated charge of each atom of the result by nearest-atom partition.
+    ///
+    /// Each grid point is assigned to its nearest atom (periodic images included), i.e. the cell
+    /// is split into Voronoi cells. This is a cheap approximation, NOT a Bader analysis.
+    #[arg(long)]
+    voronoi_charges: bool,
+
+    /// Subtract the cell average from the result before writing, thus it integrates to zero.
+    ///
+    /// This shows the spatial variation only and destroys the total charge in the written file.
+    /// The integrated charge, `--report-extrema` and `--voronoi-charges` are still reported from
+    /// the result before the mean is removed.
+    #[arg(long, conflicts_with = "info")]
+    remove_mean: bool,
+
+    /// Write the true density `rho` (e/A^3) instead of VASP's `rho * V_cell`.
+    ///
+    /// The output is NOT a standard VASP CHGCAR anymore: its title is labelled and the
+    /// augmentation part is dropped. This is for the external tools expecting physical density.
+    #[arg(long, conflicts_with_all = ["info", "ase_compat"])]
+    true_density: bool,
+
+    /// Read and combine the inputs one by one instead of loading all of them in parallel.
+    ///
+    /// Only the running result and the input being read are held in memory, this is slower but
+    /// allows combining many large files. It makes no difference with `--endpoints`. The inputs
+    /// are not cached either, see `--no-cache`.
+    #[arg(long, conflicts_with = "info")]
+    low_memory: bool,
+
+    /// Don't show the progress bar. It's hidden anyway if stderr is not a terminal.
+    #[arg(long, short)]
+    quiet: bool,
+
+    /// Don't warn when the inputs look like coming from unrelated calculations.
+    ///
+    /// By default the titles and compositions of all inputs are compared with the first one, a
+    /// warning is raised if the title is much different or the composition is different. This is
+    /// a soft guard against summing files from a wrongly globbed directory, the sum is still
+    /// done.
+    #[arg(long)]
+    no_heuristic_checks: bool,
+
+    /// Largest difference allowed between the lattice vectors of the inputs, in Å.
+    ///
+    /// The lattices and grids of all inputs are checked by reading their headers before the grid
+    /// data is read. The inputs with slightly different lattices (within the tolerance) take the
+    /// lattice of the first input.
+    #[arg(long, default_value_t = 1E-6)]
+    tolerance: f64,
+
+    /// Don't cache the parsed inputs.
+    ///
+    /// By default the parsed inputs are cached in binary form, one entry per input file, which is
+    /// overwritten once the modification time or size of the file changes. A later run over the
+    /// same unchanged files reads the cache instead of parsing the text again.
+    ///
+    /// Each entry takes about as much disk space as the grid in memory, i.e. 8 bytes per grid
+    /// point and data set, roughly half the size of the uncompressed text input. Nothing is
+    /// cached with `--low-memory`.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Directory of the cache of parsed inputs, defaults to `rsgrad` in the user's cache
+    /// directory, e.g. `~/.cache/rsgrad` on Linux.
+    #[arg(long, conflicts_with_all = ["no_cache", "low_memory"])]
+    cache_dir: Option<PathBuf>,
 }
 
-impl OptProcess for Chgsum {
-    fn process(&self) -> Result<()> {
-        if self.input.len() < 2 {
-            return Err(anyhow!("Please provide at least two CHGCAR files."));
+
+// Band and k-point indices of a PARCHG file name written by VASP with LSEPB/LSEPK, `None` stands
+// for all of them, e.g. `PARCHG.0010.ALLK` gives `(Some(10), None)`. Returns `None` if the name
+// doesn't follow this pattern.
+fn parse_parchg_name(path: &Path) -> Option<(Option<usize>, Option<usize>)> {
+    let name = path.file_name()?.to_str()?;
+    let mut parts = name.split('.');
+    if parts.next()? != "PARCHG" {
+        return None;
+    }
+
+    let parse_index = |s: &str, all: &str| -> Option<Option<usize>> {
+        if s == all {
+            Some(None)
+        } else {
+            s.parse::<usize>().ok().map(Some)
+        }
+    };
+    let band   = parse_index(parts.next()?, "ALLB")?;
+    let kpoint = parse_index(parts.next()?, "ALLK")?;
+
+    match parts.next() {
+        None => Some((band, kpoint)),
+        Some(_) => None,
+    }
+}
+
+
+// Compact form of the indices, e.g. `3,5-7,10`.
+fn format_ranges(indices: &BTreeSet<usize>) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for &i in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == i => *end = i,
+            _ => ranges.push((i, i)),
+        }
+    }
+
+    ranges.iter()
+        .map(|(a, b)| if a == b { a.to_string() } else { format!("{}-{}", a, b) })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+
+// Title summarizing the bands and k-points of the combined PARCHGs, e.g. "sum of bands 10-15,
+// all k-points". `None` if any of the file names can't be parsed.
+fn parchg_title(inputs: &[&PathBuf], reduce: Reduce) -> Option<String> {
+    let indices = inputs.iter()
+        .map(|p| parse_parchg_name(p))
+        .collect::<Option<Vec<_>>>()?;
+
+    let describe = |xs: Vec<Option<usize>>, singular: &str, plural: &str| -> String {
+        match xs.into_iter().collect::<Option<BTreeSet<usize>>>() {
+            None => format!("all {}", plural),
+            Some(xs) if xs.len() == 1 => format!("{} {}", singular, format_ranges(&xs)),
+            Some(xs) => format!("{} {}", plural, format_ranges(&xs)),
+        }
+    };
+    let bands   = describe(indices.iter().map(|x| x.0).collect(), "band", "bands");
+    let kpoints = describe(indices.iter().map(|x| x.1).collect(), "k-point", "k-points");
+
+    let verb = match reduce {
+        Reduce::Sum     => "sum",
+        Reduce::MaxAbs  => "max-abs envelope",
+        Reduce::Mean    => "mean",
+    };
+    Some(format!("{} of {}, {}", verb, bands, kpoints))
+}
+
+
+// Title similarity below which the inputs are considered from unrelated calculations.
+const TITLE_SIMILARITY_THRESHOLD: f64 = 0.5;
+
+
+// Similarity of two titles in [0, 1] by edit distance, surrounding whitespaces are ignored.
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (a.trim(), b.trim());
+    let len = a.chars().count().max(b.chars().count());
+    if len == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / len as f64
+}
+
+
+// Number of atoms of each element, the order of elements in POSCAR doesn't matter.
+fn composition(pos: &Poscar) -> BTreeMap<&str, i32> {
+    let mut ret = BTreeMap::new();
+    for (t, n) in pos.ion_types.iter().zip(pos.ions_per_type.iter()) {
+        *ret.entry(t.as_str()).or_insert(0) += n;
+    }
+    ret
+}
+
+
+// Warnings for the inputs looking like coming from unrelated calculations, compared with the
+// first one by titles and compositions.
+fn heuristic_warnings(inputs: &[&PathBuf], poses: &[&Poscar]) -> Vec<String> {
+    let (first_input, first) = match (inputs.first(), poses.first()) {
+        (Some(i), Some(p)) => (i, p),
+        _ => return vec![],
+    };
+
+    let mut ret = vec![];
+    for (input, pos) in inputs.iter().zip(poses.iter()).skip(1) {
+        if title_similarity(&first.comment, &pos.comment) < TITLE_SIMILARITY_THRESHOLD {
+            ret.push(format!("Title of {:?} ({:?}) is much different from {:?} ({:?}), are they from the same calculation?",
+                             input, pos.comment.trim(), first_input, first.comment.trim()));
+        }
+        if composition(first) != composition(pos) {
+            ret.push(format!("Composition of {:?} ({:?}) is different from {:?} ({:?}), are they from the same calculation?",
+                             input, composition(pos), first_input, composition(first)));
+        }
+    }
+    ret
+}
+
+
+fn common_dir(inputs: &[PathBuf]) -> PathBuf {
+    let parent_components = |p: &PathBuf| p.parent()
+        .map(|x| x.components().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let mut common = inputs.first().map(parent_components).unwrap_or_default();
+    for p in inputs.iter().skip(1) {
+        let components = parent_components(p);
+        let n = common.iter().zip(components.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(n);
+    }
+
+    let dir = common.iter().collect::<PathBuf>();
+    if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir
+    }
+}
+
+
+fn render_output_template(template: &str, inputs: &[PathBuf]) -> Result<PathBuf> {
+    let first = inputs.first().context("No input file provided.")?;
+    let file_name_of = |x: Option<&std::ffi::OsStr>| x
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut ret = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        ret.push_str(&rest[.. start]);
+        if rest[.. start].contains('}') {
+            bail!("Unmatched `}}` in output template {:?}.", template);
+        }
+
+        let end = rest[start ..].find('}')
+            .with_context(|| format!("Unclosed placeholder in output template {:?}.", template))? + start;
+        let value = match &rest[start + 1 .. end] {
+            "count"     => inputs.len().to_string(),
+            "first"     => file_name_of(first.file_name()),
+            "basename"  => file_name_of(first.file_stem()),
+            "dir"       => common_dir(inputs).to_string_lossy().to_string(),
+            key => bail!("Unknown placeholder `{{{}}}` in output template {:?}, available placeholders are `{{count}}`, `{{first}}`, `{{basename}}` and `{{dir}}`.",
+                         key, template),
+        };
+        ret.push_str(&value);
+        rest = &rest[end + 1 ..];
+    }
+
+    if rest.contains('}') {
+        bail!("Unmatched `}}` in output template {:?}.", template);
+    }
+    ret.push_str(rest);
+
+    if ret.trim().is_empty() {
+        bail!("Output template {:?} produces an empty file name.", template);
+    }
+
+    Ok(PathBuf::from(ret))
+}
+
+
+impl Chgsum {
+    fn cache_dir(&self) -> Option<PathBuf> {
+        if self.no_cache || self.low_memory {
+            None
+        } else {
+            Some(self.cache_dir.clone().unwrap_or_else(default_cache_dir))
+        }
+    }
+
+    fn output_path(&self) -> Result<PathBuf> {
+        match (self.output.as_ref(), self.output_template.as_ref()) {
+            (Some(output), _)       => Ok(output.clone()),
+            (None, Some(template))  => render_output_template(template, &self.input),
+            (None, None) if self.ase_compat => Ok(PathBuf::from("CHGSUM.cube")),
+            (None, None)            => Ok(PathBuf::from("CHGSUM.vasp")),
         }
+    }
+
+    // `--info` only needs the headers, the grid data is never read.
+    fn print_info(path: &Path, pos: &Poscar, ngrid: [usize; 3]) {
+        let cell = pos.clone().normalize().cell;
+        let spacing = grid_spacing(pos, ngrid);
+
+        println!("==================== {:?} ====================", path);
+        println!("  Grid dimensions : {:5} {:5} {:5}", ngrid[0], ngrid[1], ngrid[2]);
+        println!("  Lattice vectors (A):");
+        for row in cell.iter() {
+            println!("    {:15.9} {:15.9} {:15.9}", row[0], row[1], row[2]);
+        }
+        println!("  Cell volume     : {:.6} A^3", pos.get_volume());
+        println!("  Grid spacing (A): {:10.6} {:10.6} {:10.6}", spacing[0], spacing[1], spacing[2]);
+        println!();
+    }
 
-        // Load all CHGCARs in parallel
-        let chgcars: Result<Vec<ChargeDensity>> = self.input
-            .par_iter()
-            .map(|path| {
-                info!("Reading charge density from {:?}", path);
-                ChargeDensity::from_file(path, ChargeType::Chgcar)
-                    .with_context(|| format!("Failed to read charge density from {:?}", path))
+    // The reports on atoms take the atoms from `pos`, i.e. the structure of the first input, see
+    // `run`.
+    fn report_extrema(chg: &ChargeDensity, pos: &Poscar) {
+        let symbols = pos.get_atom_symbols();
+
+        println!("==================== Extrema of the result ====================");
+        for (label, point) in [("Maximum", chg.max_point()), ("Minimum", chg.min_point())] {
+            let Some((idx, value)) = point else { continue };
+            let frac = chg.grid_to_frac(idx);
+            println!("  {}: {:15.8E} at grid ({:4}, {:4}, {:4}), fractional ({:8.5}, {:8.5}, {:8.5})",
+                     label, value, idx[0], idx[1], idx[2], frac[0], frac[1], frac[2]);
+            if let Some((iatom, dist)) = chg.nearest_atom_with(pos, frac) {
+                println!("      nearest atom: {}-{:03} (#{}), distance = {:.6} A",
+                         symbols[iatom], iatom + 1, iatom + 1, dist);
+            }
+        }
+        println!();
+    }
+
+    fn print_voronoi_charges(chg: &ChargeDensity, pos: &Poscar) {
+        let symbols = pos.get_atom_symbols();
+        let charges = chg.voronoi_charges_with(pos);
+
+        println!("============ Voronoi charges of the result (NOT Bader) ============");
+        println!("  {:>6} {:>8} {:>15}", "#", "Atom", "Charge (e)");
+        for (i, (symbol, charge)) in symbols.iter().zip(charges.iter()).enumerate() {
+            println!("  {:6} {:>8} {:15.6}", i + 1, format!("{}-{:03}", symbol, i + 1), charge);
+        }
+        println!("  {:>6} {:>8} {:15.6}", "", "Total", charges.iter().sum::<f64>());
+        println!();
+    }
+
+    fn info_json(path: &Path, pos: &Poscar, ngrid: [usize; 3]) -> serde_json::Value {
+        json!({
+            "path":         path.to_string_lossy(),
+            "grid":         ngrid,
+            "lattice":      pos.clone().normalize().cell,
+            "volume":       pos.get_volume(),
+            "grid_spacing": grid_spacing(pos, ngrid),
+        })
+    }
+
+    fn extrema_json(chg: &ChargeDensity, pos: &Poscar) -> serde_json::Value {
+        let symbols = pos.get_atom_symbols();
+        let point_json = |point: Option<([usize; 3], f64)>| point.map(|(idx, value)| {
+            let frac = chg.grid_to_frac(idx);
+            let nearest = chg.nearest_atom_with(pos, frac).map(|(iatom, dist)| json!({
+                "index":    iatom + 1,
+                "symbol":   symbols[iatom],
+                "distance": dist,
+            }));
+            json!({
+                "value":        value,
+                "grid":         idx,
+                "frac":         frac,
+                "nearest_atom": nearest,
             })
-            .collect();
+        });
 
-        let chgcars = chgcars?;
+        json!({
+            "maximum": point_json(chg.max_point()),
+            "minimum": point_json(chg.min_point()),
+        })
+    }
+
+    fn voronoi_charges_json(chg: &ChargeDensity, pos: &Poscar) -> serde_json::Value {
+        pos.get_atom_symbols().iter()
+            .zip(chg.voronoi_charges_with(pos))
+            .enumerate()
+            .map(|(i, (symbol, charge))| json!({
+                "index":    i + 1,
+                "symbol":   symbol,
+                "charge":   charge,
+            }))
+            .collect()
+    }
 
-        // Reduce all ChargeDensity files into one
-        let sum = chgcars
-            .into_iter()
-            .reduce(|a, b| (a + b).expect("ChargeDensity addition failed"))
-            .ok_or_else(|| anyhow!("Failed to sum CHGCARs"))?;
+    // Steps applied to each input before combining: heuristic checks against the first input,
+    // spin channel selection and resampling. `first` holds the path, structure and target grid
+    // of the first input, it's filled by the first call.
+    fn prepare_input(&self, path: &Path, chg: ChargeDensity, first: &mut Option<(PathBuf, Poscar, [usize; 3])>) -> Result<ChargeDensity> {
+        let is_first = first.is_none();
+        let chg = match self.spin {
+            Some(spin) => chg.spin_channel(spin)
+                .with_context(|| format!("Cannot take the spin channel of {:?}", path))?,
+            None => chg,
+        };
+
+        if is_first {
+            let target = match self.grid.as_deref() {
+                Some(&[nx, ny, nz]) => [nx, ny, nz],
+                Some(grid) => bail!("Three grid dimensions are expected, got {:?}", grid),
+                None => chg.ngrid,
+            };
+            *first = Some((path.to_path_buf(), chg.pos.clone(), target));
+        }
+        let (first_path, first_pos, target) = first.as_ref().context("No charge density loaded.")?;
+
+        if !is_first && !self.no_heuristic_checks {
+            for w in heuristic_warnings(&[first_path, &path.to_path_buf()], &[first_pos, &chg.pos]) {
+                warn!("{} Pass `--no-heuristic-checks` to suppress this warning.", w);
+            }
+        }
+
+        // The lattices are checked against the tolerance in advance by `check_headers`
+        let mut chg = chg;
+        if !is_first {
+            let first_cell = first_pos.clone().normalize().cell;
+            let pos = chg.pos.clone().normalize();
+            if pos.cell != first_cell && lattice_deviation(&pos.cell, &first_cell) <= self.tolerance {
+                chg.pos = pos;
+                chg.pos.cell = first_cell;
+            }
+        }
 
-        info!("Writing summed charge density to {:?}", self.output);
-        sum.to_file(&self.output)?;
+        let chg = if self.resample && chg.ngrid != *target {
+            info!("Interpolating {:?} from grid {:?} to {:?}", path, chg.ngrid, target);
+            chg.interpolate_to(*target)?
+        } else {
+            chg
+        };
+
+        if chg.chgtype == ChargeType::Chgcar {
+            info!("Integrated charge of {:?}: {:.6} e", path, chg.integrate());
+        }
+
+        Ok(chg)
+    }
+}
+
+
+impl Chgsum {
+    // Body of `process` and `process_json`. With `json`, the reports are collected into one JSON
+    // object printed at the end instead of the text tables.
+    fn run(&self, json: bool) -> Result<()> {
+        if !self.info && self.input.len() < 2 {
+            return Err(anyhow!("Please provide at least two CHGCAR files."));
+        }
+        if !self.weights.is_empty() && self.weights.len() != self.input.len() {
+            bail!("The count of weights ({}) doesn't match the count of inputs ({}).", self.weights.len(), self.input.len());
+        }
+        if self.endpoints && self.reduce != Reduce::Sum {
+            bail!("`--endpoints` computes `last - first`, it cannot be used with `--reduce {:?}`.", self.reduce);
+        }
+
+        let output = self.output_path()?;
+
+        let format = match GridFormat::from_path(&output)? {
+            (GridFormat::Npy, _) if self.ase_compat => bail!("`--ase-compat` writes cube file, it cannot be used with `.npy` output."),
+            _ if self.ase_compat => GridFormat::Cube,
+            (format, _) => format,
+        };
+        if self.true_density && format == GridFormat::Cube {
+            bail!("Cube file is always in e/Bohr^3, it cannot be used with `--true-density`.");
+        }
+
+        let inputs: Vec<&PathBuf> = if self.endpoints {
+            if self.input.len() > 2 {
+                warn!("Only the first and last inputs are used with `--endpoints`, {} intermediate file(s) ignored.",
+                      self.input.len() - 2);
+            }
+            vec![&self.input[0], &self.input[self.input.len() - 1]]
+        } else {
+            self.input.iter().collect()
+        };
+
+        let chgtype = self.input_type.charge_type();
+        let cache_dir = self.cache_dir();
+        let cache_dir = cache_dir.as_deref();
+
+        if self.info {
+            let headers = inputs.iter()
+                .map(|path| ChargeDensity::read_header(path))
+                .collect::<Result<Vec<_>>>()?;
+            if json {
+                let infos = inputs.iter().zip(headers.iter())
+                    .map(|(path, (pos, ngrid))| Self::info_json(path, pos, *ngrid))
+                    .collect::<Vec<_>>();
+                println!("{}", serde_json::to_string_pretty(&infos)?);
+            } else {
+                for (path, (pos, ngrid)) in inputs.iter().zip(headers.iter()) {
+                    Self::print_info(path, pos, *ngrid);
+                }
+            }
+            return Ok(());
+        }
+
+        if self.tolerance.is_nan() || self.tolerance < 0.0 {
+            bail!("The tolerance should be a non-negative number, got {}.", self.tolerance);
+        }
+        info!("Checking the headers of the inputs ...");
+        check_headers(&inputs, self.tolerance, !self.resample)?;
+
+        if self.input_type == InputType::Elfcar && !self.endpoints && !self.average && self.reduce == Reduce::Sum {
+            warn!("ELF is not additive, the sum of ELFCARs is hardly meaningful. Consider `--reduce mean` or `--reduce max-abs`.");
+        }
+
+        let reduce = if self.average { Reduce::Mean } else { self.reduce };
+        let mut first = None;
+        let mut result = if self.low_memory && !self.endpoints {
+            info!("Combining the inputs one by one to save memory ...");
+            let progress = ProgressBar::new(inputs.len(), "Combining", self.quiet);
+            stream_reduce_cached(&inputs, chgtype, cache_dir, reduce, &self.weights, |path, chg| {
+                progress.inc();
+                self.prepare_input(path, chg, &mut first)
+            })?
+        } else {
+            // Load all CHGCARs in parallel
+            let progress = ProgressBar::new(inputs.len(), "Reading", self.quiet);
+            let chgcars = load_charge_densities_cached(&inputs, chgtype, cache_dir, &progress)?;
+            drop(progress);
+
+            let mut chgcars = chgcars.into_iter()
+                .zip(inputs.iter())
+                .map(|(chg, path)| self.prepare_input(path, chg, &mut first))
+                .collect::<Result<Vec<_>>>()?;
+
+            check_compatibility(&inputs, &chgcars)?;
+
+            if self.endpoints {
+                let last  = chgcars.pop().context("No charge density loaded.")?;
+                let first = chgcars.pop().context("No charge density loaded.")?;
+
+                info!("Calculating `{:?} - {:?}`", inputs[1], inputs[0]);
+                (last - first)?
+            } else {
+                if !self.weights.is_empty() {
+                    info!("Applying weights {:?} to the inputs", self.weights);
+                    chgcars = apply_weights(chgcars, &self.weights)?;
+                }
+                let progress = ProgressBar::new(chgcars.len(), "Combining", self.quiet);
+                reduce_charge_densities(chgcars.into_iter().inspect(|_| progress.inc()), reduce)?
+            }
+        };
+
+        let is_density = chgtype == ChargeType::Chgcar;
+
+        if self.input_type == InputType::Parchg {
+            result.aug.clear();
+            if !self.endpoints {
+                match parchg_title(&inputs, reduce) {
+                    Some(title) => result.pos.comment = format!("PARCHG {}. Produced by rsgrad", title),
+                    None => warn!("Band and k-point indices not found in the input file names, the title of output is not summarized."),
+                }
+            }
+        }
+
+        let mut summary = json!({
+            "inputs":   inputs.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>(),
+            "output":   output.to_string_lossy(),
+            "reduce":   if self.endpoints {
+                "endpoints".to_string()
+            } else {
+                reduce.to_possible_value().map_or_else(String::new, |v| v.get_name().to_string())
+            },
+            "grid":     result.ngrid,
+        });
+
+        if is_density {
+            let charge = result.integrate();
+            info!("Integrated charge of the result: {:.6} e", charge);
+            summary["integrated_charge"] = json!(charge);
+        }
+
+        // `Add` appends the atoms of every input to the result, thus the atoms are taken from the
+        // first input instead. Otherwise summing the same structure N times would list every atom
+        // N times, with all the charge going to the first copy.
+        let (_, first_pos, _) = first.as_ref().context("No charge density loaded.")?;
+        let first_pos = first_pos.clone().normalize();
+
+        if self.report_extrema {
+            if json {
+                summary["extrema"] = Self::extrema_json(&result, &first_pos);
+            } else {
+                Self::report_extrema(&result, &first_pos);
+            }
+        }
+
+        if self.voronoi_charges {
+            if json {
+                summary["voronoi_charges"] = Self::voronoi_charges_json(&result, &first_pos);
+            } else {
+                Self::print_voronoi_charges(&result, &first_pos);
+            }
+        }
+
+        if self.remove_mean {
+            let mean = result.remove_mean();
+            warn!("The mean density is removed from the result, it no longer integrates to the total charge.");
+            if json {
+                summary["removed_mean"] = json!(mean);
+            } else {
+                let line = format!("Removed mean value: {:.8E} {}", mean, self.input_type.unit());
+                println!("{}", line.trim_end());
+            }
+        }
+
+        if self.true_density {
+            warn!("Writing true density, {:?} is not a standard VASP CHGCAR.", output);
+            result = result.into_true_density();
+        }
+
+        match format {
+            GridFormat::Cube => {
+                info!("Writing result charge density in cube format to {:?}", output);
+                result.to_cube(&output)?;
+            },
+            GridFormat::Npy => {
+                info!("Writing result charge density in NumPy format to {:?} with metadata in {:?}",
+                      output, output.with_extension("json"));
+                result.to_npy(&output)?;
+            },
+            GridFormat::Vasp => {
+                info!("Writing result charge density to {:?}", output);
+                result.to_file(&output)?;
+            },
+        }
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        }
 
         Ok(())
     }
 }
+
+
+impl OptProcess for Chgsum {
+    fn process(&self) -> Result<()> {
+        self.run(false)
+    }
+
+    fn process_json(&self) -> Result<()> {
+        self.run(true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::vasp_parsers::chg::cubic_density;
+
+    #[test]
+    fn test_render_output_template() {
+        let inputs = vec![
+            PathBuf::from("run/snap1/CHGCAR.vasp"),
+            PathBuf::from("run/snap2/CHGCAR.vasp"),
+            PathBuf::from("run/snap3/CHGCAR.vasp"),
+        ];
+
+        assert_eq!(render_output_template("sum_of_{count}_files.vasp", &inputs).unwrap(),
+                   PathBuf::from("sum_of_3_files.vasp"));
+        assert_eq!(render_output_template("{first}.sum", &inputs).unwrap(),
+                   PathBuf::from("CHGCAR.vasp.sum"));
+        assert_eq!(render_output_template("{basename}_sum.vasp", &inputs).unwrap(),
+                   PathBuf::from("CHGCAR_sum.vasp"));
+        assert_eq!(render_output_template("{dir}/sum.vasp", &inputs).unwrap(),
+                   PathBuf::from("run/sum.vasp"));
+        assert_eq!(render_output_template("{dir}/sum.vasp", &inputs[..1]).unwrap(),
+                   PathBuf::from("run/snap1/sum.vasp"));
+        assert_eq!(render_output_template("{dir}/sum.vasp", &[PathBuf::from("CHGCAR")]).unwrap(),
+                   PathBuf::from("./sum.vasp"));
+
+        assert!(render_output_template("{unknown}.vasp", &inputs).is_err());
+        assert!(render_output_template("{count.vasp", &inputs).is_err());
+        assert!(render_output_template("count}.vasp", &inputs).is_err());
+        assert!(render_output_template("", &inputs).is_err());
+    }
+
+    #[test]
+    fn test_parchg_title() {
+        let title = |names: &[&str], reduce: Reduce| {
+            let paths = names.iter().map(PathBuf::from).collect::<Vec<_>>();
+            parchg_title(&paths.iter().collect::<Vec<_>>(), reduce)
+        };
+
+        let names = (10 ..= 15).map(|i| format!("run/PARCHG.{:04}.ALLK", i)).collect::<Vec<_>>();
+        let names = names.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(title(&names, Reduce::Sum).unwrap(), "sum of bands 10-15, all k-points");
+
+        assert_eq!(title(&["PARCHG.0003.0001", "PARCHG.0005.0002", "PARCHG.0006.0002", "PARCHG.0007.0001"], Reduce::Mean).unwrap(),
+                   "mean of bands 3,5-7, k-points 1-2");
+        assert_eq!(title(&["PARCHG.ALLB.0004", "PARCHG.ALLB.0004"], Reduce::Sum).unwrap(),
+                   "sum of all bands, k-point 4");
+
+        assert!(title(&["PARCHG.0010.ALLK", "PARCHG"], Reduce::Sum).is_none());
+        assert!(title(&["PARCHG.0010.ALLK", "CHGCAR"], Reduce::Sum).is_none());
+        assert!(title(&["PARCHG.0010.ALLK", "PARCHG.0011.ALLK.bak"], Reduce::Sum).is_none());
+        assert!(title(&["PARCHG.0010.ALLK", "PARCHG.abc.ALLK"], Reduce::Sum).is_none());
+    }
+
+    #[test]
+    fn test_input_type() {
+        assert_eq!(InputType::Chgcar.charge_type(), ChargeType::Chgcar);
+        assert_eq!(InputType::Parchg.charge_type(), ChargeType::Chgcar);
+        assert_eq!(InputType::Locpot.charge_type(), ChargeType::Locpot);
+        assert_eq!(InputType::Elfcar.charge_type(), ChargeType::Locpot);
+        assert_eq!(InputType::Parchg.unit(), "e/A^3");
+        assert_eq!(InputType::Locpot.unit(), "eV");
+        assert_eq!(InputType::Elfcar.unit(), "");
+
+        assert_eq!(InputType::from_str("elfcar", true).unwrap(), InputType::Elfcar);
+        assert_eq!(InputType::from_str("PARCHG", true).unwrap(), InputType::Parchg);
+        assert!(InputType::from_str("wavecar", true).is_err());
+    }
+
+    #[test]
+    fn test_apply_weights() {
+        let a = ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i * 12 + j * 4 + k) as f64);
+        let b = ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| ((i + j + k) % 3) as f64);
+
+        let weighted = apply_weights(vec![cubic_density(3.0, a.clone()), cubic_density(3.0, b.clone())], &[1.0, -1.0]).unwrap();
+        let diff = sum_charge_densities(weighted).unwrap();
+        assert_eq!(diff.chg[0], &a - &b);
+
+        let weighted = apply_weights(vec![cubic_density(3.0, a.clone()), cubic_density(3.0, b.clone())], &[2.0, -1.0]).unwrap();
+        let combined = sum_charge_densities(weighted).unwrap();
+        assert_eq!(combined.chg[0], &a * 2.0 - &b);
+
+        assert!(apply_weights(vec![cubic_density(3.0, a.clone()), cubic_density(3.0, b)], &[1.0]).is_err());
+        assert!(apply_weights(vec![cubic_density(3.0, a)], &[1.0, 2.0]).is_err());
+    }
+
+    #[test]
+    fn test_average() {
+        let a = ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| 0.1 * (i * 12 + j * 4 + k) as f64 + 0.3);
+        let inputs = vec![cubic_density(3.0, a.clone()), cubic_density(3.0, a.clone()), cubic_density(3.0, a.clone())];
+
+        let average = reduce_charge_densities(inputs, Reduce::Mean).unwrap();
+        assert!(average.chg[0].iter().zip(a.iter()).all(|(x, y)| (x - y).abs() < 1E-12));
+        assert_eq!(average.pos.ions_per_type, vec![1]);
+        assert_eq!(average.pos.pos_frac.len(), 1);
+
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("CHGAVG.vasp");
+        average.to_file(&path).unwrap();
+        let read = ChargeDensity::from_file(&path, ChargeType::Chgcar).unwrap();
+        assert_eq!(read.ngrid, [2, 3, 4]);
+        assert!(read.chg[0].iter().zip(a.iter()).all(|(x, y)| ((x - y) / y).abs() < 1E-8));
+    }
+
+    #[test]
+    fn test_stream_reduce() {
+        let grids = [
+            ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| 0.1 * (i * 12 + j * 4 + k) as f64 + 0.3),
+            ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| ((i + j + k) % 3) as f64 - 1.2),
+            ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i * j * k) as f64 * 0.7),
+        ];
+
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let paths = (0 .. grids.len())
+            .map(|i| dir.path().join(format!("CHGCAR_{}", i)))
+            .collect::<Vec<_>>();
+        for (path, grid) in paths.iter().zip(grids.iter()) {
+            cubic_density(3.0, grid.clone()).to_file(path).unwrap();
+        }
+
+        for mode in [Reduce::Sum, Reduce::MaxAbs, Reduce::Mean] {
+            for weights in [vec![], vec![1.0, -0.5, 2.0]] {
+                let mut loaded = load_charge_densities(&paths, ChargeType::Chgcar).unwrap();
+                if !weights.is_empty() {
+                    loaded = apply_weights(loaded, &weights).unwrap();
+                }
+                let expected = reduce_charge_densities(loaded, mode).unwrap();
+
+                let mut count = 0;
+                let streamed = stream_reduce(&paths, ChargeType::Chgcar, mode, &weights, |_, chg| {
+                    count += 1;
+                    Ok(chg)
+                }).unwrap();
+
+                assert_eq!(count, 3);
+                let cached = stream_reduce_cached(&paths, ChargeType::Chgcar, Some(&dir.path().join("cache")), mode, &weights, |_, chg| Ok(chg)).unwrap();
+                assert_eq!(cached.chg, streamed.chg);
+                assert_eq!(streamed.ngrid, expected.ngrid);
+                assert_eq!(streamed.pos.ion_types.len(), expected.pos.ion_types.len());
+                assert_eq!(streamed.pos.pos_frac.len(), expected.pos.pos_frac.len());
+                for (x, y) in streamed.chg[0].iter().zip(expected.chg[0].iter()) {
+                    assert!((x - y).abs() < 1E-12, "{:?}: {} != {}", mode, x, y);
+                }
+            }
+        }
+
+        assert!(stream_reduce(&paths, ChargeType::Chgcar, Reduce::Sum, &[1.0], |_, chg| Ok(chg)).is_err());
+        cubic_density(3.0, ndarray::Array3::zeros((2, 3, 5))).to_file(&paths[2]).unwrap();
+        let msg = format!("{:#}", stream_reduce(&paths, ChargeType::Chgcar, Reduce::Sum, &[], |_, chg| Ok(chg)).unwrap_err());
+        assert!(msg.contains("cannot be combined"), "{}", msg);
+    }
+
+    #[test]
+    fn test_check_compatibility() {
+        let paths = [PathBuf::from("a/CHGCAR"), PathBuf::from("b/CHGCAR"), PathBuf::from("c/CHGCAR")];
+        let grid = |shape: (usize, usize, usize)| cubic_density(3.0, ndarray::Array3::zeros(shape));
+
+        assert!(check_compatibility(&paths, &[grid((2, 3, 4)), grid((2, 3, 4)), grid((2, 3, 4))]).is_ok());
+
+        let err = check_compatibility(&paths, &[grid((2, 3, 4)), grid((2, 3, 4)), grid((2, 3, 5))]).unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("\"a/CHGCAR\" and \"c/CHGCAR\""), "{}", msg);
+        assert!(msg.contains("[2, 3, 4] != [2, 3, 5]"), "{}", msg);
+
+        let mut scaled = grid((2, 3, 4));
+        scaled.pos.cell[2][2] = 4.0;
+        let msg = format!("{:#}", check_compatibility(&paths[.. 2], &[grid((2, 3, 4)), scaled]).unwrap_err());
+        assert!(msg.contains("Different lattices"), "{}", msg);
+
+        // The same error is propagated by the reduction instead of panicking
+        assert!(sum_charge_densities(vec![grid((2, 3, 4)), grid((2, 3, 5))]).is_err());
+    }
+
+    #[test]
+    fn test_atom_reports_of_sum() {
+        let grid = ndarray::Array3::from_shape_fn((4, 4, 4), |(i, j, k)| (1 + i + j * 2 + k * 3) as f64 * 0.01);
+        let mut single = cubic_density(3.0, grid);
+        single.pos.ion_types = vec!["H".to_string(), "O".to_string()];
+        single.pos.ions_per_type = vec![1, 1];
+        single.pos.pos_frac = vec![[0.0; 3], [0.6; 3]];
+        single.pos.pos_cart = vec![[0.0; 3], [1.8; 3]];
+        let expected = single.voronoi_charges();
+
+        // `Add` appends the atoms of both inputs, the reports use the structure of the first one
+        let sum = sum_charge_densities(vec![single.clone(), single.clone()]).unwrap();
+        assert_eq!(sum.pos.pos_frac.len(), 4);
+
+        let charges = Chgsum::voronoi_charges_json(&sum, &single.pos);
+        let charges = charges.as_array().unwrap();
+        assert_eq!(charges.len(), 2);
+        for (i, (charge, e)) in charges.iter().zip(expected).enumerate() {
+            assert_eq!(charge["index"], i + 1);
+            assert!(e.abs() > 0.1);
+            assert!((charge["charge"].as_f64().unwrap() - 2.0 * e).abs() < 1E-10, "{} != 2 * {}", charge, e);
+        }
+
+        let extrema = Chgsum::extrema_json(&sum, &single.pos);
+        assert_eq!(extrema["maximum"]["nearest_atom"]["symbol"], "O");
+        assert_eq!(extrema["maximum"]["nearest_atom"]["index"], 2);
+        assert_eq!(extrema["minimum"]["nearest_atom"]["index"], 1);
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let paths = ["CHGCAR_0", "CHGCAR_1", "CHGCAR_2"].map(|x| dir.path().join(x));
+        let grid = ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i + j + k) as f64);
+        cubic_density(3.0, grid.clone()).to_file(&paths[0]).unwrap();
+
+        // Slightly deformed lattice, and the grid data is broken to make sure it is not read
+        let mut deformed = cubic_density(3.0, grid.clone());
+        deformed.pos.cell[2][2] += 1E-4;
+        deformed.to_file(&paths[1]).unwrap();
+        let txt = fs::read_to_string(&paths[1]).unwrap();
+        let nheader = txt.lines().position(|l| l.trim().is_empty()).unwrap() + 2;
+        let header_len = txt.lines().take(nheader).map(|l| l.len() + 1).sum::<usize>();
+        fs::write(&paths[1], format!("{}garbage garbage\n", &txt[.. header_len])).unwrap();
+
+        assert!(check_headers(&paths[.. 1], 1E-6, true).is_ok());
+        assert!(check_headers(&paths[.. 2], 1E-3, true).is_ok());
+        let msg = format!("{:#}", check_headers(&paths[.. 2], 1E-6, true).unwrap_err());
+        assert!(msg.contains("lattice vectors differ"), "{}", msg);
+
+        cubic_density(3.0, ndarray::Array3::zeros((2, 3, 5))).to_file(&paths[2]).unwrap();
+        let two = [&paths[0], &paths[2]];
+        let msg = format!("{:#}", check_headers(&two, 1E-6, true).unwrap_err());
+        assert!(msg.contains("[2, 3, 4] != [2, 3, 5]"), "{}", msg);
+        assert!(check_headers(&two, 1E-6, false).is_ok());
+    }
+
+    #[test]
+    fn test_info_from_header() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("CHGCAR");
+        let chg = cubic_density(3.0, ndarray::Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i + j + k) as f64));
+        chg.to_file(&path).unwrap();
+
+        // `--info` never reads the grid data
+        let txt = fs::read_to_string(&path).unwrap();
+        let nheader = txt.lines().position(|l| l.trim().is_empty()).unwrap() + 2;
+        fs::write(&path, txt.lines().take(nheader).map(|l| format!("{}\n", l)).collect::<String>() + "garbage\n").unwrap();
+
+        let (pos, ngrid) = ChargeDensity::read_header(&path).unwrap();
+        let info = Chgsum::info_json(&path, &pos, ngrid);
+        assert_eq!(info["grid"], json!([2, 3, 4]));
+        for (x, y) in info["grid_spacing"].as_array().unwrap().iter().zip(chg.grid_spacing()) {
+            assert!((x.as_f64().unwrap() - y).abs() < 1E-6);
+        }
+        assert!((info["volume"].as_f64().unwrap() - chg.pos.get_volume()).abs() < 1E-6);
+    }
+
+    #[test]
+    fn test_heuristic_warnings() {
+        let pos = |comment: &str, ion_types: &[&str], ions_per_type: &[i32]| Poscar {
+            comment: comment.to_string(),
+            scale: 1.0,
+            cell: [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]],
+            ion_types: ion_types.iter().map(|x| x.to_string()).collect(),
+            ions_per_type: ions_per_type.to_vec(),
+            pos_cart: vec![[0.0; 3]; ions_per_type.iter().sum::<i32>() as usize],
+            pos_frac: vec![[0.0; 3]; ions_per_type.iter().sum::<i32>() as usize],
+            constraints: None,
+        };
+        let paths = [PathBuf::from("a/CHGCAR"), PathBuf::from("b/CHGCAR"), PathBuf::from("c/CHGCAR")];
+        let inputs = paths.iter().collect::<Vec<_>>();
+
+        let a = pos("MoS2 band 12", &["Mo", "S"], &[1, 2]);
+        let b = pos("MoS2 band 13", &["S", "Mo"], &[2, 1]);
+        assert!(heuristic_warnings(&inputs[.. 2], &[&a, &b]).is_empty());
+
+        let c = pos("graphene on Cu(111)", &["C", "Cu"], &[2, 12]);
+        let warnings = heuristic_warnings(&inputs, &[&a, &b, &c]);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("Title of \"c/CHGCAR\""), "{}", warnings[0]);
+        assert!(warnings[1].starts_with("Composition of \"c/CHGCAR\""), "{}", warnings[1]);
+
+        assert!((title_similarity("  abc ", "abc") - 1.0).abs() < 1E-12);
+        assert!((title_similarity("", "") - 1.0).abs() < 1E-12);
+    }
+}
@@ -1,8 +1,12 @@
 use std::{
-    io::Write,
+    io::{
+        IsTerminal,
+        Write,
+    },
     fs,
     path::Path,
     fmt::Write as _,
+    sync::Mutex,
 };
 
 use regex::Regex;
@@ -304,6 +308,147 @@ pub fn generate_plotly_configuration() -> plotly::Configuration {
 }
 
 
+/// Edit distance between `a` and `b`, counted in chars.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0 ..= b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
+
+// The progress bar being shown, at most one bar is shown at a time.
+static ACTIVE_PROGRESS: Mutex<Option<ProgressState>> = Mutex::new(None);
+
+const PROGRESS_WIDTH: usize = 30;
+
+
+struct ProgressState {
+    message: String,
+    pos: usize,
+    len: usize,
+}
+
+
+impl ProgressState {
+    fn render(&self) -> String {
+        let filled = (PROGRESS_WIDTH * self.pos.min(self.len)).checked_div(self.len).unwrap_or(0);
+        format!("{} [{}{}] {}/{}", self.message, "=".repeat(filled), " ".repeat(PROGRESS_WIDTH - filled),
+                self.pos, self.len)
+    }
+
+    fn draw(&self) {
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", self.render());
+        let _ = stderr.flush();
+    }
+
+    fn clear() {
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+    }
+}
+
+
+/// Progress bar of `len` steps drawn on stderr, it is cleared when dropped.
+///
+/// The bar is hidden if `quiet` is set or stderr is not a terminal. Log records should go through
+/// [`ProgressLogger`] so that they are written above the bar instead of garbling it.
+pub struct ProgressBar {
+    visible: bool,
+}
+
+
+impl ProgressBar {
+    pub fn new(len: usize, message: &str, quiet: bool) -> Self {
+        let visible = !quiet && len > 0 && std::io::stderr().is_terminal();
+        if visible {
+            let state = ProgressState { message: message.to_string(), pos: 0, len };
+            state.draw();
+            *ACTIVE_PROGRESS.lock().unwrap() = Some(state);
+        }
+        Self { visible }
+    }
+
+    pub fn hidden() -> Self {
+        Self { visible: false }
+    }
+
+    /// Advance the bar by one step, it's safe to call from multiple threads.
+    pub fn inc(&self) {
+        if !self.visible {
+            return;
+        }
+        if let Some(state) = ACTIVE_PROGRESS.lock().unwrap().as_mut() {
+            state.pos += 1;
+            state.draw();
+        }
+    }
+}
+
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        if self.visible {
+            *ACTIVE_PROGRESS.lock().unwrap() = None;
+            ProgressState::clear();
+        }
+    }
+}
+
+
+/// Logger wrapper clearing the progress bar before writing a record and drawing it again
+/// afterwards.
+pub struct ProgressLogger<L> {
+    inner: L,
+}
+
+
+impl<L: log::Log> ProgressLogger<L> {
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+}
+
+
+impl<L: log::Log> log::Log for ProgressLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+
+        let active = ACTIVE_PROGRESS.lock().unwrap();
+        match active.as_ref() {
+            Some(state) => {
+                ProgressState::clear();
+                self.inner.log(record);
+                self.inner.flush();
+                state.draw();
+            },
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -371,4 +516,18 @@ mod test {
         assert!(RawSelection::parse_iorbits(Some("  \n"), &nlm).is_err());
         assert!(RawSelection::parse_iorbits(Some(" y"), &nlm).is_err());
     }
+
+    #[test]
+    fn test_progress_render() {
+        let state = |pos, len| ProgressState { message: "Reading".to_string(), pos, len };
+        assert_eq!(state(0, 3).render(), format!("Reading [{}] 0/3", " ".repeat(30)));
+        assert_eq!(state(1, 3).render(), format!("Reading [{}{}] 1/3", "=".repeat(10), " ".repeat(20)));
+        assert_eq!(state(3, 3).render(), format!("Reading [{}] 3/3", "=".repeat(30)));
+        assert_eq!(state(0, 0).render(), format!("Reading [{}] 0/0", " ".repeat(30)));
+
+        // Hidden bars are no-ops
+        let bar = ProgressBar::new(3, "Reading", true);
+        bar.inc();
+        assert!(ACTIVE_PROGRESS.lock().unwrap().is_none());
+    }
 }
@@ -0,0 +1,70 @@
+use std::io::Write;
+
+use clap::Args;
+use clap_complete::{
+    generate,
+    Shell,
+};
+
+use crate::{
+    Result,
+    OptProcess,
+    cli,
+};
+
+
+const INSTALL_HELP: &str = "\
+Installation:
+  bash: rsgrad completions bash > ~/.local/share/bash-completion/completions/rsgrad
+  zsh:  rsgrad completions zsh > ~/.zfunc/_rsgrad
+        and add `fpath+=~/.zfunc` before `compinit` in ~/.zshrc
+  fish: rsgrad completions fish > ~/.config/fish/completions/rsgrad.fish
+
+Restart the shell to make it take effect.";
+
+
+#[derive(Debug, Args)]
+/// Print the shell completion script of rsgrad to stdout.
+///
+/// All the subcommands and their options are completed.
+#[command(after_help = INSTALL_HELP)]
+pub struct Completions {
+    #[arg(value_enum, ignore_case = true)]
+    /// Target shell.
+    shell: Shell,
+}
+
+
+/// Write the completion script of `shell` to `buf`.
+pub fn write_completions(shell: Shell, buf: &mut dyn Write) {
+    let mut cmd = cli::command();
+    let name = cmd.get_name().to_string();
+    generate(shell, &mut cmd, name, buf);
+}
+
+
+impl OptProcess for Completions {
+    fn process(&self) -> Result<()> {
+        write_completions(self.shell, &mut std::io::stdout());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_completions() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut buf = Vec::<u8>::new();
+            write_completions(shell, &mut buf);
+            let script = String::from_utf8(buf).unwrap();
+
+            for token in ["rsgrad", "uc", "chgsum", "planar-average", "config", "json"] {
+                assert!(script.contains(token), "{} {:?}", shell, token);
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use clap::{
+    Args,
+    Subcommand,
+};
+use log::{
+    info,
+    warn,
+};
+use anyhow::{
+    Context,
+    bail,
+};
+
+use crate::{
+    Settings,
+    Result,
+    OptProcess,
+    vasp_parsers::potcar::{
+        FunctionalType,
+        PotcarHeader,
+    },
+};
+
+
+#[derive(Debug, Args)]
+/// Inspect the rsgrad configuration and the resources it points to.
+pub struct Config {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    ListPotcars(ListPotcars),
+    Init(Init),
+}
+
+
+#[derive(Debug, Args)]
+/// List the potentials available under the configured functional path.
+///
+/// Each sub-directory containing a POTCAR (or compressed POTCAR.z, POTCAR.Z and POTCAR.gz) is
+/// listed with the title line and valence of its POTCAR.
+struct ListPotcars {
+    #[arg(long, default_value = "PAW_PBE")]
+    /// Specify the functional type, "PAW_PBE"(or "paw_pbe", "PBE") and "PAW_LDA"(or "paw_lda",
+    /// "LDA") are available, as well as "PAW_GGA", "PAW_PBE_GW", "PAW_LDA_GW", "US_GGA" and
+    /// "US_LDA" if configured.
+    functional: FunctionalType,
+
+    #[arg(long)]
+    /// Only list the potentials whose directory name starts with this, e.g. `Fe` lists `Fe`,
+    /// `Fe_pv` and `Fe_sv`.
+    filter: Option<String>,
+}
+
+
+impl ListPotcars {
+    fn process(&self) -> Result<()> {
+        let settings = Settings::from_default()?;
+
+        let dir = settings.functional_path.try_get(self.functional)?;
+
+        info!("Scanning potentials in {:?} ...", dir);
+        let mut names = std::fs::read_dir(dir)
+            .with_context(|| format!("Cannot read directory {:?}", dir))?
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping unreadable entry in {:?}: {}", dir, e);
+                    None
+                },
+            })
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| self.filter.as_ref().map_or(true, |f| name.starts_with(f.as_str())))
+            .collect::<Vec<_>>();
+        names.sort();
+
+        let width = names.iter().map(|x| x.len()).max().unwrap_or(0).max(4);
+        println!(" {:<width$} {:>8}  {}", "Name", "Valence", "Title", width = width);
+        for name in names.iter() {
+            match PotcarHeader::from_dir(&dir.join(name)) {
+                Ok(header) => println!(" {:<width$} {:8.3}  {}", name, header.valence, header.titel, width = width),
+                Err(e) => warn!("Skipping {:?}: {}", name, e),
+            }
+        }
+
+        if names.is_empty() {
+            warn!("No potential found in {:?}.", dir);
+        }
+
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Args)]
+/// Write a commented template configuration file.
+///
+/// The paths in the template are placeholders, edit them before running `rsgrad pot`.
+struct Init {
+    #[arg(long)]
+    /// Where to write the template, if left blank, rsgrad will write the file given by the
+    /// global `--config` flag or `RSGRAD_CONFIG`, then `.rsgrad.toml` at your home dir.
+    path: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Overwrite the file if it already exists.
+    force: bool,
+}
+
+
+impl Init {
+    fn process(&self) -> Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) => path.clone(),
+            None => Settings::default_path()?,
+        };
+
+        if path.exists() && !self.force {
+            bail!("File {:?} already exists, use `--force` to overwrite it.", path);
+        }
+
+        std::fs::write(&path, Settings::template()?)
+            .with_context(|| format!("Cannot write configuration template to {:?}", path))?;
+
+        println!("Configuration template written to {:?}, please edit the paths in it.", path);
+        Ok(())
+    }
+}
+
+
+impl OptProcess for Config {
+    fn process(&self) -> Result<()> {
+        match &self.command {
+            ConfigCommand::ListPotcars(cmd) => cmd.process(),
+            ConfigCommand::Init(cmd) => cmd.process(),
+        }
+    }
+}
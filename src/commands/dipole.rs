@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use anyhow::Context;
+use crate::{
+    types::Result,
+    ChargeDensity,
+    ChargeType,
+    OptProcess,
+};
+
+
+// 1 e*A = 4.80320 Debye
+const EA_TO_DEBYE: f64 = 4.80320471;
+
+
+#[derive(Debug, Args)]
+#[command(allow_negative_numbers = true)]
+/// Calculate the dipole moment of the charge density, i.e. ∫ r·ρ(r) dV.
+///
+/// Only the electronic density is integrated, the ions are not included, and the density is taken
+/// as positive. Note that the dipole moment of a periodic system depends on the choice of cell,
+/// here the cell spans from `origin` to `origin + (1, 1, 1)` in fractional coordinates.
+pub struct Dipole {
+    #[arg(default_value = "./CHGCAR")]
+    /// CHGCAR file path
+    chgcar: PathBuf,
+
+    #[arg(long, num_args(3), default_values = &["0", "0", "0"])]
+    /// Reference point of the dipole moment in fractional coordinates.
+    ///
+    /// For slab models, it is recommended to put the origin in the vacuum region.
+    origin: Vec<f64>,
+}
+
+
+impl OptProcess for Dipole {
+    fn process(&self) -> Result<()> {
+        info!("Reading charge density from {:?}", self.chgcar);
+        let chgcar = ChargeDensity::from_file(&self.chgcar, ChargeType::Chgcar)
+            .with_context(|| format!("Failed to read charge density from {:?}", self.chgcar))?;
+
+        let origin = [self.origin[0], self.origin[1], self.origin[2]];
+        let dipole = chgcar.dipole_moment_with_origin(origin);
+        let magnitude = dipole.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+        println!("Origin (fractional): {:10.6} {:10.6} {:10.6}", origin[0], origin[1], origin[2]);
+        println!("Dipole moment (e*A): {:15.6} {:15.6} {:15.6}", dipole[0], dipole[1], dipole[2]);
+        println!("|p| = {:.6} e*A = {:.6} Debye", magnitude, magnitude * EA_TO_DEBYE);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use anyhow::{
+    bail,
+    Context,
+};
+use ndarray::Array1;
+use crate::{
+    types::Result,
+    ChargeDensity,
+    ChargeType,
+    OptProcess,
+    commands::common::write_array_to_txt,
+};
+
+
+#[derive(Debug, Args)]
+#[command(allow_negative_numbers = true)]
+/// Sample the charge density along a line between two points, e.g. along a bond.
+///
+/// The density is evaluated by trilinear interpolation with periodic boundary condition, thus
+/// the points may lie outside of the cell.
+pub struct LineProfile {
+    #[arg(default_value = "./CHGCAR")]
+    /// CHGCAR file path
+    chgcar: PathBuf,
+
+    #[arg(long, num_args(3), required = true)]
+    /// Start point of the line in fractional coordinates.
+    start: Vec<f64>,
+
+    #[arg(long, num_args(3), required = true)]
+    /// End point of the line in fractional coordinates.
+    end: Vec<f64>,
+
+    #[arg(long, short, default_value_t = 100)]
+    /// Number of sampling points, both ends are included.
+    nsample: usize,
+
+    #[arg(long, default_value = "line_profile.txt")]
+    /// Write the distance (A) and density (e/A^3) as two columns.
+    txtout: PathBuf,
+}
+
+
+impl OptProcess for LineProfile {
+    fn process(&self) -> Result<()> {
+        if self.nsample < 2 {
+            bail!("At least 2 sampling points are needed, got {}.", self.nsample);
+        }
+
+        info!("Reading charge density from {:?}", self.chgcar);
+        let chgcar = ChargeDensity::from_file(&self.chgcar, ChargeType::Chgcar)
+            .with_context(|| format!("Failed to read charge density from {:?}", self.chgcar))?;
+
+        let start = [self.start[0], self.start[1], self.start[2]];
+        let end   = [self.end[0], self.end[1], self.end[2]];
+        let (distance, density) = chgcar.line_profile(start, end, self.nsample);
+
+        info!("Writing line profile to {:?}", self.txtout);
+        write_array_to_txt(&self.txtout, vec![&Array1::from(distance), &Array1::from(density)],
+                           "Distance(A)  Density(e/A^3)")?;
+
+        Ok(())
+    }
+}
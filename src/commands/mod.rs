@@ -10,8 +10,14 @@ pub mod wav3d;
 pub mod wav1d;
 pub mod chgdiff;
 pub mod chgsum;
+pub mod chgscale;
+pub mod dipole;
+pub mod lineprofile;
+pub mod planar_average;
 pub mod common;
 pub mod tdm;
 pub mod gap;
 pub mod uc;
 pub mod modelnac;
+pub mod config;
+pub mod completions;
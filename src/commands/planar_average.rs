@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+use std::fmt::Write as _;
+use clap::Args;
+use log::info;
+use anyhow::Context;
+use crate::{
+    types::{
+        Result,
+        Axis,
+    },
+    ChargeDensity,
+    ChargeType,
+    OptProcess,
+};
+
+
+#[derive(Debug, Args)]
+/// Calculate the planar average of CHGCAR or LOCPOT along a lattice axis.
+///
+/// For each plane perpendicular to the axis, the grid values in this plane are averaged. The
+/// output is a two-column table of the fractional position along the axis and the averaged value.
+pub struct PlanarAverage {
+    #[arg(default_value = "./CHGCAR")]
+    /// CHGCAR or LOCPOT file path, gzip compressed file is also accepted.
+    input: PathBuf,
+
+    #[arg(long)]
+    /// Read the input as LOCPOT, the values are taken as is (eV). Otherwise the density is
+    /// in e/A^3.
+    locpot: bool,
+
+    #[arg(long, default_value = "z", value_enum, ignore_case = true)]
+    /// Averaging direction, `a`, `b` and `c` are the same as `x`, `y` and `z`.
+    axis: Axis,
+
+    #[arg(long, short = 'o')]
+    /// Write the table to file, the table is printed to stdout if not specified.
+    txtout: Option<PathBuf>,
+}
+
+
+// Two-column table of the fractional position along `axis` and the averaged value.
+fn format_table(values: &[f64], axis: Axis, unit: &str) -> String {
+    let n = values.len();
+    let mut ret = format!("# Frac_{}  Average({})\n", axis, unit);
+    for (i, v) in values.iter().enumerate() {
+        writeln!(ret, "  {:15.6}  {:15.6}", i as f64 / n as f64, v).unwrap();
+    }
+    ret
+}
+
+
+impl OptProcess for PlanarAverage {
+    fn process(&self) -> Result<()> {
+        let (chgtype, unit) = if self.locpot {
+            (ChargeType::Locpot, "eV")
+        } else {
+            (ChargeType::Chgcar, "e/A^3")
+        };
+
+        info!("Reading {:?} and averaging along {} axis ...", self.input, self.axis);
+        let average = ChargeDensity::planar_average_from_file(&self.input, chgtype, self.axis)
+            .with_context(|| format!("Failed to read volumetric data from {:?}", self.input))?;
+        let table = format_table(&average, self.axis, unit);
+
+        if let Some(txtout) = self.txtout.as_ref() {
+            info!("Writing planar average to {:?}", txtout);
+            std::fs::write(txtout, table)?;
+        } else {
+            print!("{}", table);
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_table() {
+        let txt = format_table(&[1.0, 2.0, 3.0, 4.5], Axis::Z, "e/A^3");
+        let lines = txt.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "# Frac_Z  Average(e/A^3)");
+
+        let row = lines[2].split_whitespace()
+            .map(|x| x.parse::<f64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(row, vec![0.25, 2.0]);
+    }
+}
@@ -18,13 +18,11 @@ use crate::{
 
 
 #[derive(Debug, Args)]
-/// Generate the POTCAR according to POSCAR
+/// Generate the POTCAR according to POSCAR, or an explicit element order.
+///
+/// Every element is checked to have a POTCAR under the functional's directory before anything is
+/// written, thus a missing one leaves no half-written POTCAR.
 pub struct Pot {
-    #[arg(long, short)]
-    /// Specify the configuration file, if left blank, rsgrad will read `.rsgrad.toml` at
-    /// your home dir.
-    config: Option<PathBuf>,
-
     #[arg(long, short, default_value = "./POSCAR")]
     /// Specify the POSCAR file
     ///
@@ -33,33 +31,61 @@ pub struct Pot {
     /// the POSCAR
     poscar: PathBuf,
 
-    #[arg(default_value = "PAW_PBE")]
-    /// Specify the functional type, now only "PAW_PBE"(or "paw_pbe") and "PAW_LDA"(or "paw_lda") are available.
-    functional: FunctionalType,
+    #[arg(long, short, value_delimiter = ',')]
+    /// Element order of the POTCAR instead of reading it from POSCAR, e.g. `--elements Fe,O,H`.
+    ///
+    /// The valence configuration can be specified the same as in POSCAR, e.g. `K_sv`.
+    elements: Vec<String>,
+
+    /// Specify the functional type, "PAW_PBE"(or "paw_pbe", "pbe") and "PAW_LDA"(or "paw_lda",
+    /// "lda") are available, as well as "PAW_GGA", "PAW_PBE_GW", "PAW_LDA_GW", "US_GGA" and
+    /// "US_LDA" if configured. Defaults to "PAW_PBE".
+    functional: Option<FunctionalType>,
+
+    #[arg(long = "functional", value_name = "FUNCTIONAL", conflicts_with = "functional")]
+    /// The same as the positional FUNCTIONAL, e.g. `--functional lda`.
+    functional_flag: Option<FunctionalType>,
 
     #[arg(long, short, default_value = "./")]
     /// Specify where the `POTCAR` would be written
     save_in: PathBuf,
+
+    #[arg(long)]
+    /// Check that all the `aliases` in the configuration point to existing POTCARs before
+    /// generating, the missing ones are listed at once.
+    check_aliases: bool,
 }
 
 
 impl OptProcess for Pot {
     fn process(&self) -> Result<()> {
-        let settings = if let Some(path) = self.config.as_ref() {
-            Settings::from_file(path)?
+        self.run(&Settings::from_default()?)
+    }
+}
+
+
+impl Pot {
+    // Body of `process` with the settings read, see the global `--config` flag.
+    fn run(&self, settings: &Settings) -> Result<()> {
+        if self.check_aliases {
+            settings.check_aliases()?;
+        }
+
+        let titels = if self.elements.is_empty() {
+            info!("Reading POSCAR file {:?} ...", &self.poscar);
+            Poscar::from_file(&self.poscar)?.ion_types
         } else {
-            Settings::from_default()?
+            self.elements.clone()
         };
-
-        info!("Reading POSCAR file {:?} ...", &self.poscar);
-        let pos = Poscar::from_file(&self.poscar)?;
+        let functional = self.functional_flag
+            .or(self.functional)
+            .unwrap_or(FunctionalType::PAW_PBE);
 
         let (symbols, specified_types) = {
-            let titels = &pos.ion_types;
             let mut symbols = Vec::<String>::new();
             let mut specified_types = Vec::<String>::new();
             
-            for titel in titels {
+            for titel in titels.iter() {
                 let mut it = titel.splitn(2, '_');
                 let symbol = it.next()
                     .context(format!("No chemical symbol found in {}", titel))?
@@ -76,11 +102,11 @@ impl OptProcess for Pot {
         };
 
         let pot = Potcar::from_config(&symbols,
-                                      &self.functional,
+                                      &functional,
                                       &specified_types,
                                       &settings.functional_path)?;
 
-        let fname = self.save_in.with_file_name("POTCAR");
+        let fname = self.save_in.join("POTCAR");
         if fname.is_file() {
             warn!("Found `POTCAR` in specified dir, renaming it to `POTCAR.bak`");
             let renamed_fname = fname.with_extension("bak");
@@ -93,3 +119,40 @@ impl OptProcess for Pot {
         Ok(())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::potential_dirs;
+
+    #[test]
+    fn test_pot_from_elements() {
+        let (dir, functional_path) = potential_dirs(&[
+            ("potpaw_LDA/Fe/POTCAR", "PAW Fe\n"),
+            ("potpaw_LDA/O/POTCAR", "PAW O\n"),
+            ("potpaw_LDA/H/POTCAR", "PAW H\n"),
+        ]);
+
+        let out = dir.path().join("out");
+        std::fs::create_dir(&out).unwrap();
+        let settings = Settings { functional_path, uc: None };
+        let pot = |elements: &[&str], functional: &str| Pot {
+            poscar: PathBuf::from("./POSCAR"),
+            elements: elements.iter().map(|x| x.to_string()).collect(),
+            functional: None,
+            functional_flag: Some(functional.parse().unwrap()),
+            save_in: out.clone(),
+            check_aliases: false,
+        };
+
+        pot(&["O", "Fe", "H"], "lda").run(&settings).unwrap();
+        assert_eq!(std::fs::read_to_string(out.join("POTCAR")).unwrap(), "PAW O\nPAW Fe\nPAW H\n");
+
+        // Nothing is written if any element is missing
+        std::fs::remove_file(out.join("POTCAR")).unwrap();
+        assert!(pot(&["O", "Fe", "H"], "pbe").run(&settings).is_err());
+        assert!(pot(&["O", "Fe_sv", "H"], "lda").run(&settings).is_err());
+        assert!(!out.join("POTCAR").exists());
+    }
+}
@@ -2,8 +2,12 @@ use std::sync::OnceLock;
 use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::fmt;
+use std::io::BufRead;
 
-use clap::Args;
+use clap::{
+    Args,
+    ValueEnum,
+};
 use nom::{
     branch::alt,
     bytes::complete::tag,
@@ -16,18 +20,26 @@ use nom::{
         map,
         eof,
         recognize,
+        success,
     },
     sequence::{
-        delimited,
+        preceded,
         terminated,
         tuple,
     },
     IResult,
 };
 use anyhow::Error;
+use log::{
+    info,
+    warn,
+};
+use unicode_width::UnicodeWidthStr;
 
 use crate::Result;
 use crate::OptProcess;
+use crate::UcSettings;
+use crate::commands::common::levenshtein;
 
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -91,6 +103,35 @@ macro_rules! prefix_parser {
 
 
 impl MetricPrefix {
+    /// Abbreviated symbol of the prefix, e.g. `m` for `Milli`, empty for `One`.
+    pub fn symbol(self) -> &'static str {
+        get_prefix_str()[&self]
+    }
+
+
+    /// Full name of the prefix, e.g. `Milli`.
+    pub fn full_name(self) -> &'static str {
+        use MetricPrefix::*;
+
+        match self {
+            Atto  => "Atto",
+            Femto => "Femto",
+            Pico  => "Pico",
+            Nano  => "Nano",
+            Micro => "Micro",
+            Milli => "Milli",
+            One   => "One",
+            Kilo  => "Kilo",
+            Mega  => "Mega",
+            Giga  => "Giga",
+            Tera  => "Tera",
+            Peta  => "Peta",
+            Exa   => "Exa",
+        }
+    }
+
+
+    // Parse the leading prefix, `One` is returned without consuming any input if there is none.
     fn parse_prefix(i: &str) -> IResult<&str, MetricPrefix> {
         use MetricPrefix::*;
 
@@ -113,15 +154,15 @@ impl MetricPrefix {
         let nano_abbr  = prefix_parser!(Nano,  "n");
         let micro_abbr = prefix_parser!(Micro, "Mu", "mu", "u");
         let milli_abbr = prefix_parser!(Milli, "m");
-        let kilo_abbr  = prefix_parser!(Kilo,  "K");
+        let kilo_abbr  = prefix_parser!(Kilo,  "K", "k");
         let mega_abbr  = prefix_parser!(Mega,  "Mi", "M");
         let giga_abbr  = prefix_parser!(Giga,  "Gi", "G");
         let tera_abbr  = prefix_parser!(Tera,  "Ti", "T");
         let peta_abbr  = prefix_parser!(Peta,  "Pi", "P");
         let exa_abbr   = prefix_parser!(Exa,   "E");
 
-        //let one   = prefix_parser!(One,   "");
-
+        // No prefix, nothing is consumed. It must come last, otherwise it shadows the others.
+        let one = success(One);
 
         alt((
             alt((
@@ -154,7 +195,7 @@ impl MetricPrefix {
                 exa_abbr,
             )),
 
-            //one,
+            one,
         ))(i)
     }
 }
@@ -204,12 +245,24 @@ fn get_prefix_str() -> &'static BTreeMap<MetricPrefix, &'static str> {
 }
 
 
+impl FromStr for MetricPrefix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match terminated(MetricPrefix::parse_prefix, eof)(s.trim()) {
+            Ok((_, prefix)) => Ok(prefix),
+            Err(e) => { anyhow::bail!("Cannot parse {:?} as metric prefix: {}{}", s, e, did_you_mean(suggest_prefix(s))) }
+        }
+    }
+}
+
+
 impl fmt::Display for MetricPrefix {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if f.alternate() {  // print full form of prefix
-            write!(f, "{:?}", self)
+            write!(f, "{}", self.full_name())
         } else {    // abbreviative by default
-            write!(f, "{}", get_prefix_str()[self])
+            write!(f, "{}", self.symbol())
         }
     }
 }
@@ -227,12 +280,27 @@ pub enum Unit {
     /// J·mol⁻¹
     JoulePerMole,
 
+    /// Joule per particle, 1 eV = 1.602176634E-19 J
+    Joule,
+
+    /// Thermochemical calorie per particle, 1 cal = 4.184 J
+    Calorie,
+
     /// Temperature as energy via E=kB*T
     Kelvin,
 
+    /// Temperature in Celsius, converted to Kelvin with an offset of 273.15 first
+    Celsius,
+
+    /// Temperature in Fahrenheit, converted to Kelvin via T(K) = (T(°F) - 32) * 5/9 + 273.15 first
+    Fahrenheit,
+
     /// 1 Hartree ~= 27.2114 eV
     Hartree,
 
+    /// 1 Rydberg = 0.5 Hartree ~= 13.6057 eV
+    Rydberg,
+
     /// Inverse of wavelength in cm, 1 eV ~= 8065 cm⁻¹
     Wavenumber,
 
@@ -244,6 +312,37 @@ pub enum Unit {
 
     /// Period of light
     Second,
+
+    /// Å⁻¹, treated as the basic unit of reciprocal space quantities
+    InverseAngstrom,
+
+    /// nm⁻¹, 1 Å⁻¹ = 10 nm⁻¹
+    InverseNanometer,
+
+    /// Bohr⁻¹, 1 Å⁻¹ ~= 0.529177 Bohr⁻¹
+    InverseBohr,
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+/// Category of units, conversions are only available between units of the same category.
+pub enum UnitCategory {
+    /// Energy and the quantities representing energy, e.g. temperature, wavelength.
+    Energy,
+
+    /// Reciprocal space quantities, i.e. inverse of length.
+    Reciprocal,
+}
+
+
+impl UnitCategory {
+    /// The unit that all the quantities in this category are normalized to.
+    pub fn base_unit(self) -> Unit {
+        match self {
+            Self::Energy     => Unit::ElectronVolt,
+            Self::Reciprocal => Unit::InverseAngstrom,
+        }
+    }
 }
 
 
@@ -254,12 +353,20 @@ fn get_unit_str() -> &'static BTreeMap<Unit, &'static str> {
             (Unit::ElectronVolt, "eV"),
             (Unit::CaloriePerMole, "Cal/mol"),
             (Unit::JoulePerMole, "J/mol"),
+            (Unit::Joule, "J"),
+            (Unit::Calorie, "cal"),
             (Unit::Kelvin, "K"),
+            (Unit::Celsius, "degC"),
+            (Unit::Fahrenheit, "degF"),
             (Unit::Hartree, "Ha"),
+            (Unit::Rydberg, "Ry"),
             (Unit::Wavenumber, "cm-1"),
             (Unit::Meter, "m"),
             (Unit::Hertz, "Hz"),
             (Unit::Second, "s"),
+            (Unit::InverseAngstrom, "A-1"),
+            (Unit::InverseNanometer, "nm-1"),
+            (Unit::InverseBohr, "Bohr-1"),
         ].iter().cloned().collect()
     })
 }
@@ -268,24 +375,97 @@ fn get_unit_str() -> &'static BTreeMap<Unit, &'static str> {
 impl fmt::Display for Unit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if f.alternate() {
-            write!(f, "{:?}", self)
+            write!(f, "{}", self.full_name())
         } else {
-            write!(f, "{}", get_unit_str()[self])
+            write!(f, "{}", self.symbol())
+        }
+    }
+}
+
+
+impl FromStr for Unit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match terminated(Unit::parse_unit, eof)(s.trim()) {
+            Ok((_, unit)) => Ok(unit),
+            Err(e) => { anyhow::bail!("Cannot parse {:?} as unit: {}{}", s, e, did_you_mean(suggest_unit(s))) }
         }
     }
 }
 
 
 impl Unit {
+    /// Symbol of the unit, e.g. `cm-1` for `Wavenumber`.
+    pub fn symbol(self) -> &'static str {
+        get_unit_str()[&self]
+    }
+
+
+    /// Full name of the unit, e.g. `Wavenumber`.
+    pub fn full_name(self) -> &'static str {
+        use Unit::*;
+
+        match self {
+            ElectronVolt     => "ElectronVolt",
+            CaloriePerMole   => "CaloriePerMole",
+            JoulePerMole     => "JoulePerMole",
+            Joule            => "Joule",
+            Calorie          => "Calorie",
+            Kelvin           => "Kelvin",
+            Celsius          => "Celsius",
+            Fahrenheit       => "Fahrenheit",
+            Hartree          => "Hartree",
+            Rydberg          => "Rydberg",
+            Wavenumber       => "Wavenumber",
+            Meter            => "Meter",
+            Hertz            => "Hertz",
+            Second           => "Second",
+            InverseAngstrom  => "InverseAngstrom",
+            InverseNanometer => "InverseNanometer",
+            InverseBohr      => "InverseBohr",
+        }
+    }
+
+
+    /// Whether this is an energy per mole unit, e.g. `J/mol`, the others are per particle.
+    pub fn is_molar(self) -> bool {
+        matches!(self, Unit::CaloriePerMole | Unit::JoulePerMole)
+    }
+
+
+    /// Whether the number in this unit is inversely proportional to energy, i.e. wavelength
+    /// (`m`) and period (`s`). Only positive energies have such counterparts.
+    pub fn is_inverse(self) -> bool {
+        matches!(self, Unit::Meter | Unit::Second)
+    }
+
+
+    /// Category of this unit.
+    pub fn category(self) -> UnitCategory {
+        use Unit::*;
+
+        match self {
+            InverseAngstrom | InverseNanometer | InverseBohr => UnitCategory::Reciprocal,
+            _ => UnitCategory::Energy,
+        }
+    }
+
+
     fn parse_unit(i: &str) -> IResult<&str, Unit> {
         use Unit::*;
 
         let ev         = prefix_parser!(ElectronVolt,   "ElectronVolt");
-        let calpmol    = prefix_parser!(CaloriePerMole, "Calorie/mol");
-        let jpmol      = prefix_parser!(JoulePerMole,   "Joule/mol");
+        let calpmol    = prefix_parser!(CaloriePerMole, "Calorie/mol", "CaloriePerMole");
+        let jpmol      = prefix_parser!(JoulePerMole,   "Joule/mol", "JoulePerMole");
+        let joule      = prefix_parser!(Joule,          "Joule");
+        let calorie    = prefix_parser!(Calorie,        "Calorie");
         let kelvin     = prefix_parser!(Kelvin,         "Kelvin");
+        let celsius    = prefix_parser!(Celsius,        "Celsius");
+        let fahrenheit = prefix_parser!(Fahrenheit,     "Fahrenheit");
         let hartree    = prefix_parser!(Hartree,        "Hartree");
-        let wavenumber = prefix_parser!(Wavenumber,     "Cm-1");
+        let rydberg    = prefix_parser!(Rydberg,        "Rydberg");
+        let wavenumber = prefix_parser!(Wavenumber,     "Cm-1", "Wavenumbers", "Wavenumber", "wavenumbers", "wavenumber");
         let meter      = prefix_parser!(Meter,          "Meter");
         let hertz      = prefix_parser!(Hertz,          "Hertz");
         let second     = prefix_parser!(Second,         "Second");
@@ -293,20 +473,42 @@ impl Unit {
         let ev_abbr         = prefix_parser!(ElectronVolt,   "eV");
         let calpmol_abbr    = prefix_parser!(CaloriePerMole, "Cal/mol");
         let jpmol_abbr      = prefix_parser!(JoulePerMole,   "J/mol");
+        let joule_abbr      = prefix_parser!(Joule,          "J");
+        let calorie_abbr    = prefix_parser!(Calorie,        "cal");
         let kelvin_abbr     = prefix_parser!(Kelvin,         "K");
+        let celsius_abbr    = prefix_parser!(Celsius,        "degC", "°C");
+        let fahrenheit_abbr = prefix_parser!(Fahrenheit,     "degF", "°F");
         let hartree_abbr    = prefix_parser!(Hartree,        "Ha");
-        let wavenumber_abbr = prefix_parser!(Wavenumber,     "cm-1");
+        let rydberg_abbr    = prefix_parser!(Rydberg,        "Ry");
+        let wavenumber_abbr = prefix_parser!(Wavenumber,     "cm-1", "cm^-1", "cm**-1", "1/cm");
         let meter_abbr      = prefix_parser!(Meter,          "m");
         let hertz_abbr      = prefix_parser!(Hertz,          "Hz");
         let second_abbr     = prefix_parser!(Second,         "s");
 
+        // `1/A` and `1/cm` like spellings need a space after the number, e.g. `0.5 1/A`, otherwise the
+        // leading `1` is consumed as part of the number.
+        let inv_angstrom = prefix_parser!(InverseAngstrom,  "InverseAngstrom",  "A-1", "Å-1", "1/A", "1/Å");
+        let inv_nm       = prefix_parser!(InverseNanometer, "InverseNanometer", "nm-1", "1/nm");
+        let inv_bohr     = prefix_parser!(InverseBohr,      "InverseBohr",      "Bohr-1", "1/Bohr");
+
         alt((
+            alt((
+                inv_angstrom,
+                inv_nm,
+                inv_bohr,
+            )),
             alt((
                 ev,
+                // per mole first, otherwise `Joule/mol` is parsed as `Joule` followed by `/mol`
                 calpmol,
                 jpmol,
+                joule,
+                calorie,
                 kelvin,
+                celsius,
+                fahrenheit,
                 hartree,
+                rydberg,
                 wavenumber,
                 meter,
                 hertz,
@@ -316,8 +518,13 @@ impl Unit {
                 ev_abbr,
                 calpmol_abbr,
                 jpmol_abbr,
+                joule_abbr,
+                calorie_abbr,
                 kelvin_abbr,
+                celsius_abbr,
+                fahrenheit_abbr,
                 hartree_abbr,
+                rydberg_abbr,
                 wavenumber_abbr,
                 meter_abbr,
                 hertz_abbr,
@@ -335,19 +542,223 @@ fn get_ratio_ev_to_other() -> &'static BTreeMap<Unit, f64> {
             (Unit::ElectronVolt,   1.0f64),
             (Unit::CaloriePerMole, 1.60217733 * 6.0223 * 1E4 / 4.184),
             (Unit::JoulePerMole,   1.60217733 * 6.0223 * 1E4),
+            (Unit::Joule,          1.602176634E-19),
+            (Unit::Calorie,        1.602176634E-19 / 4.184),
             (Unit::Kelvin,         1.160451812E4),
             (Unit::Hartree,        1.0 / 27.2114),
+            (Unit::Rydberg,        2.0 / 27.2114),
             (Unit::Wavenumber,     8065.73),
             (Unit::Meter,          1.23984193E-6),
             (Unit::Hertz,          2.417989242E14),
             (Unit::Second,         1.0 / 2.417989242E14),
+
+            // reciprocal space quantities, ratio of Å⁻¹ to other
+            (Unit::InverseAngstrom,  1.0f64),
+            (Unit::InverseNanometer, 10.0),
+            (Unit::InverseBohr,      0.529177210903),
         ].iter().cloned().collect()
     })
 }
 
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Constants used by the conversions between units, only the Boltzmann constant is configurable.
+///
+/// The methods of [`Quantity`] convert with the default constants, use the same methods here to
+/// convert with others, e.g. to reproduce numbers from other sources.
+///
+/// ```
+/// use rsgrad::commands::uc::{Conversion, PrefixMode, Quantity, Unit};
+///
+/// let q: Quantity = "1eV".parse().unwrap();
+/// let conv = Conversion::with_boltzmann_constant(Some(8.6E-5)).unwrap();
+/// let t = conv.to_quantity_with(q, Unit::Kelvin, PrefixMode::None);
+/// assert!((t.number - 1.0 / 8.6E-5).abs() < 1E-6);
+/// ```
+pub struct Conversion {
+    kb: f64,
+}
+
+
+impl Default for Conversion {
+    /// kB = 1 / 1.160451812E4 ~= 8.617333E-5 eV/K.
+    fn default() -> Self {
+        Self { kb: 1.0 / get_ratio_ev_to_other()[&Unit::Kelvin] }
+    }
+}
+
+
+impl Conversion {
+    /// Conversion with the Boltzmann constant `kb` in eV/K, the default one is used if `None`.
+    pub fn with_boltzmann_constant(kb: Option<f64>) -> Result<Self> {
+        match kb {
+            None => Ok(Self::default()),
+            Some(kb) if kb.is_finite() && kb > 0.0 => Ok(Self { kb }),
+            Some(kb) => anyhow::bail!("Boltzmann constant should be a positive number, got {}", kb),
+        }
+    }
+
+    /// The Boltzmann constant in eV/K.
+    pub fn boltzmann_constant(&self) -> f64 {
+        self.kb
+    }
+
+
+    // Ratio of eV to `unit`. For Celsius and Fahrenheit this is the ratio of temperature
+    // differences, the offset is applied by `offset_to_kelvin` and `offset_from_kelvin`.
+    fn ratio(&self, unit: Unit) -> f64 {
+        match unit {
+            Unit::Kelvin     => 1.0 / self.kb,
+            Unit::Celsius    => 1.0 / self.kb,
+            Unit::Fahrenheit => 1.8 / self.kb,
+            _ => get_ratio_ev_to_other()[&unit],
+        }
+    }
+
+
+    /// Same as [`Quantity::normalize`], with the constants of `self`.
+    pub fn normalize(&self, q: Quantity) -> Quantity {
+        self.normalize_unit(q.normalize_prefix())
+    }
+
+
+    // Convert to the base unit of the category, the `prefix` must be `One` before calling this
+    // function, i.e. call it through `normalize`.
+    //
+    // `Meter` and `Second` are inversely proportional to energy, thus the same `ratio / number`
+    // is used both here and in `to_normalized_quantity`, it is its own inverse. See
+    // `inverse_of` for the non-positive numbers.
+    fn normalize_unit(&self, mut q: Quantity) -> Quantity {
+        use Unit::*;
+
+        debug_assert_eq!(q.prefix, MetricPrefix::One);
+        let unit = q.unit;
+        let ratio = self.ratio(unit);
+        q.number = match unit {
+            Meter | Second => inverse_of(ratio, q.number),
+            Celsius | Fahrenheit => offset_to_kelvin(unit, q.number) / self.ratio(Kelvin),
+            _ => q.number / ratio,
+        };
+        q.unit = unit.category().base_unit();
+        q
+    }
+
+
+    /// Same as [`Quantity::to_quantity`], with the constants of `self`.
+    pub fn to_quantity(&self, q: Quantity, unit: Unit) -> Quantity {
+        let q = self.to_normalized_quantity(q, unit);
+        match unit.category() {
+            _ if matches!(unit, Unit::Celsius | Unit::Fahrenheit) => q,
+            UnitCategory::Energy     => q.add_metrix_prefix(),
+            UnitCategory::Reciprocal => q,
+        }
+    }
+
+
+    /// Same as [`Quantity::to_quantity_with`], with the constants of `self`.
+    pub fn to_quantity_with(&self, q: Quantity, unit: Unit, prefix: PrefixMode) -> Quantity {
+        match prefix {
+            PrefixMode::Auto      => self.to_quantity(q, unit),
+            PrefixMode::Fixed(p)  => self.to_normalized_quantity(q, unit).with_prefix(p),
+            PrefixMode::None      => self.to_normalized_quantity(q, unit),
+        }
+    }
+
+
+    /// Same as [`Quantity::snap`], with the constants of `self`.
+    pub fn snap(&self, q: Quantity, unit: Unit, step_in_unit: f64) -> Result<Quantity> {
+        let step = step_in_unit.abs();
+        if step == 0.0 || !step.is_finite() {
+            anyhow::bail!("Step of snapping should be a finite non-zero number, got {}", step_in_unit);
+        }
+
+        let mut q = self.to_normalized_quantity(q, unit);
+        q.number = (q.number / step).round() * step;
+        Ok(q)
+    }
+
+
+    /// Same as [`Quantity::approx_eq`], with the constants of `self`.
+    pub fn approx_eq(&self, lhs: &Quantity, rhs: &Quantity, rel_tol: f64) -> bool {
+        if lhs.unit.category() != rhs.unit.category() {
+            return false;
+        }
+        let a = self.normalize(*lhs).number;
+        let b = self.normalize(*rhs).number;
+        (a - b).abs() <= rel_tol * a.abs().max(b.abs())
+    }
+
+
+    /// Same as [`Quantity::convert_chain`], with the constants of `self`.
+    pub fn convert_chain(&self, q: Quantity, units: &[Unit]) -> Vec<Quantity> {
+        units.iter()
+            .scan(q, |q, unit| {
+                *q = self.to_quantity(*q, *unit);
+                Some(*q)
+            })
+            .collect()
+    }
+
+
+    // Convert to `unit` without prefix, `q` can be in any prefix and unit of the same category.
+    fn to_normalized_quantity(&self, q: Quantity, unit: Unit) -> Quantity {
+        use Unit::*;
+        assert_eq!(q.unit.category(), unit.category(),
+                   "Cannot convert {:#} to {:#}, they belong to different categories.", q.unit, unit);
+        let mut q = self.normalize(q);
+
+        q.unit = unit;
+        let ratio = self.ratio(unit);
+        q.number = match unit {
+            Meter | Second => inverse_of(ratio, q.number),
+            Celsius | Fahrenheit => offset_from_kelvin(unit, q.number * self.ratio(Kelvin)),
+            _ => q.number * ratio,
+        };
+        q
+    }
+}
+
+
+// Temperature `t` in `unit` to Kelvin, only Celsius and Fahrenheit are changed.
+fn offset_to_kelvin(unit: Unit, t: f64) -> f64 {
+    match unit {
+        Unit::Celsius    => t + 273.15,
+        Unit::Fahrenheit => (t - 32.0) / 1.8 + 273.15,
+        _ => t,
+    }
+}
+
+
+// Temperature `t` in Kelvin to `unit`, only Celsius and Fahrenheit are changed.
+fn offset_from_kelvin(unit: Unit, t: f64) -> f64 {
+    match unit {
+        Unit::Celsius    => t - 273.15,
+        Unit::Fahrenheit => (t - 273.15) * 1.8 + 32.0,
+        _ => t,
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// How the metric prefix is chosen when formatting a quantity with [`Quantity::display_in`].
+pub enum PrefixMode {
+    /// Choose a proper prefix automatically, the same as [`Quantity::to_quantity`].
+    Auto,
+
+    /// Always use the given prefix, e.g. `Fixed(MetricPrefix::Milli)` gives `meV`.
+    Fixed(MetricPrefix),
+
+    /// No prefix, the number is in the bare unit.
+    None,
+}
+
+
 #[derive(Copy, Clone, Debug)]
 /// Each energy quantity should contains three parts: number, prefix and unit.
+///
+/// `PartialEq` is deliberately not derived: the same energy has many representations, e.g.
+/// `1 eV` and `1000 meV`, which would compare unequal field by field. Use
+/// [`Quantity::same_energy`] to compare the energies they represent.
 pub struct Quantity {
     /// Singular float number
     pub number: f64,
@@ -371,10 +782,11 @@ impl FromStr for Quantity {
 
 impl fmt::Display for Quantity {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let number = self.format_number(6, false);
         if f.alternate() {
-            write!(f, "{:11.6} {:#} {:#}", self.number, self.prefix, self.unit)
+            write!(f, "{:>11} {:#} {:#}", number, self.prefix, self.unit)
         } else {
-            write!(f, "{:11.6} {:}{:}", self.number, self.prefix, self.unit)
+            write!(f, "{:>11} {:}{:}", number, self.prefix, self.unit)
         }
     }
 }
@@ -384,40 +796,115 @@ impl Quantity {
     pub fn parse_quantity(i: &str) -> Result<Self> {
         match Self::parse_quantity_helper(i) {
             Ok((_, (number, prefix, unit))) => Ok( Self{ number, prefix, unit } ),
-            Err(e) => { anyhow::bail!("{}", e) }
+            Err(_) => anyhow::bail!("{}", parse_error_message(i)),
         }
     }
 
 
+    // Some abbreviations are both a prefix and a unit, e.g. `m` (milli, meter) and `K` (kilo,
+    // Kelvin). They are disambiguated as follows:
+    //
+    // - The prefix is always tried before the unit, and it is only kept if the rest of the input
+    //   is exactly one unit, e.g. `mK` is milli-Kelvin, `Km` is kilo-meter and `mm` is milli-meter.
+    // - Otherwise the whole input is parsed as a unit without prefix, thus bare `m`, `K` and `s`
+    //   resolve to the unit, as well as the full names starting with a prefix, e.g. `Meter`.
+    //
+    // No spelling is both a prefix followed by a unit and a bare unit, so the result doesn't depend
+    // on the order of the alternatives in `parse_prefix` and `parse_unit`.
     fn parse_quantity_helper(i: &str) -> IResult<&str, (f64, MetricPrefix, Unit)> {
         let pprefix = MetricPrefix::parse_prefix;
         let punit   = Unit::parse_unit;
 
-        let with_prefix = tuple((
-            double,
-            delimited(multispace0, pprefix, multispace0),
-            punit,
+        // `1 eV`, `1 meV` or `1 m eV`, `parse_prefix` yields `One` if there is no prefix
+        let spaced = alt((
+            terminated(tuple((terminated(pprefix, multispace0), punit)), eof),
+            terminated(tuple((success(MetricPrefix::One), punit)), eof),
         ));
-
-        let one = prefix_parser!(MetricPrefix::One, "");
-        let without_prefix = tuple((
-            double,
-            delimited(multispace0, one, multispace0),
-            punit,
+        let explicit = map(
+            tuple((terminated(double, multispace0), spaced)),
+            |(number, (prefix, unit))| (number, prefix, unit)
+        );
+
+        // `keV` means `1 keV`, the whole input must be a valid prefix and unit in this case
+        let compact = alt((
+            terminated(tuple((pprefix, punit)), eof),
+            terminated(tuple((success(MetricPrefix::One), punit)), eof),
         ));
+        let implicit = map(compact, |(prefix, unit)| (1.0, prefix, unit));
+
+        alt((explicit, implicit))(i)
+    }
+
+
+    /// Format the number with `precision` digits after the decimal point, e.g. `1500.000000`,
+    /// or in scientific notation if `scientific` is set, e.g. `1.500000E3`.
+    ///
+    /// The prefix and unit are not included. The decimal point is always `.` regardless of the
+    /// locale, every output format of `rsgrad uc` goes through this function to keep the
+    /// rounding consistent.
+    pub fn format_number(&self, precision: usize, scientific: bool) -> String {
+        if scientific {
+            format!("{:.*E}", precision, self.number)
+        } else {
+            format!("{:.*}", precision, self.number)
+        }
+    }
+
 
-        terminated(alt((
-            with_prefix,
-            without_prefix,
-        )), eof)(i)
+    /// Format the number with `precision` digits after the decimal point, scientific notation is
+    /// used automatically if the magnitude is out of `[1E-3, 1E6)`, e.g. `1.23E7`. Zero and
+    /// non-finite numbers are never in scientific notation.
+    pub fn format_number_auto(&self, precision: usize) -> String {
+        let x = self.number.abs();
+        let scientific = x != 0.0 && x.is_finite() && !(1E-3 .. 1E6).contains(&x);
+        self.format_number(precision, scientific)
     }
 
 
+    /// The same as `to_string()` if `precision` is `None`, otherwise the number is formatted by
+    /// [`Quantity::format_number_auto`] with `precision` digits after the decimal point.
+    pub fn to_string_with_precision(&self, precision: Option<usize>) -> String {
+        match precision {
+            Some(p) => format!("{:>11} {}{}", self.format_number_auto(p), self.prefix, self.unit),
+            None    => self.to_string(),
+        }
+    }
+
+
+    /// Format the quantity without the width padding of `Display`, the number is in its shortest
+    /// representation that round-trips, e.g. `1 eV`, `1.5 meV` or `0.001 K`. This is meant to be
+    /// embedded in other tables or machine-readable output.
+    pub fn to_compact_string(&self) -> String {
+        format!("{} {}{}", self.number, self.prefix, self.unit)
+    }
+
+
+    /// Format the number in scientific notation for LaTeX with `precision` digits after the
+    /// decimal point of the mantissa, e.g. `1.50 \times 10^{3}`. The power is omitted if the
+    /// exponent is zero, and non-finite numbers are formatted as `\infty`, `-\infty` or `NaN`.
+    pub fn format_number_sci_latex(&self, precision: usize) -> String {
+        if self.number.is_nan() {
+            return "NaN".to_string();
+        } else if self.number.is_infinite() {
+            return if self.number > 0.0 { "\\infty".to_string() } else { "-\\infty".to_string() };
+        }
+
+        let sci = self.format_number(precision, true);
+        let (mantissa, exponent) = sci.split_once('E').unwrap_or((&sci, "0"));
+        match exponent {
+            "0" => mantissa.to_string(),
+            _   => format!("{} \\times 10^{{{}}}", mantissa, exponent),
+        }
+    }
+
+
+    /// Convert the quantity to the base unit of its category without prefix, i.e. eV for energy
+    /// and Å⁻¹ for reciprocal space quantities. Any prefix is accepted.
     pub fn normalize(self) -> Self {
-        self.normalize_prefix()
-            .normalize_unit()
+        Conversion::default().normalize(self)
     }
 
+    /// Fold the prefix into the number, the unit is unchanged. Any prefix is accepted.
     pub fn normalize_prefix(mut self) -> Self {
         let scale = get_prefix_scale()[&self.prefix];
         self.number *= scale;
@@ -425,52 +912,101 @@ impl Quantity {
         self
     }
 
-    // the `prefix` must be `One` before calling this function
-    fn normalize_unit(mut self) -> Self {
-        use Unit::*;
 
-        //assert_eq!(self.prefix, MetricPrefix::One);
-        self = self.normalize_prefix();
-        let unit = self.unit;
-        let ratio = get_ratio_ev_to_other()[&unit];
-        self.number = match unit {
-            Meter | Second => ratio / self.number,
-            _ => self.number / ratio,
-        };
-        self.unit = Unit::ElectronVolt;
-        self
+    /// Convert the quantity to `unit` with a proper metric prefix. Reciprocal space quantities
+    /// are always converted without prefix, because prefix on an inverse length is confusing.
+    /// So are Celsius and Fahrenheit temperatures, whose zero points are not zero energy.
+    ///
+    /// Panics if `unit` is not in the same [`UnitCategory`] as `self.unit`.
+    pub fn to_quantity(self, unit: Unit) -> Self {
+        Conversion::default().to_quantity(self, unit)
     }
 
 
-    pub fn to_quantity(self, unit: Unit) -> Self {
-        self.to_normalized_quantity(unit)
-            .add_metrix_prefix()
+    /// Format the quantity converted to `unit`, with the metric prefix chosen by `prefix`.
+    ///
+    /// The result is the same as what `rsgrad uc` prints, e.g. `   1.000000 meV`.
+    ///
+    /// Panics if `unit` is not in the same [`UnitCategory`] as `self.unit`.
+    pub fn display_in(self, unit: Unit, prefix: PrefixMode) -> String {
+        self.to_quantity_with(unit, prefix).to_string()
     }
 
-    // the `prefix` must be `One` before calling this function
-    fn to_normalized_quantity(mut self, unit: Unit) -> Self {
-        use Unit::*;
-        //assert_eq!(self.prefix, MetricPrefix::One);
-        //assert_eq!(self.unit, Unit::ElectronVolt);
-        self = self.normalize();
-
-        self.unit = unit;
-        let ratio = get_ratio_ev_to_other()[&unit];
-        self.number = match unit {
-            Meter | Second => ratio / self.number,
-            _ => self.number * ratio,
-        };
+
+    /// Convert the quantity to `unit`, with the metric prefix chosen by `prefix`.
+    ///
+    /// Panics if `unit` is not in the same [`UnitCategory`] as `self.unit`.
+    pub fn to_quantity_with(self, unit: Unit, prefix: PrefixMode) -> Self {
+        Conversion::default().to_quantity_with(self, unit, prefix)
+    }
+
+
+    /// Convert the quantity to `unit` and round it to the nearest multiple of `step_in_unit`,
+    /// e.g. `0.0257 eV` snapped with step `0.005` in eV gives `0.025 eV`. The result is
+    /// without prefix. The sign of `step_in_unit` is ignored.
+    ///
+    /// Panics if `unit` is not in the same [`UnitCategory`] as `self.unit`.
+    pub fn snap(self, unit: Unit, step_in_unit: f64) -> Result<Self> {
+        Conversion::default().snap(self, unit, step_in_unit)
+    }
+
+
+    /// Whether `self` and `other` represent exactly the same energy.
+    ///
+    /// Both quantities are normalized to eV then compared with exact float equality, thus
+    /// `1 eV` and `1000 meV` are the same energy even though their fields differ.
+    pub fn same_energy(&self, other: &Self) -> bool {
+        self.unit.category() == other.unit.category() &&
+            self.normalize().number == other.normalize().number
+    }
+
+
+    /// Whether `self` and `other` represent the same energy within the relative tolerance
+    /// `rel_tol`, i.e. `|a - b| <= rel_tol * max(|a|, |b|)` after both are normalized to eV.
+    ///
+    /// Unlike [`Quantity::same_energy`] this tolerates the rounding of conversions, e.g.
+    /// `1 eV` is approximately equal to itself converted to K and back. Quantities of different
+    /// categories are never equal.
+    pub fn approx_eq(&self, other: &Self, rel_tol: f64) -> bool {
+        Conversion::default().approx_eq(self, other, rel_tol)
+    }
+
+
+    /// Convert the quantity through `units` step by step, each step starts from the result of
+    /// the previous one. All the intermediate quantities are returned.
+    ///
+    /// Every step is normalized to eV before converting to the next unit, thus the chain is
+    /// lossless up to float precision, i.e. the last quantity represents the same energy as
+    /// `self` no matter how long the chain is.
+    pub fn convert_chain(self, units: &[Unit]) -> Vec<Self> {
+        Conversion::default().convert_chain(self, units)
+    }
+
+    // Convert to `unit` without prefix, `self` can be in any prefix and unit of the same category.
+    fn to_normalized_quantity(self, unit: Unit) -> Self {
+        Conversion::default().to_normalized_quantity(self, unit)
+    }
+
+
+    // Express the number in `prefix`, the `prefix` of `self` must be `One` before calling this
+    // function.
+    fn with_prefix(mut self, prefix: MetricPrefix) -> Self {
+        debug_assert_eq!(self.prefix, MetricPrefix::One);
+        self.number /= get_prefix_scale()[&prefix];
+        self.prefix  = prefix;
         self
     }
 
 
+    // Choose the prefix by magnitude, `self` can be in any prefix.
     fn add_metrix_prefix(mut self) -> Self {
         use MetricPrefix::*;
 
-        //assert_eq!(self.prefix, One);
         self = self.normalize_prefix();
-        let number = self.number;
-        let prefix = match number {
+
+        // The prefix is chosen by magnitude, the sign is kept in `number`
+        let prefix = match self.number.abs() {
+            x if x == 0.0 || !x.is_finite() => One,
             x if x <= 1E-15 => Atto,
             x if x <= 1E-12 => Femto,
             x if x <= 1E-9  => Pico,
@@ -485,13 +1021,185 @@ impl Quantity {
             _ => Exa,
         };
 
-        self.number /= get_prefix_scale()[&prefix];
-        self.prefix  = prefix;
-        self
+        self.with_prefix(prefix)
+    }
+}
+
+
+/// Convert `value` in `from_prefix``from` to `to`, without printing anything.
+///
+/// The returned quantity is in `to` with a metric prefix chosen by magnitude, the same as what
+/// `rsgrad uc` prints, e.g. converting `298 K` to eV gives `25.68 meV`, i.e. `number = 25.68`,
+/// `prefix = Milli`. Quantities in reciprocal space units are returned without prefix. Call
+/// [`Quantity::normalize_prefix`] on the result to get the plain number in `to`.
+///
+/// ```
+/// use rsgrad::commands::uc::{convert, MetricPrefix, Unit};
+///
+/// let q = convert(298.0, Unit::Kelvin, MetricPrefix::One, Unit::ElectronVolt);
+/// assert_eq!(q.prefix, MetricPrefix::Milli);
+/// assert!((q.normalize_prefix().number - 0.02568).abs() < 1E-5);
+/// ```
+///
+/// Panics if `from` and `to` are not in the same [`UnitCategory`].
+pub fn convert(value: f64, from: Unit, from_prefix: MetricPrefix, to: Unit) -> Quantity {
+    Quantity { number: value, prefix: from_prefix, unit: from }.to_quantity(to)
+}
+
+
+// The suggestion closest to `s` among `candidates`, each candidate is a pair of spelling to be
+// compared with and the suggestion. Case is ignored first, the exact distance breaks the tie.
+// Nothing is suggested if even the closest one is too far away.
+fn closest_spelling(s: &str, candidates: impl Iterator<Item = (String, String)>) -> Option<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    let lower = s.to_lowercase();
+
+    let (dist, suggestion) = candidates
+        .map(|(spelling, suggestion)| {
+            ((levenshtein(&lower, &spelling.to_lowercase()), levenshtein(s, &spelling)), suggestion)
+        })
+        .min_by_key(|x| x.0)?;
+
+    if dist.0 <= (s.chars().count() / 3).max(2) {
+        Some(suggestion)
+    } else {
+        None
+    }
+}
+
+
+// Closest unit spelling to `s`, with or without metric prefix. Both symbols and full names are
+// compared, the symbol is suggested.
+fn suggest_unit(s: &str) -> Option<String> {
+    let candidates = get_unit_str().iter()
+        .flat_map(|(unit, sym)| {
+            let mut ret = vec![
+                (sym.to_string(), sym.to_string()),
+                (unit.full_name().to_string(), sym.to_string()),
+            ];
+            if unit.category() == UnitCategory::Energy {
+                ret.extend(get_prefix_str().iter()
+                    .filter(|(p, _)| **p != MetricPrefix::One)
+                    .map(|(_, psym)| (format!("{}{}", psym, sym), format!("{}{}", psym, sym))));
+            }
+            ret
+        });
+    closest_spelling(s, candidates)
+}
+
+
+// Closest metric prefix spelling to `s`, both symbols and full names are compared, the symbol is
+// suggested.
+fn suggest_prefix(s: &str) -> Option<String> {
+    let candidates = get_prefix_str().iter()
+        .filter(|(p, _)| **p != MetricPrefix::One)
+        .flat_map(|(prefix, sym)| [
+            (sym.to_string(), sym.to_string()),
+            (prefix.full_name().to_string(), sym.to_string()),
+        ]);
+    closest_spelling(s, candidates)
+}
+
+
+// Whitespace separated words of `s` with their byte offsets.
+fn words_with_offsets(s: &str) -> Vec<(usize, &str)> {
+    let mut ret = vec![];
+    let mut start = None;
+    for (i, c) in s.char_indices() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(j)) => {
+                ret.push((j, &s[j .. i]));
+                start = None;
+            },
+            _ => (),
+        }
+    }
+    if let Some(j) = start {
+        ret.push((j, &s[j ..]));
+    }
+    ret
+}
+
+
+// Locate the part of `i` failed to parse as quantity, the byte offset and length of it, and the
+// reason are returned.
+fn locate_parse_error(i: &str) -> (usize, usize, String) {
+    if i.trim().is_empty() {
+        return (0, i.len(), "empty input".to_string());
+    }
+
+    let trimmed = i.trim_start();
+    if trimmed.len() != i.len() {
+        return (0, i.len() - trimmed.len(), "unexpected leading whitespace".to_string());
+    }
+
+    let rest = double(i).map(|(rest, _)| rest).unwrap_or(i);
+    let offset = i.len() - rest.len();
+    let words = words_with_offsets(rest);
+    let first = match words.first() {
+        Some(&(start, word)) => (offset + start, word),
+        None => return (i.len(), 0, "missing unit after the number".to_string()),
+    };
+
+    let is_unit = |s: &str| terminated(Unit::parse_unit, eof)(s).is_ok();
+    let is_prefixed_unit = |s: &str| is_unit(s) ||
+        terminated(preceded(MetricPrefix::parse_prefix, Unit::parse_unit), eof)(s).is_ok();
+    let is_prefix = |s: &str| matches!(terminated(MetricPrefix::parse_prefix, eof)(s),
+                                       Ok((_, p)) if p != MetricPrefix::One);
+    let unknown = |(start, word): (usize, &str)| {
+        (start, word.len(), format!("unknown unit `{}`{}", word, did_you_mean(suggest_unit(word))))
+    };
+
+    // `m eV` like spelling with the prefix separated
+    let nvalid = if is_prefix(first.1) && words.len() > 1 {
+        let (start, word) = words[1];
+        if !is_unit(word) {
+            return unknown((offset + start, word));
+        }
+        if offset == 0 {
+            let end = first.0 + first.1.len();
+            return (end, start - end, "unexpected whitespace between the prefix and unit without number".to_string());
+        }
+        2
+    } else if is_prefixed_unit(first.1) {
+        1
+    } else {
+        return unknown(first);
+    };
+
+    match words.get(nvalid) {
+        Some(&(start, _)) => {
+            let start = offset + start;
+            let extra = i[start ..].trim_end();
+            (start, extra.len(), format!("unexpected trailing `{}`", extra))
+        },
+        None => (i.len(), 0, "unexpected trailing whitespace".to_string()),
     }
 }
 
 
+// Human readable message of the failure to parse `i` as quantity, the offending part is marked
+// below the input.
+fn parse_error_message(i: &str) -> String {
+    let (start, len, reason) = locate_parse_error(i);
+    let indent = i[.. start].width();
+    let marker = "^".repeat(i[start .. start + len].width().max(1));
+    format!("Cannot parse {:?} as quantity, {}\n    {}\n    {}{}\n\
+             Expected a number, an optional metric prefix and a unit, e.g. `298K`, `1.5 meV` or `500 nm`.",
+            i, reason, i, " ".repeat(indent), marker)
+}
+
+
+fn did_you_mean(suggestion: Option<String>) -> String {
+    suggestion.map(|x| format!(", did you mean `{}`?", x))
+        .unwrap_or_default()
+}
+
+
 fn double(i: &str) -> IResult<&str, f64> {
     fn integral(i: &str) -> IResult<&str, &str> {
         digit1(i)
@@ -502,12 +1210,17 @@ fn double(i: &str) -> IResult<&str, f64> {
     fn opt_sign(i: &str) -> IResult<&str, &str> {
         map(opt(sign), |x| x.unwrap_or(""))(i)
     }
-    fn fraction(i: &str) -> IResult<&str, &str> {
-        recognize(
-            tuple((
+    // `5`, `5.`, `5.25` or `.25`, at least one digit is required
+    fn mantissa(i: &str) -> IResult<&str, &str> {
+        alt((
+            recognize(tuple((
+                integral,
+                opt(tuple(( tag("."), opt(integral) ))),
+            ))),
+            recognize(tuple((
                 tag("."), integral
-            ))
-        )(i)
+            ))),
+        ))(i)
     }
     fn exponent(i: &str) -> IResult<&str, &str> {
         recognize(
@@ -521,16 +1234,45 @@ fn double(i: &str) -> IResult<&str, f64> {
 
     map(tuple((
         opt_sign,
-        integral,
-        map(opt(fraction), |x| x.unwrap_or("") ),
+        mantissa,
         map(opt(exponent), |x| x.unwrap_or("") ),
-    )), |(a, b, c, d)| {
-        let s = a.to_string() + b + c + d;
+    )), |(a, b, c)| {
+        let s = a.to_string() + b + c;
         s.parse::<f64>().unwrap()
     })(i)
 }
 
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+/// Output format of `rsgrad uc`.
+pub enum OutputFormat {
+    /// Conversion table for human reading.
+    Human,
+
+    /// A single line of whitespace separated `key=value` pairs per input, e.g.
+    /// `input=298K ev=0.0256799 ... cm_1=207.12 ...`.
+    Kv,
+
+    /// A JSON array of objects with `input`, `unit`, `prefix` and `number` fields, one object
+    /// per converted unit.
+    Json,
+
+    /// CSV with a header row `input,unit,prefix,number`, one row per converted unit.
+    Csv,
+}
+
+
+// Units in `--chain` may carry a metric prefix (e.g. `nm`), which is dropped because the prefix
+// of each step is chosen automatically.
+fn parse_chain_unit(s: &str) -> Result<Unit> {
+    let prefixed = terminated(preceded(MetricPrefix::parse_prefix, Unit::parse_unit), eof);
+    match alt((terminated(Unit::parse_unit, eof), prefixed))(s.trim()) {
+        Ok((_, unit)) => Ok(unit),
+        Err(e) => { anyhow::bail!("Cannot parse {:?} as unit: {}{}", s, e, did_you_mean(suggest_unit(s))) }
+    }
+}
+
+
 #[derive(Debug, Args)]
 /// Conversion between various energy units.
 #[command(arg_required_else_help(true),
@@ -538,27 +1280,675 @@ fn double(i: &str) -> IResult<&str, f64> {
 pub struct Uc {
     /// Input energy quantity to be converted. Multiple input are supported.
     pub input: Vec<String>,
+
+    #[arg(long, conflicts_with = "input")]
+    /// Read the input quantities from stdin, one per line, e.g. `cat energies.txt | rsgrad uc
+    /// --stdin --to meV`.
+    ///
+    /// Blank lines are skipped. A line failed to parse is reported with its line number and
+    /// skipped, the rest lines are still processed.
+    pub stdin: bool,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_chain_unit)]
+    /// Convert the input through a chain of units step by step, e.g. `--chain K,eV,cm-1,nm`.
+    ///
+    /// Each step starts from the result of previous step. The prefixes are chosen automatically.
+    pub chain: Vec<Unit>,
+
+    #[arg(long)]
+    /// Boltzmann constant in eV/K used for the conversions involving Kelvin.
+    ///
+    /// Default value is 8.617333E-5 eV/K, specify this to reproduce numbers from other sources.
+    pub kb: Option<f64>,
+
+    #[arg(long, value_enum, ignore_case = true)]
+    /// Category of units listed in the conversion table, the category of input is used by default.
+    ///
+    /// Energy quantities are converted to energy units only, and reciprocal space quantities
+    /// (e.g. `0.5A-1`, `2nm-1`, `1Bohr-1`) are converted to inverse length units only. With this
+    /// flag, only the rows of the given category are listed and the inputs of other categories
+    /// are skipped, e.g. `--category reciprocal` picks the k-space lengths out of `--stdin`.
+    pub category: Option<UnitCategory>,
+
+    #[arg(long, value_enum, ignore_case = true, default_value = "human")]
+    /// Output format.
+    ///
+    /// `kv` prints one `key=value` record per input, which is friendly to grep/awk. The first key
+    /// is `input` with the input string (whitespaces removed), the other keys are the unit
+    /// symbols lowercased with non-alphanumeric characters replaced by `_`, e.g. `cm-1` becomes
+    /// `cm_1` and `J/mol` becomes `j_mol`. The values are in the bare units without prefix.
+    ///
+    /// `json` and `csv` print one record per converted unit with the numbers in full precision,
+    /// the prefix is chosen the same as `human`, or by `--prefix` if specified.
+    pub format: OutputFormat,
+
+    #[arg(long)]
+    /// Round the input to the nearest multiple of this quantity, in the unit of it, e.g.
+    /// `--snap 0.01eV` or `--snap 10meV`.
+    pub snap: Option<Quantity>,
+
+    #[arg(long, conflicts_with = "chain")]
+    /// Convert to this unit only and print a single line instead of the whole table, both full
+    /// name (e.g. `Hertz`) and symbol (e.g. `Hz`) are accepted.
+    pub to: Option<Unit>,
+
+    #[arg(long, requires = "to")]
+    /// Metric prefix of the `--to` unit, e.g. `--to eV --prefix m` prints in meV. The prefix is
+    /// chosen automatically by default.
+    pub prefix: Option<MetricPrefix>,
+
+    #[arg(long)]
+    /// Number of digits after the decimal point in the human readable output, scientific
+    /// notation is used automatically for very large or small numbers. The default is the fixed
+    /// 6 digits without scientific notation.
+    pub precision: Option<usize>,
+
+    #[arg(long, requires = "from", conflicts_with_all = ["to", "chain", "snap"])]
+    /// Treat the input as the desired result and print the quantity in `--from` unit which gives
+    /// it, e.g. `rsgrad uc 500cm-1 --invert --from eV`.
+    ///
+    /// This is the same conversion as `rsgrad uc 500cm-1 --to eV`, only labelled to make the
+    /// direction explicit.
+    pub invert: bool,
+
+    #[arg(long, requires = "invert")]
+    /// The working unit to express the input in, used with `--invert`.
+    pub from: Option<Unit>,
+
+    #[arg(long, hide = true, conflicts_with = "kb")]
+    /// Check the conversions against known values and exit with error if any of them fails.
+    pub selftest: bool,
+
+    #[arg(long, conflicts_with_all = ["chain", "to", "snap", "invert", "selftest"])]
+    /// Convert each input to every unit of its category and back, print the relative residual of
+    /// each round trip, and exit with error if any of them exceeds 1E-10.
+    ///
+    /// This is a sanity check of the conversion ratios, e.g. after new units are added. Note that
+    /// the round trips of tiny energies through Celsius and Fahrenheit lose precision because of
+    /// the offset of their zero points.
+    pub verify: bool,
+
+    #[arg(long, conflicts_with_all = ["input", "stdin", "selftest", "verify"])]
+    /// List all the supported units with their symbols and the value of 1 eV (or 1 Å⁻¹ for
+    /// reciprocal space quantities) in them, and all the metric prefixes with their scales.
+    ///
+    /// The prefixes can be combined with the energy units, e.g. `meV`, `kJ/mol` and `nm`.
+    pub list: bool,
+
+    #[arg(long)]
+    /// Keep the duplicated inputs. By default, an input equal to a previous one is skipped, e.g.
+    /// `1.0 eV` after `1eV`, or `1000meV` after `1eV`.
+    pub no_dedup: bool,
 }
 
 
-impl OptProcess for Uc {
-    fn process(&self) -> Result<()> {
-        for i in self.input.iter() {
-            println!("==================== Processing input \"{}\" ====================", i);
+// Prefix and unit of the quantity, e.g. `μeV`.
+fn unit_label(q: &Quantity) -> String {
+    format!("{}{}", q.prefix, q.unit)
+}
 
-            let q = Quantity::from_str(i)?;
-            for q_unit in get_unit_str().keys().map(|u| q.to_quantity(*u)) {
-                println!(" {} ==  {}", q, q_unit);
-            }
-            
+
+// Pad `s` with trailing spaces to `width` columns. The display width on terminal is used instead
+// of the count of bytes, thus labels with non-ASCII characters like `μ` line up with the others.
+fn pad_to_width(s: &str, width: usize) -> String {
+    let mut ret = s.to_string();
+    ret.extend(std::iter::repeat(' ').take(width.saturating_sub(s.width())));
+    ret
+}
+
+
+// One row of the conversion table, the unit label of `lhs` is padded to `lhs_width` columns to
+// make the `==` of all the rows aligned. The number of `lhs` is formatted the same as
+// `Quantity::to_string_with_precision`.
+fn format_row(lhs: &Quantity, rhs: &str, lhs_width: usize, precision: Option<usize>) -> String {
+    let number = match precision {
+        Some(p) => lhs.format_number_auto(p),
+        None    => lhs.format_number(6, false),
+    };
+    format!(" {:>11} {} ==  {}", number, pad_to_width(&unit_label(lhs), lhs_width), rhs)
+}
+
+
+// Key of `unit` in `--format kv` output.
+fn kv_key(unit: Unit) -> String {
+    get_unit_str()[&unit].chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+
+// Rows of the conversion table of `category`, in the order of `get_unit_str`.
+fn table_units(category: UnitCategory) -> Vec<Unit> {
+    get_unit_str().keys()
+        .copied()
+        .filter(|u| u.category() == category)
+        .collect()
+}
+
+
+// Split the units of conversion table into per particle and per mole groups, each with a
+// subheader. Units without molar counterpart are kept in a single group without subheader.
+fn table_groups(units: &[Unit]) -> Vec<(&'static str, Vec<Unit>)> {
+    let (molar, particle): (Vec<Unit>, Vec<Unit>) = units.iter().partition(|u| u.is_molar());
+    if molar.is_empty() {
+        vec![("", particle)]
+    } else {
+        vec![
+            ("per particle", particle),
+            ("per mole", molar),
+        ]
+    }
+}
+
+
+// The `key=value` record of `--format kv`, conversions to each of `units`.
+fn format_kv(input: &str, q: &Quantity, units: &[Unit], conv: &Conversion) -> String {
+    let input = input.split_whitespace().collect::<String>();
+
+    std::iter::once(format!("input={}", input))
+        .chain(units.iter()
+            .map(|u| format!("{}={}", kv_key(*u), conv.to_normalized_quantity(*q, *u).number)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+
+// Rows of the conversion table configured by `[uc] order` in the settings, each entry is a unit
+// with optional prefix, e.g. `meV`. `None` if not configured.
+fn parse_table_order(order: Option<&[String]>) -> Result<Option<Vec<(MetricPrefix, Unit)>>> {
+    order.map(|order| order.iter()
+        .map(|s| {
+            let q = Quantity::from_str(s)
+                .map_err(|e| anyhow::anyhow!("Invalid unit {:?} in `[uc] order` of settings: {}", s, e))?;
+            Ok((q.prefix, q.unit))
+        })
+        .collect::<Result<Vec<_>>>())
+        .transpose()
+}
+
+
+// Rows of the conversion table of `q` in the configured order, entries of other categories are
+// skipped.
+fn ordered_rows(q: &Quantity, order: &[(MetricPrefix, Unit)], conv: &Conversion) -> Vec<Quantity> {
+    order.iter()
+        .filter(|(_, u)| u.category() == q.unit.category())
+        .map(|(p, u)| conv.to_quantity_with(*q, *u, PrefixMode::Fixed(*p)))
+        .collect()
+}
+
+
+// One line of `--invert` output, `source` is the quantity needed to get `target`.
+fn format_inverted(source: &Quantity, target: &Quantity, precision: Option<usize>) -> String {
+    format!(" needed: {}  ==>  target: {}",
+            source.to_string_with_precision(precision).trim_start(),
+            target.to_string_with_precision(precision).trim_start())
+}
+
+
+// Records of `--format json`, conversions to each of `units`. The numbers are in full precision.
+fn format_json(input: &str, q: &Quantity, units: &[Unit], prefix: PrefixMode, conv: &Conversion) -> Vec<serde_json::Value> {
+    units.iter()
+        .map(|u| conv.to_quantity_with(*q, *u, prefix))
+        .map(|x| serde_json::json!({
+            "input":  input,
+            "unit":   x.unit.to_string(),
+            "prefix": x.prefix.to_string(),
+            "number": x.number,
+        }))
+        .collect()
+}
+
+
+// Rows of `--format csv` without header, conversions to each of `units`. The numbers are in full
+// precision.
+fn format_csv(input: &str, q: &Quantity, units: &[Unit], prefix: PrefixMode, conv: &Conversion) -> Vec<String> {
+    units.iter()
+        .map(|u| conv.to_quantity_with(*q, *u, prefix))
+        .map(|x| format!("{},{},{},{}", input, x.unit, x.prefix, x.number))
+        .collect()
+}
+
+
+// Known conversions checked by `--selftest`: input, target unit, expected number in the target
+// unit without prefix, and relative tolerance. The references are CODATA 2018 values.
+const SELFTEST_CASES: &[(&str, Unit, f64, f64)] = &[
+    ("298K",    Unit::ElectronVolt,     0.025679652,    1E-4),
+    ("1eV",     Unit::Wavenumber,       8065.5440,      1E-4),
+    ("1eV",     Unit::Kelvin,           11604.518,      1E-4),
+    ("1Ha",     Unit::ElectronVolt,     27.211386,      1E-4),
+    ("1Ry",     Unit::ElectronVolt,     13.605693,      1E-4),
+    ("500nm",   Unit::ElectronVolt,     2.4796839,      1E-4),
+    ("1eV",     Unit::Hertz,            2.4179892E14,   1E-4),
+    ("1eV",     Unit::Meter,            1.2398420E-6,   1E-4),
+    ("1eV",     Unit::JoulePerMole,     96485.332,      1E-4),
+    ("1eV",     Unit::CaloriePerMole,   23060.548,      1E-4),
+    ("1eV",     Unit::Joule,            1.602176634E-19, 1E-9),
+    ("1eV",     Unit::Calorie,          3.8292941E-20,  1E-6),
+    ("1fs",     Unit::ElectronVolt,     4.1356677,      1E-4),
+    ("1A-1",    Unit::InverseBohr,      0.52917721,     1E-6),
+    ("1nm-1",   Unit::InverseAngstrom,  0.1,            1E-12),
+];
+
+
+// Parse quantities line by line from `reader`, blank lines are skipped and malformed lines are
+// warned with their line numbers then skipped. The trimmed lines are returned along with the
+// parsed quantities.
+fn read_quantities(reader: impl BufRead) -> Result<Vec<(String, Quantity)>> {
+    let mut ret = vec![];
+    for (iline, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match Quantity::from_str(line) {
+            Ok(q)  => ret.push((line.to_string(), q)),
+            Err(e) => warn!("Skipping line {} {:?}: {}", iline + 1, line, e),
+        }
+    }
+    Ok(ret)
+}
+
+
+// Run the conversions in `SELFTEST_CASES` and the round trips through every unit, print the
+// result of each check. The number of failed checks is returned.
+fn run_selftest() -> usize {
+    let mut nfailed = 0;
+    let mut check = |label: String, got: f64, expected: f64, tol: f64| {
+        let passed = ((got - expected) / expected).abs() <= tol;
+        if !passed {
+            nfailed += 1;
+        }
+        println!(" [{}] {:<40} got {:15.8E}, expected {:15.8E}", if passed { "PASS" } else { "FAIL" }, label, got, expected);
+    };
+
+    for (input, unit, expected, tol) in SELFTEST_CASES.iter() {
+        let got = Quantity::from_str(input)
+            .map(|q| q.to_normalized_quantity(*unit).number)
+            .unwrap_or(f64::NAN);
+        check(format!("{} -> {}", input, unit), got, *expected, *tol);
+    }
+
+    for unit in get_unit_str().keys() {
+        let base = unit.category().base_unit();
+        let q = Quantity { number: 1.5, prefix: MetricPrefix::One, unit: base };
+        let got = q.to_quantity(*unit).to_quantity(base).normalize_prefix().number;
+        check(format!("1.5{} -> {} -> {}", base, unit, base), got, 1.5, 1E-12);
+    }
+
+    nfailed
+}
+
+
+// Rows of `--list`: the units and metric prefixes taken from `get_unit_str` and
+// `get_prefix_scale`, thus they are always in sync with the parser. The units come with the value
+// of 1 base unit of their category in them, the prefixes come with their scales.
+fn unit_list(conv: &Conversion) -> (Vec<[String; 4]>, Vec<[String; 3]>) {
+    let units = get_unit_str().iter()
+        .map(|(unit, sym)| {
+            let category = unit.category();
+            let base = category.base_unit();
+            let one = Quantity { number: 1.0, prefix: MetricPrefix::One, unit: base };
+            let value = conv.to_normalized_quantity(one, *unit).number;
+            [unit.full_name().to_string(), sym.to_string(), format!("{:?}", category), format!("1 {} = {:.9E} {}", base, value, sym)]
+        })
+        .collect();
+
+    let prefixes = get_prefix_scale().iter()
+        .map(|(prefix, scale)| {
+            let sym = if *prefix == MetricPrefix::One { "(none)" } else { prefix.symbol() };
+            [prefix.full_name().to_string(), sym.to_string(), format!("{:E}", scale)]
+        })
+        .collect();
+
+    (units, prefixes)
+}
+
+
+// `--list` in JSON, the numbers are in full precision.
+fn unit_list_json(conv: &Conversion) -> serde_json::Value {
+    let units = get_unit_str().iter()
+        .map(|(unit, sym)| {
+            let base = Quantity { number: 1.0, prefix: MetricPrefix::One, unit: unit.category().base_unit() };
+            serde_json::json!({
+                "name":      unit.full_name(),
+                "symbol":    sym,
+                "category":  format!("{:?}", unit.category()),
+                "base_unit": base.unit.to_string(),
+                "value_of_base_unit": conv.to_normalized_quantity(base, *unit).number,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let prefixes = get_prefix_scale().iter()
+        .map(|(prefix, scale)| serde_json::json!({
+            "name":   prefix.full_name(),
+            "symbol": prefix.symbol(),
+            "scale":  scale,
+        }))
+        .collect::<Vec<_>>();
+
+    serde_json::json!({ "units": units, "prefixes": prefixes })
+}
+
+
+// Align the columns of `rows` under `header` by display width.
+fn format_columns<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> Vec<String> {
+    let mut widths = header.map(|h| h.width());
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(cell.width());
+        }
+    }
+
+    std::iter::once(header.map(|h| h.to_string()))
+        .chain(rows.iter().cloned())
+        .map(|row| {
+            let line = row.iter().zip(widths.iter())
+                .map(|(cell, w)| pad_to_width(cell, *w))
+                .collect::<Vec<_>>()
+                .join("  ");
+            format!(" {}", line.trim_end())
+        })
+        .collect()
+}
+
+
+// Remove the inputs representing the same quantity as a previous one, i.e. the same category and
+// the same normalized number within a relative tolerance of 1E-12. The first one is kept.
+fn dedup_quantities(inputs: Vec<(String, Quantity)>, conv: &Conversion) -> Vec<(String, Quantity)> {
+    let mut ret: Vec<(String, Quantity)> = Vec::with_capacity(inputs.len());
+    for (i, q) in inputs {
+        let duplicated = ret.iter().find(|(_, x)| conv.approx_eq(x, &q, 1E-12));
+        match duplicated {
+            Some((first, _)) => info!("Skipping input {:?}, it is the same as {:?}.", i, first),
+            None => ret.push((i, q)),
+        }
+    }
+    ret
+}
+
+
+// `ratio / number` for the units inversely proportional to energy. Zero and negative numbers have
+// no wavelength or period, NaN is returned for them instead of infinity or a negative wavelength.
+fn inverse_of(ratio: f64, number: f64) -> f64 {
+    if number > 0.0 {
+        ratio / number
+    } else {
+        f64::NAN
+    }
+}
+
+
+// Tolerance of the relative residuals checked by `--verify`.
+const VERIFY_TOLERANCE: f64 = 1E-10;
+
+
+// Round trips of `q` through every unit of its category, i.e. base unit -> unit -> base unit.
+// The quantity converted to each unit and the relative residual of the round trip are returned,
+// the residual is absolute if `q` is zero. Wavelength and period are skipped for non-positive
+// energies, they are undefined.
+fn verify_round_trips(q: &Quantity, conv: &Conversion) -> Vec<(Quantity, f64)> {
+    let expected = conv.normalize(*q).number;
+    let positive = expected > 0.0;
+    get_unit_str().keys()
+        .filter(|u| u.category() == q.unit.category())
+        .filter(|u| positive || !u.is_inverse())
+        .map(|u| {
+            let converted = conv.to_quantity(*q, *u);
+            let got = conv.normalize(converted).number;
+            let residual = if expected == 0.0 {
+                got.abs()
+            } else {
+                ((got - expected) / expected).abs()
+            };
+            (converted, residual)
+        })
+        .collect()
+}
+
+
+impl Uc {
+    // Body of `process` and `process_json`, `format` overrides `--format`.
+    fn run(&self, format: OutputFormat) -> Result<()> {
+        let conv = Conversion::with_boltzmann_constant(self.kb)?;
+
+        if self.list {
+            let (units, prefixes) = unit_list(&conv);
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&unit_list_json(&conv))?);
+            } else {
+                println!("Units:");
+                format_columns(["Name", "Symbol", "Category", "Value of the base unit"], &units)
+                    .iter().for_each(|line| println!("{}", line));
+                println!();
+                println!("Metric prefixes, combined with the energy units, e.g. `meV`:");
+                format_columns(["Name", "Symbol", "Scale"], &prefixes)
+                    .iter().for_each(|line| println!("{}", line));
+            }
+            return Ok(());
+        }
+
+        if self.selftest {
+            let nfailed = run_selftest();
+            let ntotal = SELFTEST_CASES.len() + get_unit_str().len();
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::json!({ "passed": ntotal - nfailed, "total": ntotal }));
+            } else {
+                println!("{} of {} checks passed.", ntotal - nfailed, ntotal);
+            }
+            if nfailed > 0 {
+                anyhow::bail!("{} check(s) of unit conversion failed.", nfailed);
+            }
+            return Ok(());
+        }
+
+        let inputs = if self.stdin {
+            read_quantities(std::io::stdin().lock())?
+        } else {
+            self.input.iter()
+                .map(|i| Ok((i.clone(), Quantity::from_str(i)?)))
+                .collect::<Result<Vec<_>>>()?
+        };
+        if let Some((i, _)) = inputs.iter().find(|(_, q)| q.unit.is_inverse() && !q.normalize().number.is_finite()) {
+            anyhow::bail!("Input {:?} is not a positive wavelength or period, it has no corresponding energy.", i);
+        }
+        let inputs = if self.no_dedup {
+            inputs
+        } else {
+            dedup_quantities(inputs, &conv)
+        };
+        let lhs_width = inputs.iter()
+            .map(|(_, q)| unit_label(q).width())
+            .max()
+            .unwrap_or(0);
+
+        if self.verify {
+            let mut nfailed = 0;
+            let mut json_records = vec![];
+            for (i, q) in inputs.iter() {
+                let base = q.unit.category().base_unit();
+                if format != OutputFormat::Json {
+                    println!("==================== Verifying input \"{}\" ====================", i);
+                }
+                for (x, residual) in verify_round_trips(q, &conv) {
+                    // NaN residuals fail as well
+                    let passed = residual <= VERIFY_TOLERANCE;
+                    if !passed {
+                        nfailed += 1;
+                    }
+                    if format == OutputFormat::Json {
+                        json_records.push(serde_json::json!({
+                            "input":    i,
+                            "unit":     x.unit.to_string(),
+                            "number":   x.normalize_prefix().number,
+                            "residual": residual,
+                            "passed":   passed,
+                        }));
+                    } else {
+                        println!(" [{}] {} -> {} -> {}  residual {:.3E}",
+                                 if passed { "PASS" } else { "FAIL" },
+                                 base, pad_to_width(&x.to_string(), 24), base, residual);
+                    }
+                }
+            }
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&json_records)?);
+            }
+            if nfailed > 0 {
+                anyhow::bail!("{} round trip(s) exceed the relative tolerance {:E}.", nfailed, VERIFY_TOLERANCE);
+            }
+            return Ok(());
+        }
+
+        if format != OutputFormat::Human && !self.chain.is_empty() {
+            anyhow::bail!("`--format {:?}` cannot be used with `--chain`.", format);
+        }
+        if matches!(format, OutputFormat::Json | OutputFormat::Csv) && self.snap.is_some() {
+            anyhow::bail!("`--format {:?}` cannot be used with `--snap`.", format);
+        }
+
+        if let Some(from) = self.from.filter(|_| self.invert) {
+            if format != OutputFormat::Human {
+                anyhow::bail!("`--format {:?}` cannot be used with `--invert`.", format);
+            }
+            for (i, q) in inputs.iter() {
+                if from.category() != q.unit.category() {
+                    anyhow::bail!("Cannot express input {:?} in {:#}, they belong to different categories.", i, from);
+                }
+                println!("{}", format_inverted(&conv.to_quantity(*q, from), q, self.precision));
+            }
+            return Ok(());
+        }
+
+        let table_order = if format == OutputFormat::Human && self.to.is_none() && self.chain.is_empty() {
+            parse_table_order(UcSettings::from_default()?.order.as_deref())?
+        } else {
+            None
+        };
+
+        let prefix = self.prefix.map_or(PrefixMode::Auto, PrefixMode::Fixed);
+        let mut json_records = vec![];
+        if format == OutputFormat::Csv {
+            println!("input,unit,prefix,number");
+        }
+
+        for (i, q) in inputs.iter().map(|(i, q)| (i, *q)) {
+            let category = q.unit.category();
+            let table_category = self.category.unwrap_or(category);
+            if table_category != category {
+                warn!("Skipping input {:?}, it is not in the category {:?}.", i, table_category);
+                continue;
+            }
+            if let Some(u) = self.chain.iter().find(|u| u.category() != category) {
+                anyhow::bail!("Cannot convert input {:?} to {:#}, they belong to different categories.", i, u);
+            }
+            if let Some(u) = self.to.filter(|u| u.category() != category) {
+                anyhow::bail!("Cannot convert input {:?} to {:#}, they belong to different categories.", i, u);
+            }
+            if let Some(step) = self.snap.filter(|s| s.unit.category() != category) {
+                anyhow::bail!("Cannot snap input {:?} to {:#}, they belong to different categories.", i, step.unit);
+            }
+            let snapped = self.snap
+                .map(|step| conv.snap(q, step.unit, step.normalize_prefix().number))
+                .transpose()?;
+
+            let to_inverse = match self.to {
+                Some(u) => u.is_inverse(),
+                None    => self.chain.is_empty() || self.chain.iter().any(|u| u.is_inverse()),
+            };
+            if category == UnitCategory::Energy && to_inverse && q.normalize().number <= 0.0 {
+                warn!("Input {:?} is not a positive energy, its wavelength and period are undefined and shown as NaN.", i);
+            }
+
+            let units = match self.to {
+                Some(u) => vec![u],
+                None    => table_units(table_category),
+            };
+
+            match format {
+                OutputFormat::Json => {
+                    json_records.extend(format_json(i, &q, &units, prefix, &conv));
+                    continue;
+                },
+                OutputFormat::Csv => {
+                    format_csv(i, &q, &units, prefix, &conv).iter().for_each(|row| println!("{}", row));
+                    continue;
+                },
+                _ => (),
+            }
+
+            if format == OutputFormat::Kv {
+                match snapped {
+                    Some(x) => println!("{} snap_{}={}", format_kv(i, &q, &units, &conv), kv_key(x.unit), x.number),
+                    None    => println!("{}", format_kv(i, &q, &units, &conv)),
+                }
+                continue;
+            }
+
+            let fmt = |x: &Quantity| x.to_string_with_precision(self.precision);
+
+            if let Some(to) = self.to {
+                println!("{}", format_row(&q, &fmt(&conv.to_quantity_with(q, to, prefix)), lhs_width, self.precision));
+                if let Some(x) = snapped {
+                    println!("{}", format_row(&q, &format!("{} (snapped)", fmt(&x)), lhs_width, self.precision));
+                }
+                continue;
+            }
+
+            println!("==================== Processing input \"{}\" ====================", i);
+
+            let ordered = table_order.as_ref()
+                .map(|order| ordered_rows(&q, order, &conv))
+                .filter(|rows| !rows.is_empty());
+
+            if let Some(rows) = ordered {
+                for rhs in rows.iter().map(fmt) {
+                    println!("{}", format_row(&q, &rhs, lhs_width, self.precision));
+                }
+            } else if self.chain.is_empty() {
+                for (header, group) in table_groups(&units) {
+                    if !header.is_empty() {
+                        println!(" --- {} ---", header);
+                    }
+                    for rhs in group.iter().map(|u| fmt(&conv.to_quantity(q, *u))) {
+                        println!("{}", format_row(&q, &rhs, lhs_width, self.precision));
+                    }
+                }
+            } else {
+                println!(" {}", fmt(&q));
+                for q_step in conv.convert_chain(q, &self.chain) {
+                    println!(" -> {}", fmt(&q_step));
+                }
+            }
+
+            if let (Some(step), Some(x)) = (self.snap, snapped) {
+                println!(" Snapped to multiples of {}{}{}: {}", step.number, step.prefix, step.unit, fmt(&x));
+            }
+            
             println!("================================================================================");
             println!();
         }
+
+        if format == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&json_records)?);
+        }
         Ok(())
     }
 }
 
 
+impl OptProcess for Uc {
+    fn process(&self) -> Result<()> {
+        self.run(self.format)
+    }
+
+    fn process_json(&self) -> Result<()> {
+        self.run(OutputFormat::Json)
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -576,7 +1966,7 @@ mod tests {
             (Nano,  vec!["nano",  "Nano",  "n"]),
             (Micro, vec!["μ",     "mu",    "Mu", "micro", "Micro", "u"]),
             (Milli, vec!["milli", "Milli", "m"]),
-            (Kilo,  vec!["kilo",  "Kilo",  "K"]),
+            (Kilo,  vec!["kilo",  "Kilo",  "K", "k"]),
             (Mega,  vec!["mega",  "Mega",  "Mi", "M"]),
             (Giga,  vec!["giga",  "Giga",  "Gi", "G"]),
             (Tera,  vec!["tera",  "Tera",  "Ti", "T"]),
@@ -589,6 +1979,43 @@ mod tests {
                 assert_eq!(parser(s), Ok(("", prefix)));
             }
         }
+
+        // No prefix, nothing is consumed
+        assert_eq!(parser(""), Ok(("", One)));
+        assert_eq!(parser("eV"), Ok(("eV", One)));
+        assert_eq!(parser("Ha"), Ok(("Ha", One)));
+        assert_eq!(parser("cm-1"), Ok(("cm-1", One)));
+        assert_eq!(parser(" meV"), Ok((" meV", One)));
+        assert_eq!(parser("meV"), Ok(("eV", Milli)));
+        assert_eq!(MetricPrefix::from_str("").unwrap(), One);
+    }
+
+    #[test]
+    fn test_parse_without_prefix() {
+        use MetricPrefix::*;
+        use Unit::*;
+
+        let cases = vec![
+            ("1.0 eV",  1.0, One,   ElectronVolt),
+            ("1.0eV",   1.0, One,   ElectronVolt),
+            ("1meV",    1.0, Milli, ElectronVolt),
+            ("1m eV",   1.0, Milli, ElectronVolt),
+            ("1 m eV",  1.0, Milli, ElectronVolt),
+            ("1 meV",   1.0, Milli, ElectronVolt),
+            // `m` without unit is the meter rather than a dangling milli
+            ("1m",      1.0, One,   Meter),
+            ("1 m",     1.0, One,   Meter),
+            ("2 Ha",    2.0, One,   Hartree),
+        ];
+
+        for (s, number, prefix, unit) in cases {
+            assert_eq!(Quantity::parse_quantity_helper(s), Ok(("", (number, prefix, unit))), "{}", s);
+        }
+
+        // A prefix without unit, or a prefix and unit with whitespace in the implicit form
+        for s in ["1k", "1 k", "1 m m m", "1 Mi", "k eV", " eV", "1 eV m"] {
+            assert!(Quantity::from_str(s).is_err(), "{:?}", s);
+        }
     }
 
     #[test]
@@ -598,11 +2025,15 @@ mod tests {
         let parser = Unit::parse_unit;
         let cases = vec![
             (ElectronVolt,   vec!["ElectronVolt", "eV"]),
-            (CaloriePerMole, vec!["Calorie/mol", "Cal/mol"]),
-            (JoulePerMole,   vec!["Joule/mol", "J/mol"]),
+            (CaloriePerMole, vec!["Calorie/mol", "CaloriePerMole", "Cal/mol"]),
+            (JoulePerMole,   vec!["Joule/mol", "JoulePerMole", "J/mol"]),
             (Kelvin,         vec!["Kelvin", "K"]),
+            (Celsius,        vec!["Celsius", "degC", "°C"]),
+            (Fahrenheit,     vec!["Fahrenheit", "degF", "°F"]),
             (Hartree,        vec!["Hartree", "Ha"]),
-            (Wavenumber,     vec!["Cm-1", "cm-1"]),
+            (Rydberg,        vec!["Rydberg", "Ry"]),
+            (Wavenumber,     vec!["Cm-1", "Wavenumber", "wavenumber", "Wavenumbers", "wavenumbers",
+                                  "cm-1", "cm^-1", "cm**-1", "1/cm"]),
             (Meter,          vec!["Meter", "m"]),
             (Hertz,          vec!["Hertz", "Hz"]),
             (Second,         vec!["Second", "s"]),
@@ -615,6 +2046,158 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rydberg_and_nanometer() {
+        let ry = Quantity::from_str("1Ry").unwrap();
+        assert!((ry.to_quantity(Unit::ElectronVolt).number - 13.6057).abs() < 1E-9);
+        assert!((ry.to_quantity(Unit::Hartree).normalize_prefix().number - 0.5).abs() < 1E-12);
+
+        let ev = Quantity::from_str("13.6057eV").unwrap();
+        let back = ev.to_quantity(Unit::Rydberg).to_quantity(Unit::ElectronVolt);
+        assert!((back.normalize_prefix().number - 13.6057).abs() < 1E-9);
+
+        let nm = Quantity::from_str("500nm").unwrap();
+        assert_eq!((nm.number, nm.prefix, nm.unit), (500.0, MetricPrefix::Nano, Unit::Meter));
+        let ev = nm.to_quantity(Unit::ElectronVolt);
+        assert!((ev.number - 1239.84193 / 500.0).abs() < 1E-9);
+        let back = ev.to_quantity_with(Unit::Meter, PrefixMode::Fixed(MetricPrefix::Nano));
+        assert!((back.number - 500.0).abs() < 1E-9);
+        assert_eq!(back.to_string().trim(), "500.000000 nm");
+    }
+
+    #[test]
+    fn test_symbol_and_full_name() {
+        assert_eq!(Unit::Wavenumber.symbol(), "cm-1");
+        assert_eq!(Unit::Wavenumber.full_name(), "Wavenumber");
+        assert_eq!(MetricPrefix::Milli.symbol(), "m");
+        assert_eq!(MetricPrefix::Milli.full_name(), "Milli");
+        assert_eq!(MetricPrefix::One.symbol(), "");
+
+        for unit in get_unit_str().keys() {
+            assert_eq!(unit.full_name(), format!("{:?}", unit));
+            assert_eq!(unit.symbol(), unit.to_string());
+        }
+        for prefix in get_prefix_str().keys() {
+            assert_eq!(prefix.full_name(), format!("{:?}", prefix));
+            assert_eq!(prefix.full_name(), format!("{:#}", prefix));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_all_units() {
+        let units = get_unit_str().keys().copied().collect::<Vec<_>>();
+        let prefixes = [MetricPrefix::Milli, MetricPrefix::One, MetricPrefix::Kilo];
+
+        for &from in units.iter() {
+            for &to in units.iter().filter(|u| u.category() == from.category()) {
+                for &prefix in prefixes.iter() {
+                    for number in [1.5, -0.37, 2.0E4] {
+                        let q = Quantity { number, prefix, unit: from };
+                        let back = q.to_quantity(to).to_quantity(from);
+                        assert_eq!(back.unit, from);
+
+                        // Non-positive energies have no wavelength or period
+                        let defined = q.normalize().number > 0.0 || !(from.is_inverse() || to.is_inverse());
+                        if !defined {
+                            assert!(back.number.is_nan(), "{:#} -> {:?} -> {:?}: {}", q, to, from, back.number);
+                            continue;
+                        }
+
+                        let expected = q.normalize_prefix().number;
+                        let got = back.normalize_prefix().number;
+                        assert!(((got - expected) / expected).abs() < 1E-9,
+                                "{:#} -> {:?} -> {:?}: {} != {}", q, to, from, got, expected);
+
+                        let via_base = q.to_quantity_with(to, PrefixMode::None).normalize();
+                        assert!((via_base.number - q.normalize().number).abs() <= 1E-9 * q.normalize().number.abs(),
+                                "{:#} -> {:?}", q, to);
+                    }
+                }
+            }
+        }
+
+        let q = Quantity::from_str("1eV").unwrap();
+        let hz = q.to_quantity(Unit::Hertz);
+        assert!((hz.to_quantity(Unit::ElectronVolt).number - 1.0).abs() < 1E-15);
+        let ns = q.to_quantity_with(Unit::Second, PrefixMode::Fixed(MetricPrefix::Nano));
+        assert!((ns.to_quantity(Unit::ElectronVolt).number - 1.0).abs() < 1E-15);
+
+        let q = Quantity::from_str("-0.37eV").unwrap();
+        assert_eq!(q.to_quantity(Unit::Meter).to_string(), "        NaN m");
+        assert_eq!(q.to_quantity(Unit::Second).to_string(), "        NaN s");
+    }
+
+    #[test]
+    fn test_format_inverted() {
+        let target = Quantity::from_str("500cm-1").unwrap();
+        let source = target.to_quantity(Unit::ElectronVolt);
+        assert_eq!((source.prefix, source.unit), (MetricPrefix::Milli, Unit::ElectronVolt));
+        assert!((source.normalize().number - target.normalize().number).abs() < 1E-15);
+
+        let line = format_inverted(&source, &target, None);
+        assert_eq!(line, " needed: 61.990669 meV  ==>  target: 500.000000 cm-1");
+    }
+
+    #[test]
+    fn test_precision() {
+        let q = |number: f64| Quantity { number, prefix: MetricPrefix::Milli, unit: Unit::ElectronVolt };
+
+        assert_eq!(q(25.6796).to_string_with_precision(None), q(25.6796).to_string());
+        assert_eq!(q(25.6796).to_string_with_precision(Some(2)), "      25.68 meV");
+        assert_eq!(q(1.5E7).to_string_with_precision(Some(3)), "    1.500E7 meV");
+        assert_eq!(q(-2.5E-5).to_string_with_precision(Some(1)), "    -2.5E-5 meV");
+        assert_eq!(q(0.0).to_string_with_precision(Some(1)), "        0.0 meV");
+
+        let row = format_row(&q(25.6796), "x", 3, Some(1));
+        assert_eq!(row, "        25.7 meV ==  x");
+    }
+
+    #[test]
+    fn test_table_order() {
+        let order = ["eV", "meV", "cm-1", "nm", "THz", "A-1"].iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let order = parse_table_order(Some(&order)).unwrap().unwrap();
+        assert_eq!(order[1], (MetricPrefix::Milli, Unit::ElectronVolt));
+        assert_eq!(order[3], (MetricPrefix::Nano, Unit::Meter));
+
+        let rows = ordered_rows(&Quantity::from_str("1eV").unwrap(), &order, &Conversion::default());
+        assert_eq!(rows.iter().map(unit_label).collect::<Vec<_>>(), vec!["eV", "meV", "cm-1", "nm", "THz"]);
+        assert!((rows[1].number - 1000.0).abs() < 1E-9);
+
+        let rows = ordered_rows(&Quantity::from_str("2nm-1").unwrap(), &order, &Conversion::default());
+        assert_eq!(rows.iter().map(unit_label).collect::<Vec<_>>(), vec!["A-1"]);
+
+        assert!(parse_table_order(None).unwrap().is_none());
+        assert!(parse_table_order(Some(&["eV".to_string(), "elctronvolt".to_string()])).is_err());
+    }
+
+    #[test]
+    fn test_parse_double() {
+        let cases = [
+            (".5",    0.5),
+            ("-.5",   -0.5),
+            ("+.5e3", 500.0),
+            (".5e2",  50.0),
+            ("5.",    5.0),
+            ("5.25",  5.25),
+            ("-5",    -5.0),
+            ("5E-1",  0.5),
+        ];
+        for (s, x) in cases {
+            assert_eq!(double(s), Ok(("", x)), "{}", s);
+        }
+
+        assert_eq!(double("5.eV"), Ok(("eV", 5.0)));
+        assert!(double("").is_err());
+        assert!(double(".").is_err());
+        assert!(double("-.e3").is_err());
+        assert!(double("eV").is_err());
+
+        let q = Quantity::from_str(".5eV").unwrap();
+        assert_eq!((q.number, q.prefix, q.unit), (0.5, MetricPrefix::One, Unit::ElectronVolt));
+        let q = Quantity::from_str("-.25Ha").unwrap();
+        assert_eq!((q.number, q.prefix, q.unit), (-0.25, MetricPrefix::One, Unit::Hartree));
+    }
+
     #[test]
     fn test_parse_quantity() {
         use MetricPrefix::*;
@@ -694,4 +2277,659 @@ mod tests {
         }
 
     }
+
+    #[test]
+    fn test_parse_implicit_number() {
+        use MetricPrefix::*;
+        use Unit::*;
+
+        let cases = vec![
+            ("keV",  Kilo,  ElectronVolt),
+            ("KeV",  Kilo,  ElectronVolt),
+            ("meV",  Milli, ElectronVolt),
+            ("nm",   Nano,  Meter),
+            ("eV",   One,   ElectronVolt),
+            ("m",    One,   Meter),
+            ("K",    One,   Kelvin),
+            ("cm-1", One,   Wavenumber),
+        ];
+
+        for (s, prefix, unit) in cases {
+            let q = Quantity::from_str(s).unwrap();
+            assert_eq!((q.number, q.prefix, q.unit), (1.0, prefix, unit), "{}", s);
+        }
+
+        for s in ["", "k", "keVx", "e", "-eV", " eV", "eV 1"] {
+            assert!(Quantity::from_str(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_prefix_unit_disambiguation() {
+        use MetricPrefix::*;
+        use Unit::*;
+
+        let cases = vec![
+            ("mK",           Milli, Kelvin),
+            ("Km",           Kilo,  Meter),
+            ("km",           Kilo,  Meter),
+            ("mm",           Milli, Meter),
+            ("Ms",           Mega,  Second),
+            ("ms",           Milli, Second),
+            ("ps",           Pico,  Second),
+            ("Ps",           Peta,  Second),
+            ("KK",           Kilo,  Kelvin),
+            ("MHz",          Mega,  Hertz),
+            ("mHz",          Milli, Hertz),
+            ("kJ/mol",       Kilo,  JoulePerMole),
+            ("kCal/mol",     Kilo,  CaloriePerMole),
+            ("EeV",          Exa,   ElectronVolt),
+            ("mum",          Micro, Meter),
+            ("m",            One,   Meter),
+            ("K",            One,   Kelvin),
+            ("s",            One,   Second),
+            ("Kelvin",       One,   Kelvin),
+            ("Meter",        One,   Meter),
+            ("ElectronVolt", One,   ElectronVolt),
+            ("nm-1",         One,   InverseNanometer),
+        ];
+
+        for (s, prefix, unit) in cases {
+            for input in [s.to_string(), format!("2 {}", s), format!("2{}", s)] {
+                let q = Quantity::from_str(&input).unwrap();
+                let number = if input == s { 1.0 } else { 2.0 };
+                assert_eq!((q.number, q.prefix, q.unit), (number, prefix, unit), "{}", input);
+            }
+        }
+
+        for s in ["mKm", "Kmm", "mmm", "sm", "Mss"] {
+            assert!(Quantity::from_str(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_spelling_suggestion() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "eV"), 2);
+        assert_eq!(levenshtein("μeV", "ueV"), 1);
+
+        let err = Unit::from_str("elctronvolt").unwrap_err().to_string();
+        assert!(err.contains("did you mean `eV`?"), "{}", err);
+
+        let err = Quantity::from_str("1 elctronvolt").unwrap_err().to_string();
+        assert!(err.contains("did you mean `eV`?"), "{}", err);
+
+        let err = Quantity::from_str("2.5 mev").unwrap_err().to_string();
+        assert!(err.contains("did you mean `meV`?"), "{}", err);
+
+        let err = Quantity::from_str("300 Hrtree").unwrap_err().to_string();
+        assert!(err.contains("did you mean `Ha`?"), "{}", err);
+
+        let err = MetricPrefix::from_str("kilp").unwrap_err().to_string();
+        assert!(err.contains("did you mean `K`?"), "{}", err);
+        assert_eq!(MetricPrefix::from_str("milli").unwrap(), MetricPrefix::Milli);
+
+        let err = Quantity::from_str("1 xyzzyqwerty").unwrap_err().to_string();
+        assert!(!err.contains("did you mean"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_error_message() {
+        let cases = [
+            ("298 Kelvns",  4,  6, "unknown unit `Kelvns`"),
+            ("298Kelvns",   3,  6, "unknown unit `Kelvns`"),
+            ("1.5 m Hrtree", 6, 6, "unknown unit `Hrtree`"),
+            ("1.2.3eV",     3,  4, "unknown unit `.3eV`"),
+            ("xyz",         0,  3, "unknown unit `xyz`"),
+            ("1 eV m",      5,  1, "unexpected trailing `m`"),
+            ("1 m eV 2 K",  7,  3, "unexpected trailing `2 K`"),
+            ("300",         3,  0, "missing unit after the number"),
+            (" eV",         0,  1, "unexpected leading whitespace"),
+            ("k  eV",       1,  2, "unexpected whitespace between the prefix and unit"),
+            ("",            0,  0, "empty input"),
+        ];
+
+        for (input, start, len, reason) in cases {
+            let (s, l, r) = locate_parse_error(input);
+            assert_eq!((s, l), (start, len), "{:?}", input);
+            assert!(r.starts_with(reason), "{:?}: {}", input, r);
+            assert!(Quantity::from_str(input).unwrap_err().to_string().contains(reason), "{:?}", input);
+        }
+
+        let err = Quantity::from_str("298 Kelvns").unwrap_err().to_string();
+        assert!(err.contains("did you mean `K`?"), "{}", err);
+        assert!(err.contains("\n    298 Kelvns\n        ^^^^^^\n"), "{}", err);
+        assert!(err.contains("Expected a number, an optional metric prefix and a unit"), "{}", err);
+
+        // The marker is aligned by display width
+        let err = Quantity::from_str("1 μ eVx").unwrap_err().to_string();
+        assert!(err.contains("\n    1 μ eVx\n        ^^^\n"), "{}", err);
+    }
+
+    #[test]
+    fn test_joule_and_calorie() {
+        use MetricPrefix::*;
+        use Unit::*;
+
+        let cases = vec![
+            ("1J",          One,   Joule),
+            ("2 Joule",     One,   Joule),
+            ("1kJ",         Kilo,  Joule),
+            ("1 cal",       One,   Calorie),
+            ("1kcal",       Kilo,  Calorie),
+            ("1 Calorie",   One,   Calorie),
+            ("1 J/mol",     One,   JoulePerMole),
+            ("1 kJ/mol",    Kilo,  JoulePerMole),
+            ("1 Joule/mol", One,   JoulePerMole),
+            ("1 Cal/mol",   One,   CaloriePerMole),
+        ];
+        for (s, prefix, unit) in cases {
+            let q = Quantity::from_str(s).unwrap();
+            assert_eq!((q.prefix, q.unit), (prefix, unit), "{}", s);
+        }
+        assert!(!Joule.is_molar());
+        assert!(!Calorie.is_molar());
+
+        let q = Quantity::from_str("1eV").unwrap();
+        let joule = q.to_normalized_quantity(Joule);
+        assert!((joule.number / 1.602176634E-19 - 1.0).abs() < 1E-12);
+        assert!((joule.normalize().number - 1.0).abs() < 1E-12);
+
+        let calorie = q.to_normalized_quantity(Calorie);
+        assert!((calorie.number * 4.184 / 1.602176634E-19 - 1.0).abs() < 1E-12);
+        assert!((calorie.normalize().number - 1.0).abs() < 1E-12);
+
+        // 1 J/mol per particle is 1 J divided by the Avogadro constant
+        let q = Quantity::from_str("1 J/mol").unwrap();
+        assert!((q.to_normalized_quantity(Joule).number * 6.02214076E23 - 1.0).abs() < 1E-3);
+    }
+
+    #[test]
+    fn test_dedup_quantities() {
+        let inputs = ["1eV", "1.0 eV", "298K", "1000 meV", "2eV", "298.0K", "1A-1", "1nm-1", "10 nm-1"]
+            .iter()
+            .map(|s| (s.to_string(), Quantity::from_str(s).unwrap()))
+            .collect::<Vec<_>>();
+
+        let kept = dedup_quantities(inputs.clone(), &Conversion::default())
+            .into_iter()
+            .map(|(s, _)| s)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec!["1eV", "298K", "2eV", "1A-1", "1nm-1"]);
+
+        // Different categories are never the same
+        let inputs = vec![
+            ("1eV".to_string(),  Quantity::from_str("1eV").unwrap()),
+            ("1A-1".to_string(), Quantity::from_str("1A-1").unwrap()),
+        ];
+        assert_eq!(dedup_quantities(inputs, &Conversion::default()).len(), 2);
+    }
+
+    #[test]
+    fn test_unit_list() {
+        let (units, prefixes) = unit_list(&Conversion::default());
+        assert_eq!(units.len(), get_unit_str().len());
+        assert_eq!(prefixes.len(), get_prefix_scale().len());
+
+        // Every listed spelling is accepted by the parser
+        for [name, symbol, _, _] in units.iter() {
+            assert_eq!(Unit::from_str(name).unwrap(), Unit::from_str(symbol).unwrap(), "{}", name);
+        }
+        for [name, symbol, _] in prefixes.iter().filter(|[name, _, _]| name != "One") {
+            assert_eq!(MetricPrefix::from_str(name).unwrap(), MetricPrefix::from_str(symbol).unwrap(), "{}", name);
+        }
+
+        assert_eq!(unit_list_json(&Conversion::default())["units"].as_array().unwrap().len(), units.len());
+
+        let ev = units.iter().find(|row| row[1] == "eV").unwrap();
+        assert_eq!(ev[3], "1 eV = 1.000000000E0 eV");
+        let kelvin = units.iter().find(|row| row[1] == "K").unwrap();
+        assert!(kelvin[3].starts_with("1 eV = 1.16045"), "{}", kelvin[3]);
+        assert!(prefixes.contains(&["Kilo".to_string(), "K".to_string(), "1E3".to_string()]));
+
+        // Columns are aligned by display width, `μ` included
+        let lines = format_columns(["Name", "Symbol", "Scale"], &prefixes);
+        assert_eq!(lines.len(), prefixes.len() + 1);
+        let column = lines[0].find("Scale").unwrap();
+        for line in lines.iter().skip(1) {
+            let prefix = line.chars().take_while(|c| !c.is_ascii_digit()).collect::<String>();
+            assert_eq!(prefix.width(), column, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_convert_chain() {
+        use Unit::*;
+
+        let q = Quantity::from_str("298K").unwrap();
+        let chain = q.convert_chain(&[Kelvin, ElectronVolt, Wavenumber, Meter, Hertz, Kelvin]);
+        assert_eq!(chain.len(), 6);
+        assert_eq!(chain[1].unit, ElectronVolt);
+        assert_eq!(chain[3].unit, Meter);
+
+        let last = chain.last().unwrap().normalize_prefix();
+        assert_eq!(last.unit, Kelvin);
+        assert!((last.number - 298.0).abs() < 1E-9, "{}", last);
+
+        assert!(q.convert_chain(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_chain_unit() {
+        assert_eq!(parse_chain_unit("eV").unwrap(), Unit::ElectronVolt);
+        assert_eq!(parse_chain_unit("m").unwrap(), Unit::Meter);
+        assert_eq!(parse_chain_unit("nm").unwrap(), Unit::Meter);
+        assert_eq!(parse_chain_unit("cm-1").unwrap(), Unit::Wavenumber);
+        assert!(parse_chain_unit("nmx").is_err());
+    }
+
+    #[test]
+    fn test_default_boltzmann_constant() {
+        let kb = Conversion::default().boltzmann_constant();
+        assert!(((kb - 8.617333E-5) / 8.617333E-5).abs() < 1E-7, "{} != 8.617333E-5", kb);
+        assert_eq!(Conversion::with_boltzmann_constant(None).unwrap(), Conversion::default());
+
+        assert!(Conversion::with_boltzmann_constant(Some(0.0)).is_err());
+        assert!(Conversion::with_boltzmann_constant(Some(-8.617333E-5)).is_err());
+        assert!(Conversion::with_boltzmann_constant(Some(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_custom_boltzmann_constant() {
+        let conv = Conversion::with_boltzmann_constant(Some(8.6E-5)).unwrap();
+        let q = Quantity::from_str("1eV").unwrap();
+
+        let t = conv.to_quantity_with(q, Unit::Kelvin, PrefixMode::None);
+        assert!((t.number - 1.0 / 8.6E-5).abs() < 1E-6, "{}", t.number);
+        assert!((q.to_quantity_with(Unit::Kelvin, PrefixMode::None).number - 1.0 / 8.617333E-5).abs() < 1E-2);
+
+        let back = conv.normalize(Quantity::from_str("1000K").unwrap());
+        assert!((back.number - 8.6E-2).abs() < 1E-12, "{}", back.number);
+        let c = conv.to_quantity(Quantity::from_str("273.15degC").unwrap(), Unit::ElectronVolt);
+        assert!(c.normalize_prefix().number.abs() < 1E-12);
+
+        // Units without temperature are not affected
+        let cm = conv.to_quantity_with(q, Unit::Wavenumber, PrefixMode::None).number;
+        assert_eq!(cm, q.to_quantity_with(Unit::Wavenumber, PrefixMode::None).number);
+    }
+
+    #[test]
+    fn test_same_energy() {
+        let cases = vec![
+            ("1eV",     "1000meV",    true),
+            ("1KeV",    "1000eV",     true),
+            ("2.5eV",   "2500 meV",   true),
+            ("1 Kelvin", "1K",        true),
+            ("1KJ/mol", "1000J/mol",  true),
+            ("1eV",     "1.0001eV",   false),
+            ("1eV",     "1meV",       false),
+            ("1Hz",     "1s",         false),
+        ];
+
+        for (a, b, expected) in cases {
+            let qa = Quantity::from_str(a).unwrap();
+            let qb = Quantity::from_str(b).unwrap();
+            assert_eq!(qa.same_energy(&qb), expected, "{} vs {}", a, b);
+            assert_eq!(qb.same_energy(&qa), expected, "{} vs {}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let q = |s: &str| Quantity::from_str(s).unwrap();
+
+        let same = [q("1 eV"), q("1000 meV"), q("1e-3 keV")];
+        for a in same.iter() {
+            for b in same.iter() {
+                assert!(a.approx_eq(b, 1E-12), "{} vs {}", a, b);
+            }
+        }
+
+        let ev = q("1eV");
+        let round_trip = ev.to_quantity(Unit::Kelvin).to_quantity(Unit::ElectronVolt);
+        assert!(ev.approx_eq(&round_trip, 1E-12));
+        assert!(ev.approx_eq(&q("1.0001eV"), 1E-3));
+        assert!(!ev.approx_eq(&q("1.0001eV"), 1E-6));
+        assert!(!ev.approx_eq(&q("-1eV"), 1E-6));
+        assert!(q("0eV").approx_eq(&q("0 meV"), 0.0));
+        assert!(!q("1Hz").approx_eq(&q("1s"), 1E-6));
+    }
+
+    #[test]
+    fn test_format_row_alignment() {
+        let lhs = ["1μeV", "1meV", "1K", "1KJ/mol", "1 micro Hz"].iter()
+            .map(|s| Quantity::from_str(s).unwrap())
+            .collect::<Vec<_>>();
+        let lhs_width = lhs.iter().map(|q| unit_label(q).width()).max().unwrap();
+        let rhs = Quantity::from_str("1eV").unwrap().to_string();
+
+        let widths = lhs.iter()
+            .map(|q| format_row(q, &rhs, lhs_width, None))
+            .map(|row| row[.. row.find("==").unwrap()].width())
+            .collect::<Vec<_>>();
+        assert!(widths.iter().all(|w| *w == widths[0]), "{:?}", widths);
+
+        assert_eq!(pad_to_width("μeV", 5), "μeV  ");
+        assert_eq!(pad_to_width("meV", 5), "meV  ");
+        assert_eq!(pad_to_width("KJ/mol", 3), "KJ/mol");
+    }
+
+    #[test]
+    fn test_read_quantities() {
+        let txt = "298K\n\n  1 eV  \n1 elctronvolt\n2meV\n";
+        let parsed = read_quantities(std::io::Cursor::new(txt)).unwrap();
+
+        assert_eq!(parsed.iter().map(|(s, _)| s.as_str()).collect::<Vec<_>>(), vec!["298K", "1 eV", "2meV"]);
+        assert_eq!((parsed[2].1.number, parsed[2].1.prefix, parsed[2].1.unit), (2.0, MetricPrefix::Milli, Unit::ElectronVolt));
+    }
+
+    #[test]
+    fn test_format_number() {
+        let q = |number: f64| Quantity { number, prefix: MetricPrefix::One, unit: Unit::ElectronVolt };
+
+        assert_eq!(q(1500.0).format_number(6, false), "1500.000000");
+        assert_eq!(q(-0.0256799).format_number(3, false), "-0.026");
+        assert_eq!(q(2.4).format_number(0, false), "2");
+        assert_eq!(q(1500.0).format_number(2, true), "1.50E3");
+        assert_eq!(q(-2.5E-5).format_number(1, true), "-2.5E-5");
+
+        assert_eq!(q(1500.0).format_number_sci_latex(2), "1.50 \\times 10^{3}");
+        assert_eq!(q(-2.5E-5).format_number_sci_latex(1), "-2.5 \\times 10^{-5}");
+        assert_eq!(q(2.5).format_number_sci_latex(1), "2.5");
+        assert_eq!(q(f64::INFINITY).format_number_sci_latex(1), "\\infty");
+
+        assert_eq!(q(1.5).to_string(), "   1.500000 eV");
+        assert_eq!(format!("{:#}", q(1.5)), "   1.500000 One ElectronVolt");
+
+        assert_eq!(q(1.0).to_compact_string(), "1 eV");
+        assert_eq!(q(-2.5E-5).to_compact_string(), "-0.000025 eV");
+        assert_eq!(Quantity::from_str("1.5 meV").unwrap().to_compact_string(), "1.5 meV");
+        let compact = Quantity::from_str("1500.25cm-1").unwrap().to_compact_string();
+        assert_eq!(Quantity::from_str(&compact).unwrap().number, 1500.25);
+    }
+
+    #[test]
+    fn test_format_json_and_csv() {
+        let q = Quantity::from_str("1.5eV").unwrap();
+        let units = [Unit::ElectronVolt, Unit::Kelvin];
+
+        let records = format_json("1.5eV", &q, &units, PrefixMode::Auto, &Conversion::default());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], serde_json::json!({"input": "1.5eV", "unit": "eV", "prefix": "", "number": 1.5}));
+        assert_eq!(records[1]["prefix"], "K");
+        assert_eq!(records[1]["number"].as_f64().unwrap(), q.to_quantity(Unit::Kelvin).number);
+
+        let rows = format_csv("1.5eV", &q, &units, PrefixMode::Fixed(MetricPrefix::Milli), &Conversion::default());
+        assert_eq!(rows[0], "1.5eV,eV,m,1500");
+        let fields = rows[1].split(',').collect::<Vec<_>>();
+        assert_eq!(&fields[.. 3], &["1.5eV", "K", "m"]);
+        assert_eq!(fields[3].parse::<f64>().unwrap(), q.to_quantity_with(Unit::Kelvin, PrefixMode::Fixed(MetricPrefix::Milli)).number);
+    }
+
+    #[test]
+    fn test_verify_round_trips() {
+        for s in ["1eV", "298K", "-3meV", "25degC", "500nm", "1fs", "0eV", "2nm-1", "1E-3Ha"] {
+            let q = Quantity::from_str(s).unwrap();
+            let rows = verify_round_trips(&q, &Conversion::default());
+            let nunits = get_unit_str().keys()
+                .filter(|u| u.category() == q.unit.category())
+                .filter(|u| q.normalize().number > 0.0 || !u.is_inverse())
+                .count();
+            assert_eq!(rows.len(), nunits, "{}", s);
+            for (x, residual) in rows {
+                assert!(residual <= VERIFY_TOLERANCE, "{} -> {}: {}", s, x.unit, residual);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inverse_units_of_non_positive_energies() {
+        for s in ["0eV", "-1eV", "-3meV", "-300degC"] {
+            let q = Quantity::from_str(s).unwrap();
+            for unit in [Unit::Meter, Unit::Second] {
+                let x = q.to_quantity(unit);
+                assert!(x.number.is_nan(), "{} -> {}", s, x);
+                assert_eq!(x.prefix, MetricPrefix::One);
+            }
+            assert!(q.to_quantity(Unit::Hertz).number <= 0.0);
+        }
+
+        for s in ["0m", "-500nm", "-1fs"] {
+            assert!(Quantity::from_str(s).unwrap().normalize().number.is_nan(), "{}", s);
+        }
+
+        assert!(Unit::Meter.is_inverse() && Unit::Second.is_inverse());
+        assert!(!Unit::Hertz.is_inverse() && !Unit::Wavenumber.is_inverse());
+    }
+
+    #[test]
+    fn test_selftest() {
+        assert_eq!(run_selftest(), 0);
+    }
+
+    #[test]
+    fn test_snap() {
+        let q = Quantity::from_str("0.0257eV").unwrap();
+        let snapped = q.snap(Unit::ElectronVolt, 0.005).unwrap();
+        assert_eq!((snapped.prefix, snapped.unit), (MetricPrefix::One, Unit::ElectronVolt));
+        assert!((snapped.number - 0.025).abs() < 1E-12, "{}", snapped);
+
+        let snapped = Quantity::from_str("25.7meV").unwrap().snap(Unit::ElectronVolt, -0.005).unwrap();
+        assert!((snapped.number - 0.025).abs() < 1E-12, "{}", snapped);
+
+        let snapped = Quantity::from_str("-0.0257eV").unwrap().snap(Unit::ElectronVolt, 0.005).unwrap();
+        assert!((snapped.number + 0.025).abs() < 1E-12, "{}", snapped);
+
+        let snapped = Quantity::from_str("298K").unwrap().snap(Unit::Kelvin, 50.0).unwrap();
+        assert!((snapped.number - 300.0).abs() < 1E-9, "{}", snapped);
+
+        assert!(q.snap(Unit::ElectronVolt, 0.0).is_err());
+        assert!(q.snap(Unit::ElectronVolt, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_format_kv() {
+        assert_eq!(kv_key(Unit::Wavenumber), "cm_1");
+        assert_eq!(kv_key(Unit::JoulePerMole), "j_mol");
+        assert_eq!(kv_key(Unit::ElectronVolt), "ev");
+
+        let q = Quantity::from_str("298 K").unwrap();
+        let units = get_unit_str().keys().copied().filter(|u| u.category() == UnitCategory::Energy).collect::<Vec<_>>();
+        let record = format_kv("298 K", &q, &units, &Conversion::default());
+        assert!(!record.contains('\n'));
+
+        let pairs = record.split_whitespace()
+            .map(|kv| kv.split_once('=').unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(pairs[0], ("input", "298K"));
+        assert_eq!(pairs.len(), 1 + get_unit_str().keys().filter(|u| u.category() == UnitCategory::Energy).count());
+
+        let value = |key: &str| pairs.iter().find(|(k, _)| *k == key).unwrap().1.parse::<f64>().unwrap();
+        assert!((value("ev") - 298.0 / 1.160451812E4).abs() < 1E-12);
+        assert!((value("k") - 298.0).abs() < 1E-9);
+        assert!((value("cm_1") - 298.0 / 1.160451812E4 * 8065.73).abs() < 1E-9);
+        assert!(pairs.iter().all(|(k, _)| k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')));
+
+        assert_eq!(format_kv("1eV", &Quantity::from_str("1eV").unwrap(), &[Unit::ElectronVolt], &Conversion::default()), "input=1eV ev=1");
+    }
+
+    #[test]
+    fn test_reciprocal_units() {
+        use Unit::*;
+
+        let cases = vec![
+            (InverseAngstrom,  vec!["InverseAngstrom",  "A-1", "Å-1", "1/A", "1/Å"]),
+            (InverseNanometer, vec!["InverseNanometer", "nm-1", "1/nm"]),
+            (InverseBohr,      vec!["InverseBohr",      "Bohr-1", "1/Bohr"]),
+        ];
+        for (unit, ss) in cases {
+            assert_eq!(unit.category(), UnitCategory::Reciprocal);
+            for s in ss {
+                assert_eq!(Unit::parse_unit(s), Ok(("", unit)));
+                let q = Quantity::from_str(&format!("0.5 {}", s)).unwrap();
+                assert_eq!((q.number, q.prefix, q.unit), (0.5, MetricPrefix::One, unit), "{}", s);
+            }
+        }
+
+        assert_eq!(Quantity::from_str("2nm-1").unwrap().unit, InverseNanometer);
+
+        let q = Quantity::from_str("1A-1").unwrap();
+        let q_nm = q.to_quantity(InverseNanometer);
+        assert_eq!(q_nm.prefix, MetricPrefix::One);
+        assert!((q_nm.number - 10.0).abs() < 1E-12);
+
+        let q_bohr = q.to_quantity(InverseBohr);
+        assert_eq!(q_bohr.prefix, MetricPrefix::One);
+        assert!((q_bohr.number - 0.529177210903).abs() < 1E-12);
+        assert!((q_bohr.to_quantity(InverseAngstrom).number - 1.0).abs() < 1E-12);
+
+        assert!(get_unit_str().keys().filter(|u| u.category() == UnitCategory::Energy)
+                .all(|u| !matches!(u, InverseAngstrom | InverseNanometer | InverseBohr)));
+    }
+
+    #[test]
+    fn test_negative_and_zero_prefix() {
+        use MetricPrefix::*;
+        use Unit::*;
+
+        let q = Quantity::from_str("-2.5eV").unwrap();
+        let cases = vec![
+            (ElectronVolt, One,  -2.5),
+            (Kelvin,       Kilo, -2.5 * 1.160451812E4 / 1E3),
+            (Hertz,        Tera, -2.5 * 2.417989242E14 / 1E12),
+            (Hartree,      Milli, -2.5 / 27.2114 * 1E3),
+        ];
+        for (unit, prefix, expected) in cases {
+            let converted = q.to_quantity(unit);
+            assert_eq!(converted.prefix, prefix, "{}", converted);
+            assert!(((converted.number - expected) / expected).abs() < 1E-12, "{}", converted);
+        }
+
+        let q = Quantity::from_str("-2500meV").unwrap().to_quantity(ElectronVolt);
+        assert_eq!(q.prefix, One);
+        assert!((q.number + 2.5).abs() < 1E-12);
+
+        for unit in [ElectronVolt, Kelvin, Hertz, Wavenumber] {
+            let zero = Quantity::from_str("0eV").unwrap().to_quantity(unit);
+            assert_eq!((zero.prefix, zero.number), (One, 0.0), "{}", zero);
+        }
+    }
+
+    #[test]
+    fn test_table_units() {
+        use Unit::*;
+        assert_eq!(table_units(UnitCategory::Reciprocal), vec![InverseAngstrom, InverseNanometer, InverseBohr]);
+
+        let energy = table_units(UnitCategory::Energy);
+        assert!(energy.contains(&ElectronVolt) && energy.contains(&Kelvin));
+        assert!(energy.iter().all(|u| u.category() == UnitCategory::Energy));
+        assert_eq!(energy.len() + 3, get_unit_str().len());
+    }
+
+    #[test]
+    fn test_table_groups() {
+        let energy = get_unit_str().keys().copied().filter(|u| u.category() == UnitCategory::Energy).collect::<Vec<_>>();
+        let groups = table_groups(&energy);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].0, "per particle");
+        assert!(groups[0].1.iter().all(|u| !u.is_molar()));
+        assert!(groups[0].1.contains(&Unit::ElectronVolt));
+        assert_eq!(groups[1], ("per mole", vec![Unit::CaloriePerMole, Unit::JoulePerMole]));
+
+        let reciprocal = [Unit::InverseAngstrom, Unit::InverseNanometer];
+        assert_eq!(table_groups(&reciprocal), vec![("", reciprocal.to_vec())]);
+    }
+
+    #[test]
+    fn test_convert_to_single_unit() {
+        assert_eq!(Unit::from_str("Hertz").unwrap(), Unit::Hertz);
+        assert_eq!(Unit::from_str("Hz").unwrap(), Unit::Hertz);
+        assert!(Unit::from_str("Hzz").is_err());
+        assert_eq!(MetricPrefix::from_str("T").unwrap(), MetricPrefix::Tera);
+
+        let q = Quantity::from_str("298K").unwrap();
+        let row = format_row(&q, &q.display_in(Unit::Hertz, PrefixMode::Fixed(MetricPrefix::Tera)), 1, None);
+        assert!(row.contains("==") && row.trim_end().ends_with("THz"), "{}", row);
+        assert_eq!(row.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_display_in() {
+        let q = Quantity::from_str("1.5eV").unwrap();
+        let parse = |s: String| Quantity::from_str(&s).unwrap();
+
+        let auto = parse(q.display_in(Unit::ElectronVolt, PrefixMode::Auto));
+        assert_eq!((auto.number, auto.prefix, auto.unit), (1.5, MetricPrefix::One, Unit::ElectronVolt));
+        let auto = parse(q.display_in(Unit::Kelvin, PrefixMode::Auto));
+        assert_eq!((auto.prefix, auto.unit), (MetricPrefix::Kilo, Unit::Kelvin));
+
+        let fixed = parse(q.display_in(Unit::ElectronVolt, PrefixMode::Fixed(MetricPrefix::Milli)));
+        assert_eq!((fixed.number, fixed.prefix), (1500.0, MetricPrefix::Milli));
+        assert_eq!(q.display_in(Unit::ElectronVolt, PrefixMode::Fixed(MetricPrefix::Milli)).trim(), "1500.000000 meV");
+
+        let none = parse(q.display_in(Unit::Kelvin, PrefixMode::None));
+        assert_eq!((none.prefix, none.unit), (MetricPrefix::One, Unit::Kelvin));
+        assert!((none.number - 1.5 / 8.617333E-5).abs() < 1E-3);
+
+        assert_eq!(q.display_in(Unit::ElectronVolt, PrefixMode::Auto), q.to_quantity(Unit::ElectronVolt).to_string());
+    }
+
+    #[test]
+    fn test_convert() {
+        let q = convert(298.0, Unit::Kelvin, MetricPrefix::One, Unit::ElectronVolt);
+        assert_eq!((q.prefix, q.unit), (MetricPrefix::Milli, Unit::ElectronVolt));
+        assert!((q.number - 25.679).abs() < 1E-3);
+        assert!(q.same_energy(&Quantity::from_str("298K").unwrap()));
+
+        let q = convert(1.0, Unit::Hartree, MetricPrefix::Milli, Unit::ElectronVolt).normalize_prefix();
+        assert!((q.number - 0.0272114).abs() < 1E-6);
+    }
+
+    #[test]
+    fn test_celsius_and_fahrenheit() {
+        assert_eq!(Unit::parse_unit("degC"), Ok(("", Unit::Celsius)));
+        assert_eq!(Unit::parse_unit("°C"), Ok(("", Unit::Celsius)));
+        assert_eq!(Unit::parse_unit("degF"), Ok(("", Unit::Fahrenheit)));
+        assert_eq!(Unit::parse_unit("°F"), Ok(("", Unit::Fahrenheit)));
+
+        let ev = |s: &str| Quantity::from_str(s).unwrap().normalize().number;
+        assert!((ev("25degC") - ev("298.15K")).abs() < 1E-12);
+        assert!((ev("25 °C") - ev("298.15K")).abs() < 1E-12);
+        assert!((ev("77degF") - ev("298.15K")).abs() < 1E-12);
+        assert!((ev("-273.15degC")).abs() < 1E-12);
+
+        let q = Quantity::from_str("25degC").unwrap();
+        let back = q.to_quantity(Unit::ElectronVolt).to_quantity(Unit::Celsius);
+        assert_eq!((back.prefix, back.unit), (MetricPrefix::One, Unit::Celsius));
+        assert!((back.number - 25.0).abs() < 1E-9);
+
+        let f = q.to_quantity(Unit::Fahrenheit);
+        assert!((f.number - 77.0).abs() < 1E-9);
+        let k = q.to_quantity(Unit::Kelvin).normalize_prefix();
+        assert!((k.number - 298.15).abs() < 1E-9);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_convert_across_categories() {
+        Quantity::from_str("1eV").unwrap().to_quantity(Unit::InverseAngstrom);
+    }
+
+    #[test]
+    fn test_parse_wavenumber_variants() {
+        let spellings = ["Cm-1", "cm-1", "cm^-1", "cm**-1", "1/cm", "wavenumber", "wavenumbers", "Wavenumber", "Wavenumbers"];
+
+        for s in spellings {
+            assert_eq!(Unit::parse_unit(s), Ok(("", Unit::Wavenumber)), "{}", s);
+
+            let q = Quantity::from_str(&format!("500 {}", s)).unwrap();
+            assert_eq!((q.number, q.prefix, q.unit), (500.0, MetricPrefix::One, Unit::Wavenumber), "{}", s);
+        }
+
+        let q = Quantity::from_str("2Kcm^-1").unwrap();
+        assert_eq!((q.number, q.prefix, q.unit), (2.0, MetricPrefix::Kilo, Unit::Wavenumber));
+
+        // The number parser is greedy, `5001/cm` is parsed as `5001` followed by an invalid unit
+        assert!(Quantity::from_str("5001/cm").is_err());
+    }
 }
@@ -37,11 +37,15 @@ pub use vasp_parsers::potcar::{
 pub use vasp_parsers::chg::{
     ChargeType,
     ChargeDensity,
+    GridFormat,
+    SpinChannel,
+    SymmetryOp,
 };
 
 pub use settings::{
     Settings,
     FunctionalPath,
+    UcSettings,
 };
 
 pub use vasp_parsers::procar::{
@@ -1,10 +1,10 @@
 use std::time;
 
-use env_logger::init_from_env;
 use log::info;
 use rsgrad::{
     Result,
     cli,
+    commands::common::ProgressLogger,
 };
 
 
@@ -12,8 +12,10 @@ use rsgrad::{
 fn main() -> Result<()> {
     let now = time::Instant::now();
 
-    init_from_env(
-        env_logger::Env::new().filter_or("RSGRAD_LOG", "info"));
+    let logger = env_logger::Builder::from_env(
+        env_logger::Env::new().filter_or("RSGRAD_LOG", "info")).build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(ProgressLogger::new(logger)))?;
 
     cli::run()?;
 
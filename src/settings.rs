@@ -2,13 +2,22 @@ use std::path::{
     Path,
     PathBuf,
 };
-use std::collections::HashMap;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+use std::convert::TryFrom;
+use std::sync::OnceLock;
 use anyhow::{
     Result,
     Context,
+    anyhow,
     bail,
 };
-use log::info;
+use log::{
+    info,
+    warn,
+};
 use figment::{
     Figment,
     providers::{Format, Toml},
@@ -20,6 +29,12 @@ use serde::{
 };
 use colored::Colorize;
 
+use crate::vasp_parsers::potcar::FunctionalType;
+
+
+// Settings file specified by the global `--config` flag, see `Settings::set_config_path`.
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
@@ -27,10 +42,12 @@ pub struct Settings {
     #[serde(rename(serialize   = "functional-path",
                    deserialize = "functional-path"))]
     pub functional_path: FunctionalPath,
+
+    pub uc: Option<UcSettings>,
 }
 
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct FunctionalPath {
     #[serde(rename(serialize   = "PAW_PBE",
@@ -40,7 +57,210 @@ pub struct FunctionalPath {
     #[serde(rename(serialize   = "PAW_LDA",
                    deserialize = "PAW_LDA"))]
     pub paw_lda: PathBuf,
-    pub aliases: Option<HashMap<String, String>>,
+
+    // Optional potential sets, only checked if present.
+    #[serde(rename(serialize   = "PAW_GGA",
+                   deserialize = "PAW_GGA"))]
+    pub paw_gga: Option<PathBuf>,
+
+    #[serde(rename(serialize   = "PAW_PBE_GW",
+                   deserialize = "PAW_PBE_GW"))]
+    pub paw_pbe_gw: Option<PathBuf>,
+
+    #[serde(rename(serialize   = "PAW_LDA_GW",
+                   deserialize = "PAW_LDA_GW"))]
+    pub paw_lda_gw: Option<PathBuf>,
+
+    #[serde(rename(serialize   = "US_GGA",
+                   deserialize = "US_GGA"))]
+    pub us_gga: Option<PathBuf>,
+
+    #[serde(rename(serialize   = "US_LDA",
+                   deserialize = "US_LDA"))]
+    pub us_lda: Option<PathBuf>,
+
+    /// Expected version of the PAW potentials, e.g. `.54`. If specified, both `PAW_PBE` and
+    /// `PAW_LDA` directory names are checked to end with it, a warning is raised on mismatch.
+    pub version: Option<String>,
+    pub aliases: Option<Aliases>,
+}
+
+
+/// Element to POTCAR aliases, e.g. `K = "K_sv"`. The aliases can also be nested in the table of a
+/// functional, which take precedence over the flat ones for that functional:
+///
+/// ```toml
+/// [functional-path.aliases]
+/// K = "K_sv"
+///
+/// [functional-path.aliases.PAW_LDA]
+/// K = "K_pv"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "HashMap<String, AliasEntry>", into = "BTreeMap<String, AliasEntry>")]
+pub struct Aliases {
+    /// Aliases shared by all the functionals.
+    pub common: HashMap<String, String>,
+    /// Aliases specific to one functional.
+    pub functional: HashMap<FunctionalType, HashMap<String, String>>,
+}
+
+
+/// An entry of the `aliases` table, either a flat alias or the table of a functional.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AliasEntry {
+    Potcar(String),
+    Functional(BTreeMap<String, String>),
+}
+
+
+impl Aliases {
+    /// Alias of `titel` for `functional`, the table of `functional` is looked up before the flat
+    /// aliases.
+    pub fn get(&self, functional: FunctionalType, titel: &str) -> Option<&String> {
+        self.functional.get(&functional)
+            .and_then(|table| table.get(titel))
+            .or_else(|| self.common.get(titel))
+    }
+}
+
+
+impl TryFrom<HashMap<String, AliasEntry>> for Aliases {
+    type Error = anyhow::Error;
+
+    fn try_from(entries: HashMap<String, AliasEntry>) -> Result<Self> {
+        let mut aliases = Self::default();
+        for (key, entry) in entries {
+            match entry {
+                AliasEntry::Potcar(potcar) => {
+                    aliases.common.insert(key, potcar);
+                },
+                AliasEntry::Functional(table) => {
+                    let functional = key.parse::<FunctionalType>()
+                        .map_err(|_| anyhow!("Unknown functional `{}` in `aliases`.", key))?;
+                    aliases.functional.entry(functional).or_default().extend(table);
+                },
+            }
+        }
+        Ok(aliases)
+    }
+}
+
+
+impl From<Aliases> for BTreeMap<String, AliasEntry> {
+    fn from(aliases: Aliases) -> Self {
+        let mut entries = aliases.common.into_iter()
+            .map(|(titel, potcar)| (titel, AliasEntry::Potcar(potcar)))
+            .collect::<BTreeMap<_, _>>();
+        entries.extend(aliases.functional.into_iter()
+            .map(|(functional, table)| (functional.to_string(), AliasEntry::Functional(table.into_iter().collect()))));
+        entries
+    }
+}
+
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct UcSettings {
+    /// Rows of the `rsgrad uc` table in order, units with optional prefix, e.g.
+    /// `["eV", "meV", "cm-1", "nm", "THz"]`. All units are listed if not specified.
+    pub order: Option<Vec<String>>,
+}
+
+
+impl UcSettings {
+    /// Read the `[uc]` table from the default settings file merged on top of the system-wide one,
+    /// the default value is returned if the files or the table don't exist. The other tables are
+    /// neither required nor checked.
+    pub fn from_default() -> Result<Self> {
+        #[derive(Deserialize)]
+        struct UcOnly {
+            uc: Option<UcSettings>,
+        }
+
+        let paths = [Settings::system_path(), Settings::default_path()?];
+        let mut figment = Figment::new();
+        for path in paths.iter().filter(|p| p.is_file()) {
+            info!("Reading `uc` settings from {:?} ...", path);
+            figment = figment.merge(Toml::file(path));
+        }
+
+        let settings: UcOnly = figment
+            .extract()
+            .with_context(|| format!("Invalid `uc` settings in {:?}", paths))?;
+        Ok(settings.uc.unwrap_or_default())
+    }
+}
+
+
+impl FunctionalPath {
+    /// Directory of the potentials of `functional`, `None` if it is not configured.
+    pub fn get(&self, functional: FunctionalType) -> Option<&PathBuf> {
+        match functional {
+            FunctionalType::PAW_PBE     => Some(&self.paw_pbe),
+            FunctionalType::PAW_LDA     => Some(&self.paw_lda),
+            FunctionalType::PAW_GGA     => self.paw_gga.as_ref(),
+            FunctionalType::PAW_PBE_GW  => self.paw_pbe_gw.as_ref(),
+            FunctionalType::PAW_LDA_GW  => self.paw_lda_gw.as_ref(),
+            FunctionalType::US_GGA      => self.us_gga.as_ref(),
+            FunctionalType::US_LDA      => self.us_lda.as_ref(),
+        }
+    }
+
+    /// Same as `get`, but raises an error if the functional is not configured.
+    pub fn try_get(&self, functional: FunctionalType) -> Result<&PathBuf> {
+        self.get(functional)
+            .with_context(|| format!("Functional `{}` is not configured in `functional-path`.", functional))
+    }
+
+    /// POTCAR directories of `elements` in order under the directory of `functional`, the
+    /// aliases are applied where present, otherwise the bare symbol is used. The elements without
+    /// POTCAR (or compressed POTCAR.z, POTCAR.Z and POTCAR.gz) are reported at once.
+    pub fn resolve_potcars(&self, elements: &[&str], functional: FunctionalType) -> Result<Vec<PathBuf>> {
+        let root = self.try_get(functional)?;
+        let mut missing = Vec::<String>::new();
+
+        let dirs = elements.iter()
+            .map(|&element| {
+                let titel = match self.aliases.as_ref().and_then(|aliases| aliases.get(functional, element)) {
+                    Some(alias) => {
+                        info!("Found functional alias: \"{}\" -> \"{}\".", element, alias);
+                        alias.as_str()
+                    },
+                    None => element,
+                };
+                let dir = root.join(titel);
+                if !Self::has_potcar(&dir) {
+                    missing.push(format!("  {} -> {:?}", element, dir));
+                }
+                dir
+            })
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            bail!("No POTCAR found for the following elements with functional `{}`:\n{}", functional, missing.join("\n"));
+        }
+
+        Ok(dirs)
+    }
+
+    // Whether `dir` contains a POTCAR, either plain or compressed.
+    fn has_potcar(dir: &Path) -> bool {
+        ["POTCAR", "POTCAR.z", "POTCAR.Z", "POTCAR.gz"].iter().any(|x| dir.join(x).is_file())
+    }
+
+    fn get_mut(&mut self, functional: FunctionalType) -> Option<&mut PathBuf> {
+        match functional {
+            FunctionalType::PAW_PBE     => Some(&mut self.paw_pbe),
+            FunctionalType::PAW_LDA     => Some(&mut self.paw_lda),
+            FunctionalType::PAW_GGA     => self.paw_gga.as_mut(),
+            FunctionalType::PAW_PBE_GW  => self.paw_pbe_gw.as_mut(),
+            FunctionalType::PAW_LDA_GW  => self.paw_lda_gw.as_mut(),
+            FunctionalType::US_GGA      => self.us_gga.as_mut(),
+            FunctionalType::US_LDA      => self.us_lda.as_mut(),
+        }
+    }
 }
 
 
@@ -49,25 +269,107 @@ impl Settings {
         info!("Reading rsgrad settings from {:?} ...", path.as_ref());
         Self::check_file_availability(&path)?;
         
-        let mut settings: Settings = Figment::new()
-            .merge(Toml::file(path))
-            .extract()?;
+        Self::from_figment(Figment::new().merge(Toml::file(path)))
+    }
+
+    /// Read the system-wide settings file `system` first, then the user's `user` on top of it.
+    /// The values in `user` take precedence, and the tables are merged key by key, e.g. the
+    /// `aliases` of `user` are added to those of `system`, overriding the same elements only.
+    /// Either of the files can be absent, but not both.
+    pub fn from_layered(system: impl AsRef<Path>, user: impl AsRef<Path>) -> Result<Self> {
+        let (system, user) = (system.as_ref(), user.as_ref());
+
+        let mut figment = Figment::new();
+        let mut found = false;
+        for path in [system, user] {
+            if path.is_file() {
+                info!("Reading rsgrad settings from {:?} ...", path);
+                figment = figment.merge(Toml::file(path));
+                found = true;
+            }
+        }
+        if !found {
+            bail!("Neither {:?} nor {:?} is a regular file.", system, user);
+        }
+
+        Self::from_figment(figment)
+    }
 
-        settings.functional_path.paw_lda = Self::expand_home_dir(&settings.functional_path.paw_lda);
-        settings.functional_path.paw_pbe = Self::expand_home_dir(&settings.functional_path.paw_pbe);
+    fn from_figment(figment: Figment) -> Result<Self> {
+        let mut settings: Settings = figment.extract()?;
+
+        for functional in FunctionalType::ALL {
+            if let Some(path) = settings.functional_path.get_mut(functional) {
+                let key = format!("functional-path.{}", functional);
+                *path = Self::expand_home_dir(Self::expand_env_vars(path, &key)?);
+            }
+        }
 
         settings.check_availability()?;
 
         Ok(settings)
     }
 
+    /// Override the settings file read by `from_default`, it takes precedence over the
+    /// `RSGRAD_CONFIG` environment variable. Only the first call takes effect.
+    pub fn set_config_path(path: impl Into<PathBuf>) {
+        let _ = CONFIG_PATH.set(path.into());
+    }
+
+    /// Path of the system-wide settings file maintained by the administrators, the
+    /// `RSGRAD_SYSTEM_CONFIG` environment variable or `/etc/rsgrad.toml`. The user's settings
+    /// file is merged on top of it.
+    pub fn system_path() -> PathBuf {
+        std::env::var_os("RSGRAD_SYSTEM_CONFIG")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/etc/rsgrad.toml"))
+    }
+
+    /// Path of the user's settings file, in order of precedence: the global `--config` flag, the
+    /// `RSGRAD_CONFIG` environment variable and `~/.rsgrad.toml`.
+    pub fn default_path() -> Result<PathBuf> {
+        Self::resolve_user_path().map(|(path, _)| path)
+    }
+
+    // `default_path` and whether it is given explicitly, i.e. by the flag or the environment
+    // variable.
+    fn resolve_user_path() -> Result<(PathBuf, bool)> {
+        let home = BaseDirs::new().map(|d| d.home_dir().to_path_buf());
+        Self::choose_user_path(CONFIG_PATH.get().cloned(), std::env::var_os("RSGRAD_CONFIG"), home)
+    }
+
+    // Body of `resolve_user_path`, the sources are passed in thus it can be tested without
+    // touching the environment of the process.
+    fn choose_user_path(flag: Option<PathBuf>, env: Option<std::ffi::OsString>, home: Option<PathBuf>) -> Result<(PathBuf, bool)> {
+        if let Some(path) = flag {
+            return Ok((path, true));
+        }
+        if let Some(path) = env.filter(|p| !p.is_empty()) {
+            return Ok((PathBuf::from(path), true));
+        }
+        let home = home.context("Home directory not found.")?;
+        Ok((home.join(".rsgrad.toml"), false))
+    }
+
+    /// Read the user's settings file merged on top of the system-wide one, see `default_path`
+    /// and `system_path`.
+    ///
+    /// A settings file given by the `--config` flag or `RSGRAD_CONFIG` must exist, only the
+    /// implicit `~/.rsgrad.toml` can be absent if the system-wide one exists.
     pub fn from_default() -> Result<Self> {
-        let mut path: PathBuf = BaseDirs::new()
-            .context("Home directory not found.")?
-            .home_dir().to_path_buf();
-        path.push(".rsgrad.toml");
+        let (path, explicit) = Self::resolve_user_path()?;
+        Self::from_user_and_system(&Self::system_path(), &path, explicit)
+    }
+
+    // Body of `from_default` with the paths resolved, `explicit` tells whether `path` is
+    // specified by the user.
+    fn from_user_and_system(system: &Path, path: &Path, explicit: bool) -> Result<Self> {
+        if explicit && !path.is_file() {
+            bail!("rsgrad configuration file {:?} is not a regular file or doesn't exist.", path);
+        }
 
-        if !path.is_file() {
+        if !path.is_file() && !system.is_file() {
             let help_conf = r#"[functional-path]
 PAW_PBE = "/public/apps/vasp/potpaw_PBE.54"
 PAW_LDA = "/public/apps/vasp/potpaw_LDA.54""#.bright_yellow();
@@ -83,10 +385,43 @@ Consider create that file with similar content in the following:
 
 Please replace {} with actual path of corresponding PP's directory, for example:
 
-{}"#, path, example_conf, "<path of ...>".bright_yellow(), help_conf);
+{}
+
+Or run `rsgrad config init` to generate a template."#, path, example_conf, "<path of ...>".bright_yellow(), help_conf);
         }
 
-        Self::from_file(&path)
+        Self::from_layered(system, path)
+    }
+
+    /// Commented template of the settings file, all the fields should be edited before use.
+    pub fn template() -> Result<String> {
+        let settings = Settings {
+            functional_path: FunctionalPath {
+                paw_pbe: PathBuf::from("/path/to/potpaw_PBE.54"),
+                paw_lda: PathBuf::from("/path/to/potpaw_LDA.54"),
+                version: Some(".54".to_string()),
+                aliases: Some(Aliases {
+                    common: HashMap::from([("K".to_string(), "K_sv".to_string())]),
+                    functional: HashMap::from([(FunctionalType::PAW_LDA,
+                        HashMap::from([("K".to_string(), "K_pv".to_string())]))]),
+                }),
+                ..Default::default()
+            },
+            uc: None,
+        };
+
+        let header = r#"# rsgrad configuration file.
+#
+# `PAW_PBE` and `PAW_LDA` are the directories of the pseudopotentials, each element has a
+# sub-directory containing its POTCAR, e.g. `/path/to/potpaw_PBE.54/Fe/POTCAR`.
+# `PAW_GGA`, `PAW_PBE_GW`, `PAW_LDA_GW`, `US_GGA` and `US_LDA` can be added in the same way.
+# `version` is optional, rsgrad warns if the directory names don't end with it.
+# `aliases` is optional, it maps the element symbols in POSCAR to the POTCARs used by `rsgrad pot`,
+# the aliases in the table of a functional, e.g. `[functional-path.aliases.PAW_LDA]`, take
+# precedence for that functional.
+
+"#;
+        Ok(header.to_string() + &toml::to_string(&settings)?)
     }
 
     fn check_availability(&self) -> Result<()> {
@@ -94,10 +429,83 @@ Please replace {} with actual path of corresponding PP's directory, for example:
 
         Self::check_dir_availability(&self.functional_path.paw_pbe)?;
         Self::check_dir_availability(&self.functional_path.paw_lda)?;
+        for functional in FunctionalType::OPTIONAL {
+            if let Some(dir) = self.functional_path.get(functional) {
+                Self::check_dir_availability(dir)?;
+            }
+        }
+
+        if let Some(version) = self.functional_path.version.as_ref() {
+            for dir in [&self.functional_path.paw_pbe, &self.functional_path.paw_lda] {
+                if !Self::match_version(dir, version) {
+                    warn!("Directory {:?} doesn't match the declared PAW version {:?}, please check the `functional-path`.",
+                          dir, version);
+                }
+            }
+        }
 
         Ok(())
     }
 
+    /// Check that every alias points to a POTCAR (or compressed POTCAR.z, POTCAR.Z and POTCAR.gz)
+    /// under the directory of the functionals it applies to, all the missing ones are reported at
+    /// once. Flat aliases are checked under every configured functional.
+    pub fn check_aliases(&self) -> Result<()> {
+        let aliases = match self.functional_path.aliases.as_ref() {
+            Some(aliases) => aliases,
+            None => return Ok(()),
+        };
+
+        info!("Checking POTCAR aliases ...");
+        let mut missing = Vec::<String>::new();
+        for functional in FunctionalType::ALL {
+            let table = aliases.functional.get(&functional);
+            let dir = match self.functional_path.get(functional) {
+                Some(dir) => dir,
+                None => {
+                    if table.is_some() {
+                        missing.push(format!("  {}: aliases specified but the functional is not configured", functional));
+                    }
+                    continue;
+                },
+            };
+
+            let mut titels = aliases.common.keys()
+                .chain(table.into_iter().flat_map(|t| t.keys()))
+                .collect::<Vec<_>>();
+            titels.sort();
+            titels.dedup();
+
+            for titel in titels {
+                let target = aliases.get(functional, titel).unwrap();
+                let path = dir.join(target);
+                if !FunctionalPath::has_potcar(&path) {
+                    missing.push(format!("  {}: {} -> {:?}", functional, titel, path));
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            bail!("No POTCAR found for the following aliases:\n{}", missing.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    // Whether the directory name ends with `version`, `54` and `.54` are treated the same.
+    fn match_version(dir: impl AsRef<Path>, version: &str) -> bool {
+        let version = version.trim().trim_start_matches('.');
+        if version.is_empty() {
+            return true;
+        }
+
+        dir.as_ref()
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(version))
+            .is_some_and(|rest| rest.ends_with('.') || rest.ends_with('_'))
+    }
+
     fn check_dir_availability(dir: impl AsRef<Path>) -> Result<()> {
         if !dir.as_ref().is_dir() {
             bail!("Directory {:?} not available. It should be a regular directory.", dir.as_ref())
@@ -114,6 +522,48 @@ Please replace {} with actual path of corresponding PP's directory, for example:
         }
     }
 
+    // Expand `$VAR` and `${VAR}` in `path`, `key` is the configuration key reported on error.
+    // A `$` not followed by a variable name is kept as is.
+    fn expand_env_vars(path: &Path, key: &str) -> Result<PathBuf> {
+        let txt = match path.to_str() {
+            Some(txt) => txt,
+            None => return Ok(path.to_path_buf()),
+        };
+
+        let mut ret = String::with_capacity(txt.len());
+        let mut rest = txt;
+        while let Some(i) = rest.find('$') {
+            ret.push_str(&rest[..i]);
+            rest = &rest[i + 1 ..];
+
+            let (name, len) = if let Some(braced) = rest.strip_prefix('{') {
+                let end = braced.find('}')
+                    .with_context(|| format!("Unclosed `${{` in `{}` = {:?}.", key, txt))?;
+                if end == 0 {
+                    bail!("Empty variable name `${{}}` in `{}` = {:?}.", key, txt);
+                }
+                (&braced[..end], end + 2)
+            } else {
+                let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], end)
+            };
+
+            if name.is_empty() {
+                ret.push('$');
+                continue;
+            }
+
+            let value = std::env::var(name)
+                .map_err(|_| anyhow!("Environment variable `{}` used in `{}` = {:?} is not set.", name, key, txt))?;
+            ret.push_str(&value);
+            rest = &rest[len..];
+        }
+        ret.push_str(rest);
+
+        Ok(PathBuf::from(ret))
+    }
+
     // copied from https://stackoverflow.com/a/70926549/8977923
     fn expand_home_dir<P: AsRef<Path>>(path: P) -> PathBuf {
         let path = path.as_ref();
@@ -134,6 +584,30 @@ Please replace {} with actual path of corresponding PP's directory, for example:
 }
 
 
+// `potpaw_PBE` and `potpaw_LDA` in a temporary directory for tests, with the `files` (path
+// relative to the temporary directory and content) written in them, e.g.
+// `("potpaw_PBE/O/POTCAR", "")`. Keep the returned `TempDir` alive while using the paths.
+#[cfg(test)]
+pub(crate) fn potential_dirs(files: &[(&str, &str)]) -> (tempdir::TempDir, FunctionalPath) {
+    let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+    let functional_path = FunctionalPath {
+        paw_pbe: dir.path().join("potpaw_PBE"),
+        paw_lda: dir.path().join("potpaw_LDA"),
+        ..Default::default()
+    };
+    std::fs::create_dir(&functional_path.paw_pbe).unwrap();
+    std::fs::create_dir(&functional_path.paw_lda).unwrap();
+
+    for (path, content) in files {
+        let path = dir.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+    }
+
+    (dir, functional_path)
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -145,8 +619,13 @@ mod tests {
             functional_path: FunctionalPath {
                 paw_pbe: PathBuf::from("~/apps/vasp/potpaw_PBE.54"),
                 paw_lda: PathBuf::from("~/apps/vasp/potpaw_LDA.54"),
-                aliases: Some(HashMap::from([("K".to_string(), "K_sv".to_string())])),
+                aliases: Some(Aliases {
+                    common: HashMap::from([("K".to_string(), "K_sv".to_string())]),
+                    functional: HashMap::new(),
+                }),
+                ..Default::default()
             },
+            uc: None,
         };
 
         let txt = r#"[functional-path]
@@ -162,8 +641,9 @@ K = "K_sv"
             functional_path: FunctionalPath {
                 paw_pbe: PathBuf::from("~/apps/vasp/potpaw_PBE.54"),
                 paw_lda: PathBuf::from("~/apps/vasp/potpaw_LDA.54"),
-                aliases: None,
+                ..Default::default()
             },
+            uc: None,
         };
 
         let txt = r#"[functional-path]
@@ -179,8 +659,13 @@ PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
             functional_path: FunctionalPath {
                 paw_pbe: PathBuf::from("~/apps/vasp/potpaw_PBE.54"),
                 paw_lda: PathBuf::from("~/apps/vasp/potpaw_LDA.54"),
-                aliases: Some(HashMap::from([("K".to_string(), "K_sv".to_string())])),
+                aliases: Some(Aliases {
+                    common: HashMap::from([("K".to_string(), "K_sv".to_string())]),
+                    functional: HashMap::new(),
+                }),
+                ..Default::default()
             },
+            uc: None,
         };
 
         let txt = r#"[functional-path]
@@ -197,8 +682,9 @@ aliases = { K = "K_sv" }
             functional_path: FunctionalPath {
                 paw_pbe: PathBuf::from("~/apps/vasp/potpaw_PBE.54"),
                 paw_lda: PathBuf::from("~/apps/vasp/potpaw_LDA.54"),
-                aliases: None,
+                ..Default::default()
             },
+            uc: None,
         };
 
         let txt = r#"[functional-path]
@@ -210,6 +696,350 @@ PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
         assert_eq!(parsed, settings_expected);
     }
 
+    #[test]
+    fn test_aliases_flat_and_nested() {
+        let flat = r#"[functional-path]
+PAW_PBE = "~/apps/vasp/potpaw_PBE.54"
+PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
+
+[functional-path.aliases]
+K = "K_sv"
+"#;
+        let nested = r#"[functional-path]
+PAW_PBE = "~/apps/vasp/potpaw_PBE.54"
+PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
+
+[functional-path.aliases.PAW_PBE]
+K = "K_sv"
+
+[functional-path.aliases.PAW_LDA]
+K = "K_sv"
+"#;
+        let flat = toml::from_str::<Settings>(flat).unwrap().functional_path.aliases.unwrap();
+        let nested = toml::from_str::<Settings>(nested).unwrap().functional_path.aliases.unwrap();
+        for functional in [FunctionalType::PAW_PBE, FunctionalType::PAW_LDA] {
+            assert_eq!(flat.get(functional, "K"), nested.get(functional, "K"));
+            assert_eq!(flat.get(functional, "K").unwrap(), "K_sv");
+        }
+        assert_eq!(flat.get(FunctionalType::PAW_PBE, "Na"), None);
+        assert_eq!(nested.get(FunctionalType::PAW_GGA, "K"), None);
+
+        let mixed = r#"[functional-path]
+PAW_PBE = "~/apps/vasp/potpaw_PBE.54"
+PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
+
+[functional-path.aliases]
+K = "K_sv"
+
+[functional-path.aliases.PAW_LDA]
+K = "K_pv"
+"#;
+        let parsed: Settings = toml::from_str(mixed).unwrap();
+        let aliases = parsed.functional_path.aliases.as_ref().unwrap();
+        assert_eq!(aliases.get(FunctionalType::PAW_PBE, "K").unwrap(), "K_sv");
+        assert_eq!(aliases.get(FunctionalType::PAW_LDA, "K").unwrap(), "K_pv");
+        assert_eq!(toml::to_string(&parsed).unwrap(), mixed);
+
+        assert!(toml::from_str::<Settings>(&mixed.replace("aliases.PAW_LDA", "aliases.PAW_XC")).is_err());
+    }
+
+    #[test]
+    fn test_extra_functionals() {
+        let txt = r#"[functional-path]
+PAW_PBE = "~/apps/vasp/potpaw_PBE.54"
+PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
+PAW_GGA = "~/apps/vasp/potpaw_GGA"
+US_LDA = "~/apps/vasp/pot_LDA"
+"#;
+        let parsed: Settings = toml::from_str(txt).unwrap();
+        assert_eq!(parsed.functional_path.paw_gga, Some(PathBuf::from("~/apps/vasp/potpaw_GGA")));
+        assert_eq!(parsed.functional_path.us_lda, Some(PathBuf::from("~/apps/vasp/pot_LDA")));
+        assert_eq!(parsed.functional_path.paw_pbe_gw, None);
+        assert_eq!(parsed.functional_path.get(FunctionalType::US_LDA), parsed.functional_path.us_lda.as_ref());
+        assert!(parsed.functional_path.try_get(FunctionalType::PAW_PBE_GW).is_err());
+        assert_eq!(toml::to_string(&parsed).unwrap(), txt);
+
+        assert!(toml::from_str::<Settings>(&txt.replace("PAW_GGA", "PAW_GGA_GW")).is_err());
+    }
+
+    #[test]
+    fn test_optional_functional_availability() {
+        let (dir, functional_path) = potential_dirs(&[]);
+        let mut settings = Settings { functional_path, uc: None };
+        assert!(settings.check_availability().is_ok());
+
+        settings.functional_path.paw_pbe_gw = Some(dir.path().join("potpaw_PBE_GW"));
+        assert!(settings.check_availability().is_err());
+
+        std::fs::create_dir(dir.path().join("potpaw_PBE_GW")).unwrap();
+        assert!(settings.check_availability().is_ok());
+    }
+
+    #[test]
+    fn test_check_aliases() {
+        let (_dir, functional_path) = potential_dirs(&[
+            ("potpaw_PBE/K_sv/POTCAR", ""),
+            ("potpaw_LDA/K_sv/POTCAR.gz", ""),
+        ]);
+        std::fs::create_dir(functional_path.paw_lda.join("K_pv")).unwrap();
+
+        let mut settings = Settings { functional_path, uc: None };
+        assert!(settings.check_aliases().is_ok());
+
+        settings.functional_path.aliases = Some(Aliases {
+            common: HashMap::from([("K".to_string(), "K_sv".to_string())]),
+            functional: HashMap::new(),
+        });
+        assert!(settings.check_aliases().is_ok());
+
+        // `K_pv` has no POTCAR, `Na_pv` doesn't exist in `PAW_PBE`, `PAW_GGA` is not configured.
+        settings.functional_path.aliases = Some(Aliases {
+            common: HashMap::from([("K".to_string(), "K_sv".to_string()),
+                                   ("Na".to_string(), "Na_pv".to_string())]),
+            functional: HashMap::from([
+                (FunctionalType::PAW_LDA, HashMap::from([("K".to_string(), "K_pv".to_string()),
+                                                         ("Na".to_string(), "K_sv".to_string())])),
+                (FunctionalType::PAW_GGA, HashMap::new()),
+            ]),
+        });
+        let msg = settings.check_aliases().unwrap_err().to_string();
+        assert!(msg.contains("PAW_PBE: Na -> "));
+        assert!(msg.contains("PAW_LDA: K -> "));
+        assert!(!msg.contains("PAW_PBE: K -> "));
+        assert!(!msg.contains("PAW_LDA: Na -> "));
+        assert!(msg.contains("PAW_GGA: aliases specified"));
+        assert_eq!(msg.lines().count(), 4);
+    }
+
+    #[test]
+    fn test_resolve_potcars() {
+        let (_dir, mut functional_path) = potential_dirs(&[
+            ("potpaw_PBE/K_sv/POTCAR", ""),
+            ("potpaw_PBE/O/POTCAR.Z", ""),
+            ("potpaw_LDA/K_pv/POTCAR", ""),
+            ("potpaw_LDA/O/POTCAR", ""),
+        ]);
+        let paw_pbe = functional_path.paw_pbe.clone();
+        let paw_lda = functional_path.paw_lda.clone();
+
+        // Bare symbols without aliases, `K` has no POTCAR.
+        assert_eq!(functional_path.resolve_potcars(&["O"], FunctionalType::PAW_PBE).unwrap(),
+                   vec![paw_pbe.join("O")]);
+        let msg = functional_path.resolve_potcars(&["K", "O"], FunctionalType::PAW_PBE).unwrap_err().to_string();
+        assert!(msg.contains("K -> "));
+        assert!(!msg.contains("O -> "));
+
+        functional_path.aliases = Some(Aliases {
+            common: HashMap::from([("K".to_string(), "K_sv".to_string())]),
+            functional: HashMap::from([
+                (FunctionalType::PAW_LDA, HashMap::from([("K".to_string(), "K_pv".to_string())])),
+            ]),
+        });
+        assert_eq!(functional_path.resolve_potcars(&["K", "O", "K"], FunctionalType::PAW_PBE).unwrap(),
+                   vec![paw_pbe.join("K_sv"), paw_pbe.join("O"), paw_pbe.join("K_sv")]);
+        assert_eq!(functional_path.resolve_potcars(&["O", "K"], FunctionalType::PAW_LDA).unwrap(),
+                   vec![paw_lda.join("O"), paw_lda.join("K_pv")]);
+
+        // All missing elements are reported.
+        let msg = functional_path.resolve_potcars(&["Na", "K", "Cl"], FunctionalType::PAW_PBE).unwrap_err().to_string();
+        assert!(msg.contains("Na -> ") && msg.contains("Cl -> "));
+        assert!(!msg.contains("K -> "));
+        assert_eq!(msg.lines().count(), 3);
+
+        assert!(functional_path.resolve_potcars(&["O"], FunctionalType::PAW_GGA).is_err());
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        std::env::set_var("RSGRAD_TEST_POTENTIALS", "/public/apps/vasp");
+        let expand = |path: &str| Settings::expand_env_vars(Path::new(path), "functional-path.PAW_PBE");
+
+        assert_eq!(expand("$RSGRAD_TEST_POTENTIALS/potpaw_PBE.54").unwrap(),
+                   PathBuf::from("/public/apps/vasp/potpaw_PBE.54"));
+        assert_eq!(expand("${RSGRAD_TEST_POTENTIALS}_old/potpaw_PBE").unwrap(),
+                   PathBuf::from("/public/apps/vasp_old/potpaw_PBE"));
+        assert_eq!(expand("~/pp/$/potpaw_PBE").unwrap(), PathBuf::from("~/pp/$/potpaw_PBE"));
+        assert_eq!(expand("/apps/potpaw_PBE").unwrap(), PathBuf::from("/apps/potpaw_PBE"));
+
+        let msg = expand("$RSGRAD_TEST_UNSET_VARIABLE/potpaw_PBE").unwrap_err().to_string();
+        assert!(msg.contains("`RSGRAD_TEST_UNSET_VARIABLE`"));
+        assert!(msg.contains("`functional-path.PAW_PBE`"));
+
+        assert!(expand("${RSGRAD_TEST_POTENTIALS/potpaw_PBE").is_err());
+        assert!(expand("${}/potpaw_PBE").is_err());
+    }
+
+    #[test]
+    fn test_uc_order() {
+        let txt = r#"[functional-path]
+PAW_PBE = "~/apps/vasp/potpaw_PBE.54"
+PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
+
+[uc]
+order = ["eV", "meV", "cm-1", "nm", "THz"]
+"#;
+        let parsed: Settings = toml::from_str(txt).unwrap();
+        assert_eq!(parsed.uc, Some(UcSettings {
+            order: Some(["eV", "meV", "cm-1", "nm", "THz"].iter().map(|x| x.to_string()).collect()),
+        }));
+        assert_eq!(toml::to_string(&parsed).unwrap(), txt);
+
+        assert!(toml::from_str::<Settings>(&txt.replace("order", "orders")).is_err());
+    }
+
+    #[test]
+    fn test_version_round_trip() {
+        let settings = Settings {
+            functional_path: FunctionalPath {
+                paw_pbe: PathBuf::from("~/apps/vasp/potpaw_PBE.54"),
+                paw_lda: PathBuf::from("~/apps/vasp/potpaw_LDA.54"),
+                version: Some(".54".to_string()),
+                aliases: None,
+                ..Default::default()
+            },
+            uc: None,
+        };
+
+        let txt = r#"[functional-path]
+PAW_PBE = "~/apps/vasp/potpaw_PBE.54"
+PAW_LDA = "~/apps/vasp/potpaw_LDA.54"
+version = ".54"
+"#;
+        assert_eq!(toml::to_string(&settings).unwrap(), txt);
+        assert_eq!(toml::from_str::<Settings>(txt).unwrap(), settings);
+    }
+
+    #[test]
+    fn test_match_version() {
+        assert!(Settings::match_version("/apps/potpaw_PBE.54", ".54"));
+        assert!(Settings::match_version("/apps/potpaw_PBE.54", "54"));
+        assert!(Settings::match_version("/apps/potpaw_PBE_54", "54"));
+        assert!(!Settings::match_version("/apps/potpaw_PBE.52", ".54"));
+        assert!(!Settings::match_version("/apps/potpaw_PBE.154", ".54"));
+        assert!(!Settings::match_version("/apps/potpaw_PBE", ".54"));
+    }
+
+    #[test]
+    fn test_template() {
+        let txt = Settings::template().unwrap();
+        assert!(txt.starts_with("# rsgrad configuration file."));
+        assert!(txt.contains("[functional-path.aliases]"));
+
+        let parsed: Settings = toml::from_str(&txt).unwrap();
+        assert_eq!(parsed.functional_path.version.as_deref(), Some(".54"));
+        let aliases = parsed.functional_path.aliases.unwrap();
+        assert_eq!(aliases.get(FunctionalType::PAW_PBE, "K").unwrap(), "K_sv");
+        assert_eq!(aliases.get(FunctionalType::PAW_LDA, "K").unwrap(), "K_pv");
+    }
+
+    #[test]
+    fn test_choose_user_path() {
+        let home = Some(PathBuf::from("/home/user"));
+        let flag = Some(PathBuf::from("flag.toml"));
+        let env = Some(std::ffi::OsString::from("env.toml"));
+
+        let choose = Settings::choose_user_path;
+        assert_eq!(choose(flag.clone(), env.clone(), home.clone()).unwrap(), (PathBuf::from("flag.toml"), true));
+        assert_eq!(choose(None, env, home.clone()).unwrap(), (PathBuf::from("env.toml"), true));
+        assert_eq!(choose(None, Some("".into()), home.clone()).unwrap(), (PathBuf::from("/home/user/.rsgrad.toml"), false));
+        assert_eq!(choose(flag, None, None).unwrap().0, PathBuf::from("flag.toml"));
+        assert!(choose(None, None, None).is_err());
+    }
+
+    #[test]
+    fn test_explicit_config_must_exist() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let paw_pbe = dir.path().join("potpaw_PBE.54");
+        let paw_lda = dir.path().join("potpaw_LDA.54");
+        std::fs::create_dir(&paw_pbe).unwrap();
+        std::fs::create_dir(&paw_lda).unwrap();
+
+        let system = dir.path().join("system.toml");
+        std::fs::write(&system, format!(r#"[functional-path]
+PAW_PBE = {:?}
+PAW_LDA = {:?}
+"#, paw_pbe, paw_lda)).unwrap();
+        let missing = dir.path().join("mistyped.toml");
+
+        // The implicit `~/.rsgrad.toml` falls back to the system-wide settings
+        let settings = Settings::from_user_and_system(&system, &missing, false).unwrap();
+        assert_eq!(settings.functional_path.paw_pbe, paw_pbe);
+
+        // A mistyped `--config` or `RSGRAD_CONFIG` is never ignored
+        let msg = format!("{:#}", Settings::from_user_and_system(&system, &missing, true).unwrap_err());
+        assert!(msg.contains("mistyped.toml"), "{}", msg);
+
+        let settings = Settings::from_user_and_system(&missing, &system, true).unwrap();
+        assert_eq!(settings.functional_path.paw_lda, paw_lda);
+    }
+
+    #[test]
+    fn test_from_layered() {
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let site = dir.path().join("site");
+        let home = dir.path().join("home");
+        for d in ["potpaw_PBE.54", "potpaw_LDA.54"] {
+            std::fs::create_dir_all(site.join(d)).unwrap();
+        }
+        std::fs::create_dir_all(home.join("potpaw_PBE.64")).unwrap();
+
+        let system = dir.path().join("system.toml");
+        std::fs::write(&system, format!(r#"[functional-path]
+PAW_PBE = {:?}
+PAW_LDA = {:?}
+version = ".54"
+
+[functional-path.aliases]
+K = "K_sv"
+Na = "Na_pv"
+
+[functional-path.aliases.PAW_LDA]
+K = "K_pv"
+Ca = "Ca_pv"
+"#, site.join("potpaw_PBE.54"), site.join("potpaw_LDA.54"))).unwrap();
+
+        let user = dir.path().join("user.toml");
+        std::fs::write(&user, r#"[functional-path.aliases]
+Na = "Na_sv"
+Li = "Li_sv"
+
+[functional-path.aliases.PAW_LDA]
+Ca = "Ca_sv"
+"#).unwrap();
+
+        // Only the aliases are overridden or added key by key
+        let settings = Settings::from_layered(&system, &user).unwrap();
+        let path = &settings.functional_path;
+        assert_eq!(path.paw_pbe, site.join("potpaw_PBE.54"));
+        assert_eq!(path.version.as_deref(), Some(".54"));
+        let aliases = path.aliases.as_ref().unwrap();
+        assert_eq!(aliases.get(FunctionalType::PAW_PBE, "K").unwrap(), "K_sv");
+        assert_eq!(aliases.get(FunctionalType::PAW_PBE, "Na").unwrap(), "Na_sv");
+        assert_eq!(aliases.get(FunctionalType::PAW_PBE, "Li").unwrap(), "Li_sv");
+        assert_eq!(aliases.get(FunctionalType::PAW_LDA, "K").unwrap(), "K_pv");
+        assert_eq!(aliases.get(FunctionalType::PAW_LDA, "Ca").unwrap(), "Ca_sv");
+        assert_eq!(aliases.get(FunctionalType::PAW_PBE, "Ca"), None);
+
+        // Scalar values of the user take precedence
+        std::fs::write(&user, format!(r#"[functional-path]
+PAW_PBE = {:?}
+version = ".64"
+"#, home.join("potpaw_PBE.64"))).unwrap();
+        let settings = Settings::from_layered(&system, &user).unwrap();
+        let path = &settings.functional_path;
+        assert_eq!(path.paw_pbe, home.join("potpaw_PBE.64"));
+        assert_eq!(path.paw_lda, site.join("potpaw_LDA.54"));
+        assert_eq!(path.version.as_deref(), Some(".64"));
+        assert_eq!(path.aliases.as_ref().unwrap().get(FunctionalType::PAW_PBE, "Na").unwrap(), "Na_pv");
+
+        // Either of them can be absent
+        let missing = dir.path().join("missing.toml");
+        assert_eq!(Settings::from_layered(&system, &missing).unwrap().functional_path.paw_pbe,
+                   site.join("potpaw_PBE.54"));
+        assert!(Settings::from_layered(&missing, &user).is_err());     // PAW_LDA is missing
+        assert!(Settings::from_layered(&missing, &missing).is_err());
+    }
+
     #[test]
     #[ignore]
     fn test_from_default() -> Result<()> {
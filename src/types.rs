@@ -99,8 +99,11 @@ pub struct Structure {
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
 pub enum Axis {
+    #[value(alias = "a")]
     X,
+    #[value(alias = "b")]
     Y,
+    #[value(alias = "c")]
     Z,
 }
 
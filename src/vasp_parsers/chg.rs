@@ -2,34 +2,267 @@ use std::{
     path::Path,
     fs,
     fmt,
+    io::{
+        BufRead,
+        BufReader,
+        BufWriter,
+        Read,
+        Write,
+    },
     ops::{
         Add,
+        Mul,
         Sub,
     },
+    str::FromStr,
 };
 
 use regex::Regex;
+use clap::ValueEnum;
 use ndarray::{
     Array3,
     ShapeBuilder,
+    Zip,
 };
 use anyhow::{
     Context,
+    Error,
     bail,
 };
 use rayon::prelude::*;
+use byteorder::{
+    LittleEndian,
+    WriteBytesExt,
+};
+use flate2::{
+    Compression,
+    read::MultiGzDecoder,
+    write::GzEncoder,
+};
 
 use crate::{
-    types::Mat33,
+    types::{
+        Mat33,
+        Axis,
+    },
     Result,
     Poscar,
 };
 
 
+const BOHR_IN_A: f64 = 0.529177210903;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const ELEMENTS: [&str; 118] = [
+    "H",  "He", "Li", "Be", "B",  "C",  "N",  "O",  "F",  "Ne", "Na", "Mg", "Al", "Si", "P",
+    "S",  "Cl", "Ar", "K",  "Ca", "Sc", "Ti", "V",  "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn",
+    "Ga", "Ge", "As", "Se", "Br", "Kr", "Rb", "Sr", "Y",  "Zr", "Nb", "Mo", "Tc", "Ru", "Rh",
+    "Pd", "Ag", "Cd", "In", "Sn", "Sb", "Te", "I",  "Xe", "Cs", "Ba", "La", "Ce", "Pr", "Nd",
+    "Pm", "Sm", "Eu", "Gd", "Tb", "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W",  "Re",
+    "Os", "Ir", "Pt", "Au", "Hg", "Tl", "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th",
+    "Pa", "U",  "Np", "Pu", "Am", "Cm", "Bk", "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db",
+    "Sg", "Bh", "Hs", "Mt", "Ds", "Rg", "Cn", "Nh", "Fl", "Mc", "Lv", "Ts", "Og",
+];
+
+
+// Write `txt` to `path`, gzip compressed if `path` ends with `.gz`.
+fn write_text(path: &Path, txt: &str) -> Result<()> {
+    if path.extension().is_some_and(|x| x.eq_ignore_ascii_case("gz")) {
+        let mut encoder = GzEncoder::new(BufWriter::new(fs::File::create(path)?), Compression::default());
+        encoder.write_all(txt.as_bytes())?;
+        encoder.finish()?.flush()?;
+    } else {
+        fs::write(path, txt)?;
+    }
+    Ok(())
+}
+
+
+/// Atomic number of an element symbol, `0` for unknown symbols.
+fn atomic_number(symbol: &str) -> usize {
+    // POTCAR titles may leave suffixes like `Fe_pv` or `H1.25` in the POSCAR
+    let symbol = symbol.split(|c: char| !c.is_ascii_alphabetic()).next().unwrap_or("");
+    ELEMENTS.iter()
+        .position(|x| *x == symbol)
+        .map(|i| i + 1)
+        .unwrap_or(0)
+}
+
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ChargeType {
     Chgcar,
     Locpot,
+    /// Density in e/A^3 written as is, see [`ChargeDensity::into_true_density`].
+    TrueDensity,
+}
+
+
+/// File format of the written volumetric data, see [`GridFormat::from_path`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GridFormat {
+    /// CHGCAR-like VASP grid
+    Vasp,
+
+    /// Gaussian cube
+    Cube,
+
+    /// NumPy array with a JSON companion
+    Npy,
+}
+
+
+impl GridFormat {
+    /// Infer the format from the extension of `path`, and whether it should be gzip compressed,
+    /// i.e. `path` ends with `.gz`. The extension before `.gz` picks the format: `.cube` for cube,
+    /// `.npy` for NumPy, and anything else (`.vasp`, `CHGCAR`, `CHGDIFF.dat` ...) for VASP grid,
+    /// as VASP files don't have a conventional extension. NumPy output cannot be compressed.
+    ///
+    /// ```
+    /// use rsgrad::GridFormat;
+    /// assert_eq!(GridFormat::from_path("CHGSUM.cube.gz").unwrap(), (GridFormat::Cube, true));
+    /// assert_eq!(GridFormat::from_path("CHGCAR").unwrap(), (GridFormat::Vasp, false));
+    /// ```
+    pub fn from_path(path: &(impl AsRef<Path> + ?Sized)) -> Result<(Self, bool)> {
+        let path = path.as_ref();
+        let has_ext = |p: &Path, ext: &str| p.extension().is_some_and(|x| x.eq_ignore_ascii_case(ext));
+
+        let gzip = has_ext(path, "gz");
+        let stem = if gzip { Path::new(path.file_stem().unwrap_or_default()) } else { path };
+
+        let format = if has_ext(stem, "cube") {
+            Self::Cube
+        } else if has_ext(stem, "npy") {
+            Self::Npy
+        } else {
+            Self::Vasp
+        };
+
+        if gzip && format == Self::Npy {
+            bail!("[CHG]: NumPy output cannot be gzip compressed: {:?}", path);
+        }
+        Ok((format, gzip))
+    }
+}
+
+
+/// Spin channel of a spin-polarized (ISPIN = 2) CHGCAR, whose data sets are the total density
+/// and the magnetization density.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SpinChannel {
+    /// Total density, i.e. up + down
+    Total,
+
+    /// Spin up density, i.e. (total + mag) / 2
+    Up,
+
+    /// Spin down density, i.e. (total - mag) / 2
+    Down,
+
+    /// Magnetization density, i.e. up - down
+    Mag,
+}
+
+
+
+/// Symmetry operation acting on fractional coordinates: `f' = rotation · f + translation`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SymmetryOp {
+    pub rotation:       [[i32; 3]; 3],
+    pub translation:    [f64; 3],
+}
+
+
+impl SymmetryOp {
+    /// Image of fractional coordinate `frac` under this operation.
+    pub fn apply(&self, frac: [f64; 3]) -> [f64; 3] {
+        let mut ret = self.translation;
+        for (r, row) in ret.iter_mut().zip(self.rotation.iter()) {
+            *r += row[0] as f64 * frac[0] + row[1] as f64 * frac[1] + row[2] as f64 * frac[2];
+        }
+        ret
+    }
+
+
+    fn determinant(&self) -> i32 {
+        let m = &self.rotation;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+
+impl FromStr for SymmetryOp {
+    type Err = Error;
+
+    /// Parse 12 numbers separated by spaces or commas: the 9 integer elements of rotation
+    /// matrix in row-major order followed by the 3 elements of translation. e.g.
+    /// `"-1 0 0 0 1 0 0 0 1 0 0 0"` is the mirror `x -> -x`.
+    fn from_str(s: &str) -> Result<Self> {
+        let fields = s.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>();
+        if fields.len() != 12 {
+            bail!("[CHG]: Symmetry operation needs 9 rotation and 3 translation elements, got {} in {:?}.",
+                  fields.len(), s);
+        }
+
+        let mut rotation = [[0i32; 3]; 3];
+        for (r, x) in rotation.iter_mut().flatten().zip(fields.iter()) {
+            *r = x.parse::<i32>()
+                .with_context(|| format!("[CHG]: Rotation element {:?} of symmetry operation is not an integer.", x))?;
+        }
+
+        let mut translation = [0.0f64; 3];
+        for (t, x) in translation.iter_mut().zip(fields[9 ..].iter()) {
+            *t = x.parse::<f64>()
+                .with_context(|| format!("[CHG]: Translation element {:?} of symmetry operation is not a number.", x))?;
+        }
+
+        let op = Self { rotation, translation };
+        if op.determinant().abs() != 1 {
+            bail!("[CHG]: The determinant of rotation in symmetry operation must be 1 or -1, got {}.", op.determinant());
+        }
+
+        Ok(op)
+    }
+}
+
+
+// Value of `grid` at fractional coordinate `frac` by trilinear interpolation, the grid is
+// treated as periodic.
+fn trilinear(grid: &Array3<f64>, frac: [f64; 3]) -> f64 {
+    let shape = grid.shape();
+    let mut lower = [0usize; 3];
+    let mut weight = [0.0f64; 3];
+
+    for (d, (l, w)) in lower.iter_mut().zip(weight.iter_mut()).enumerate() {
+        let n = shape[d];
+        let mut x = frac[d].rem_euclid(1.0) * n as f64;
+        if (x - x.round()).abs() < 1E-8 {     // snap onto the grid point to avoid noise
+            x = x.round();
+        }
+        *l = (x.floor() as usize) % n;
+        *w = x - x.floor();
+    }
+
+    let mut ret = 0.0;
+    for (a, wa) in [(0, 1.0 - weight[0]), (1, weight[0])] {
+        for (b, wb) in [(0, 1.0 - weight[1]), (1, weight[1])] {
+            for (c, wc) in [(0, 1.0 - weight[2]), (1, weight[2])] {
+                if wa * wb * wc == 0.0 { continue }
+                let idx = [
+                    (lower[0] + a) % shape[0],
+                    (lower[1] + b) % shape[1],
+                    (lower[2] + c) % shape[2],
+                ];
+                ret += wa * wb * wc * grid[idx];
+            }
+        }
+    }
+    ret
 }
 
 
@@ -113,13 +346,105 @@ pub struct ChargeDensity {
 
 
 impl ChargeDensity {
-    /// Read CHGCAR like volumetric data from file.
+    /// Read volumetric data from file. The title line is decoded lossily because some generators
+    /// leave stray non-UTF-8 bytes in it, the rest of the file must be valid UTF-8.
+    ///
+    /// Gzip compressed files, e.g. `CHGCAR.gz`, are detected by the magic number and decompressed
+    /// transparently.
     pub fn from_file(path: &(impl AsRef<Path> + ?Sized), chgtype: ChargeType) -> Result<Self> {
-        let txt = fs::read_to_string(path)?;
+        let bytes = Self::decompress_if_gzip(fs::read(path)?)
+            .with_context(|| format!("[CHG]: Cannot decompress {:?}", path.as_ref()))?;
+        let txt = Self::decode_with_lossy_title(bytes)?;
         Self::from_txt(&txt, chgtype)
     }
 
 
+    /// Read the structure and the grid dimensions only, the grid data is not read. This is cheap
+    /// even for very large files, e.g. to check that the inputs are compatible in advance.
+    pub fn read_header(path: &(impl AsRef<Path> + ?Sized)) -> Result<(Poscar, [usize; 3])> {
+        let (pos, ngrid, _) = Self::open_with_header(path)
+            .with_context(|| format!("[CHG]: Cannot read the header of {:?}", path.as_ref()))?;
+        Ok((pos, ngrid))
+    }
+
+
+    // Open the file, gzip compressed or not, and read the structure and the grid dimensions. The
+    // rest lines starting from the grid data are returned for streaming.
+    fn open_with_header(path: &(impl AsRef<Path> + ?Sized)) -> Result<(Poscar, [usize; 3], std::io::Lines<Box<dyn BufRead>>)> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        let mut reader: Box<dyn BufRead> = if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+        } else {
+            Box::new(reader)
+        };
+
+        // The title line is decoded lossily, the same as `from_file`
+        let mut title = Vec::new();
+        reader.read_until(b'\n', &mut title)?;
+        let mut header = String::from_utf8_lossy(&title).trim_end().to_string();
+        header.push('\n');
+
+        // POSCAR part ends with an empty line
+        let mut lines = reader.lines();
+        for line in lines.by_ref() {
+            let line = line?;
+            if line.trim().is_empty() {
+                break;
+            }
+            header.push_str(&line);
+            header.push('\n');
+        }
+        let pos = Self::read_poscar(&header)?;
+
+        let ngrid = loop {
+            let line = lines.next().context("[CHG]: This file has no grid size data.")??;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let v = line.split_whitespace()
+                .map(|s| s.parse::<usize>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .with_context(|| format!("[CHG]: Invalid grid size line: {:?}", line))?;
+            if v.len() != 3 || v.contains(&0) {
+                bail!("[CHG]: Invalid grid size line: {:?}", line);
+            }
+            break [v[0], v[1], v[2]];
+        };
+
+        Ok((pos, ngrid, lines))
+    }
+
+
+    // Decompress `bytes` if it starts with the gzip magic number, otherwise it is returned as is.
+    fn decompress_if_gzip(bytes: Vec<u8>) -> Result<Vec<u8>> {
+        if !bytes.starts_with(&GZIP_MAGIC) {
+            return Ok(bytes);
+        }
+
+        let mut ret = Vec::new();
+        MultiGzDecoder::new(&bytes[..]).read_to_end(&mut ret)?;
+        Ok(ret)
+    }
+
+
+    // Invalid UTF-8 bytes in the title line are replaced by U+FFFD, while the other lines are
+    // decoded strictly.
+    fn decode_with_lossy_title(bytes: Vec<u8>) -> Result<String> {
+        let bytes = match String::from_utf8(bytes) {
+            Ok(txt) => return Ok(txt),
+            Err(e)  => e.into_bytes(),
+        };
+
+        let end = bytes.iter().position(|b| *b == b'\n').map_or(bytes.len(), |i| i + 1);
+        let rest = std::str::from_utf8(&bytes[end ..])
+            .context("[CHG]: Invalid UTF-8 data found after the title line.")?;
+
+        let mut txt = String::from_utf8_lossy(&bytes[.. end]).into_owned();
+        txt.push_str(rest);
+        Ok(txt)
+    }
+
+
     /// Parse CHGCAR like volumetric data from string.
     pub fn from_txt(txt: &str, chgtype: ChargeType) -> Result<Self> {
         let separate_pos = Regex::new(r"(?m)^\s*$").unwrap()
@@ -148,7 +473,7 @@ impl ChargeDensity {
                 chg.par_iter_mut()
                     .for_each(|charge| *charge /= vol);
             },
-            ChargeType::Locpot => { }
+            ChargeType::Locpot | ChargeType::TrueDensity => { }
         }
 
         let ngrid = {
@@ -174,12 +499,652 @@ impl ChargeDensity {
     }
 
 
+    /// Write the volumetric data in CHGCAR format, the file is gzip compressed if `path` ends
+    /// with `.gz`.
     pub fn to_file(&self, path: &(impl AsRef<Path> + ?Sized)) -> Result<()> {
-        fs::write(path, self.to_string())?;
+        write_text(path.as_ref(), &self.to_string())
+    }
+
+
+    /// Write the data to `path` in the format inferred from its extension by
+    /// [`GridFormat::from_path`], e.g. `CHGSUM.cube.gz` is a gzip compressed cube file.
+    /// Returns the format written.
+    pub fn to_file_auto(&self, path: &(impl AsRef<Path> + ?Sized)) -> Result<GridFormat> {
+        let (format, _) = GridFormat::from_path(path)?;
+        match format {
+            GridFormat::Vasp => self.to_file(path)?,
+            GridFormat::Cube => self.to_cube(path)?,
+            GridFormat::Npy  => self.to_npy(path)?,
+        }
+        Ok(format)
+    }
+
+
+    /// Write the first data set (i.e. the total density for spin-polarized CHGCAR) in Gaussian
+    /// cube format, following the conventions ASE round-trips without any fixup:
+    ///
+    /// - The origin is always `(0, 0, 0)`;
+    /// - Lengths (voxel vectors and atomic positions) are in Bohr, which is signaled by positive
+    ///   grid counts. ASE converts them back to Angstrom on reading;
+    /// - Atoms are written in the order of the input POSCAR part, with atomic numbers derived
+    ///   from the element symbols, unknown symbols are written as `0`;
+    /// - The volumetric data of `ChargeType::Chgcar` and `ChargeType::TrueDensity` is written as
+    ///   the electron density in `e/Bohr^3`, i.e. the usual cube convention, not `rho * V_cell` as
+    ///   CHGCAR does. ASE keeps the data as is, so `ase.io.cube.read_cube_data` gives `e/Bohr^3`,
+    ///   divide it by `ase.units.Bohr ** 3` to get `e/A^3`. `ChargeType::Locpot` data is written
+    ///   unchanged (eV).
+    ///
+    /// The output can be compared with the original CHGCAR in ASE by
+    ///
+    /// ```text
+    /// from ase.io.cube import read_cube_data
+    /// from ase.calculators.vasp import VaspChargeDensity
+    /// from ase.units import Bohr
+    /// data, atoms = read_cube_data("CHGSUM.cube")
+    /// vasp = VaspChargeDensity("CHGSUM.vasp")     # already divided by the volume, in e/A^3
+    /// assert abs(data / Bohr**3 - vasp.chg[0]).max() < 1e-8
+    /// assert abs(atoms.positions - vasp.atoms[0].positions).max() < 1e-6
+    /// ```
+    pub fn to_cube_string(&self) -> String {
+        use std::fmt::Write;
+
+        let pos = self.pos.clone().normalize();
+        let factor = match self.chgtype {
+            ChargeType::Chgcar | ChargeType::TrueDensity => BOHR_IN_A.powi(3),
+            ChargeType::Locpot => 1.0,
+        };
+
+        let mut ret = String::new();
+        let _ = writeln!(ret, "{}", pos.comment.trim());
+        let _ = writeln!(ret, "Written by rsgrad, OUTER LOOP: X, MIDDLE LOOP: Y, INNER LOOP: Z");
+        let _ = writeln!(ret, "{:5} {:12.6} {:12.6} {:12.6}", pos.get_natoms(), 0.0, 0.0, 0.0);
+        for (n, row) in self.ngrid.iter().zip(pos.cell.iter()) {
+            let nf = *n as f64;
+            let _ = writeln!(ret, "{:5} {:12.6} {:12.6} {:12.6}", n,
+                             row[0] / nf / BOHR_IN_A, row[1] / nf / BOHR_IN_A, row[2] / nf / BOHR_IN_A);
+        }
+        for (symbol, p) in pos.get_atom_symbols().iter().zip(pos.pos_cart.iter()) {
+            let z = atomic_number(symbol);
+            let _ = writeln!(ret, "{:5} {:12.6} {:12.6} {:12.6} {:12.6}", z, z as f64,
+                             p[0] / BOHR_IN_A, p[1] / BOHR_IN_A, p[2] / BOHR_IN_A);
+        }
+
+        let chg = &self.chg[0];
+        for ix in 0 .. self.ngrid[0] {
+            for iy in 0 .. self.ngrid[1] {
+                for iz in 0 .. self.ngrid[2] {
+                    let _ = write!(ret, " {:13.5E}", chg[[ix, iy, iz]] * factor);
+                    if iz % 6 == 5 {
+                        ret.push('\n');
+                    }
+                }
+                if self.ngrid[2] % 6 != 0 {
+                    ret.push('\n');
+                }
+            }
+        }
+
+        ret
+    }
+
+
+    /// Write the data to `path` in Gaussian cube format, see [`ChargeDensity::to_cube_string`]
+    /// for the conventions. The file is gzip compressed if `path` ends with `.gz`.
+    pub fn to_cube(&self, path: &(impl AsRef<Path> + ?Sized)) -> Result<()> {
+        write_text(path.as_ref(), &self.to_cube_string())
+    }
+
+
+    /// Write the first data set to `path` as a NumPy `.npy` file of `float64`, with a companion
+    /// JSON file (same name with `.json` extension) describing the lattice and composition.
+    ///
+    /// The array is in C order with shape `(NX, NY, NZ)`, i.e. `np.load(path)[ix, iy, iz]` is the
+    /// value at fractional coordinate `(ix/NX, iy/NY, iz/NZ)`. For `ChargeType::Chgcar` the data
+    /// is the density in e/A^3 (not `rho * V_cell`), other data is written as is.
+    pub fn to_npy(&self, path: &(impl AsRef<Path> + ?Sized)) -> Result<()> {
+        let path = path.as_ref();
+        let mut f = BufWriter::new(fs::File::create(path)?);
+        f.write_all(&npy_header(self.ngrid))?;
+        for v in self.chg[0].iter() {       // iter() visits in logical (C) order
+            f.write_f64::<LittleEndian>(*v)?;
+        }
+        f.flush()?;
+
+        let pos = self.pos.clone().normalize();
+        let meta = serde_json::json!({
+            "grid_shape":       self.ngrid,
+            "order":            "C",
+            "data_type":        format!("{:?}", self.chgtype),
+            "unit":             match self.chgtype {
+                ChargeType::Chgcar | ChargeType::TrueDensity => "e/A^3",
+                ChargeType::Locpot => "eV",
+            },
+            "comment":          pos.comment,
+            "lattice":          pos.cell,
+            "volume":           pos.get_volume(),
+            "species":          pos.ion_types,
+            "counts":           pos.ions_per_type,
+            "positions_frac":   pos.pos_frac,
+        });
+        fs::write(path.with_extension("json"), serde_json::to_string_pretty(&meta)?)?;
+
+        Ok(())
+    }
+
+
+    /// Planar average of the first grid along `axis`, i.e. for each plane perpendicular to
+    /// `axis`, the grid values in this plane are averaged.
+    pub fn planar_average(&self, axis: Axis) -> Vec<f64> {
+        let (a, b) = match axis {
+            Axis::X => (2, 1),
+            Axis::Y => (2, 0),
+            Axis::Z => (1, 0),
+        };
+
+        self.chg[0]
+            .mean_axis(ndarray::Axis(a)).unwrap()
+            .mean_axis(ndarray::Axis(b)).unwrap()
+            .to_vec()
+    }
+
+
+    /// Same as [`ChargeDensity::planar_average`], but the grid is never loaded into memory.
+    ///
+    /// The values of the first grid are read one by one and accumulated into the sum of the plane
+    /// they belong to, thus the memory usage is `O(N_axis)`. VASP writes the grid in Fortran order,
+    /// i.e. `x` is the fastest index and `z` the slowest:
+    ///
+    /// ```text
+    /// WRITE(IU, *) (((RHO(NX, NY, NZ), NX=1,NGXF), NY=1,NGYF), NZ=1,NGZF)
+    /// ```
+    ///
+    /// then the `n`-th value (starts from 0) is at `(n % NX, n / NX % NY, n / (NX * NY))`.
+    pub fn planar_average_from_file(path: &(impl AsRef<Path> + ?Sized), chgtype: ChargeType, axis: Axis) -> Result<Vec<f64>> {
+        let (pos, ngrid, lines) = Self::open_with_header(path)?;
+
+        let iaxis = match axis {
+            Axis::X => 0usize,
+            Axis::Y => 1usize,
+            Axis::Z => 2usize,
+        };
+        let total = ngrid.iter().product::<usize>();
+        let mut sums = vec![0.0f64; ngrid[iaxis]];
+        let mut n = 0usize;
+
+        for line in lines {
+            let line = line?;
+            for s in line.split_whitespace() {
+                let v = s.parse::<f64>()
+                    .with_context(|| format!("[CHG]: Cannot parse {} into float number", s))?;
+                let idx = [n % ngrid[0], n / ngrid[0] % ngrid[1], n / (ngrid[0] * ngrid[1])];
+                sums[idx[iaxis]] += v;
+
+                n += 1;
+                if n == total {
+                    break;
+                }
+            }
+            if n == total {
+                break;
+            }
+        }
+
+        if n < total {
+            bail!("[CHG]: Incomplete grid data, {} values expected but only {} found.", total, n);
+        }
+
+        let nplane = (total / ngrid[iaxis]) as f64;
+        let factor = match chgtype {
+            ChargeType::Chgcar => 1.0 / (nplane * pos.get_volume()),
+            ChargeType::Locpot | ChargeType::TrueDensity => 1.0 / nplane,
+        };
+
+        Ok(sums.into_iter().map(|x| x * factor).collect())
+    }
+
+
+    /// Real space grid spacing along each lattice vector in Angstrom, i.e. `|a_i| / N_i`.
+    pub fn grid_spacing(&self) -> [f64; 3] {
+        let (lengths, _) = self.pos.get_cell_params();
+        [
+            lengths[0] / self.ngrid[0] as f64,
+            lengths[1] / self.ngrid[1] as f64,
+            lengths[2] / self.ngrid[2] as f64,
+        ]
+    }
+
+
+    /// Dipole moment `∫ r·ρ(r) dV` of the first grid in e·Å, with the cell's corner as origin.
+    ///
+    /// See [`ChargeDensity::dipole_moment_with_origin`] for the details.
+    pub fn dipole_moment(&self) -> [f64; 3] {
+        self.dipole_moment_with_origin([0.0; 3])
+    }
+
+
+    /// Dipole moment `∫ r·ρ(r) dV` of the first grid in e·Å, `r` is measured from `origin` in
+    /// fractional coordinates.
+    ///
+    /// The density is taken as it is, i.e. positive for electrons, and the ions are not included.
+    ///
+    /// Note: the dipole moment of a periodic system is ill-defined, it depends on which cell is
+    /// chosen to integrate over. Here the cell spans from `origin` to `origin + (1, 1, 1)` in
+    /// fractional coordinates, thus a grid point at fractional coordinate `f` contributes with
+    /// `r = ((f - origin) mod 1) · A`. Choose `origin` in the vacuum region for slab models.
+    pub fn dipole_moment_with_origin(&self, origin: [f64; 3]) -> [f64; 3] {
+        let dv = self.pos.get_volume().abs() / self.ngrid.iter().product::<usize>() as f64;
+
+        let mut dipole = [0.0f64; 3];
+        for ((i, j, k), rho) in self.chg[0].indexed_iter() {
+            let frac = self.grid_to_frac([i, j, k]);
+            let frac = [
+                (frac[0] - origin[0]).rem_euclid(1.0),
+                (frac[1] - origin[1]).rem_euclid(1.0),
+                (frac[2] - origin[2]).rem_euclid(1.0),
+            ];
+            let r = frac_to_cart(frac, &self.pos.cell, self.pos.scale);
+            for (d, x) in dipole.iter_mut().zip(r.iter()) {
+                *d += x * rho * dv;
+            }
+        }
+
+        dipole
+    }
+
+
+    /// Make the data written as the true density `rho` (e/A^3) instead of VASP's convention
+    /// `rho * V_cell`, the in-memory data is unchanged.
+    ///
+    /// The written file is NOT a standard VASP CHGCAR anymore, thus the title is labelled and the
+    /// augmentation part is dropped. Reading it back requires `ChargeType::TrueDensity`, i.e. data
+    /// as is.
+    pub fn into_true_density(mut self) -> Self {
+        if self.chgtype == ChargeType::Chgcar {
+            self.chgtype = ChargeType::TrueDensity;
+            self.pos.comment = format!("True density (e/A^3), not a VASP CHGCAR: {}", self.pos.comment);
+            self.aug.clear();
+        }
+        self
+    }
+
+
+    /// Fold a supercell made of `n[0] x n[1] x n[2]` primitive cells back to the primitive cell.
+    ///
+    /// Every data set is averaged over the equivalent points of all the primitive cells, thus the
+    /// grid shrinks to `ngrid / n`, the lattice vectors are divided by `n`, and only the atoms
+    /// in the first primitive cell are kept. The augmentation part is dropped since it is per
+    /// atom.
+    ///
+    /// The grid dimensions must be divisible by `n`, and the number of atoms must be divisible by
+    /// `n[0] * n[1] * n[2]`.
+    pub fn fold_supercell(&self, n: [usize; 3]) -> Result<Self> {
+        if n.contains(&0) || self.ngrid.iter().zip(n.iter()).any(|(g, r)| g % r != 0) {
+            bail!("[CHG]: Grid dimensions {:?} are not divisible by the repetition {:?}.", self.ngrid, n);
+        }
+        let ncells = n.iter().product::<usize>();
+        let m = [self.ngrid[0] / n[0], self.ngrid[1] / n[1], self.ngrid[2] / n[2]];
+
+        let chg = self.chg.iter()
+            .map(|grid| {
+                let mut folded = Array3::<f64>::zeros(m.f());
+                for ((i, j, k), v) in grid.indexed_iter() {
+                    folded[[i % m[0], j % m[1], k % m[2]]] += v;
+                }
+                folded / ncells as f64
+            })
+            .collect::<Vec<_>>();
+
+        // Keep the atoms in the first primitive cell, the coordinates are rescaled to it.
+        let mut pos = self.pos.clone();
+        for (row, r) in pos.cell.iter_mut().zip(n.iter()) {
+            row.iter_mut().for_each(|x| *x /= *r as f64);
+        }
+
+        let symbols = self.pos.get_atom_symbols();
+        let mut ion_types = Vec::<String>::new();
+        let mut ions_per_type = Vec::<i32>::new();
+        let mut pos_frac = Vec::<[f64; 3]>::new();
+        let mut constraints = Vec::<[bool; 3]>::new();
+
+        for (iatom, (symbol, frac)) in symbols.iter().zip(self.pos.pos_frac.iter()).enumerate() {
+            let mut new_frac = [0.0f64; 3];
+            let mut in_first_cell = true;
+            for ((f, x), r) in new_frac.iter_mut().zip(frac.iter()).zip(n.iter()) {
+                let r = *r as f64;
+                let mut x = x.rem_euclid(1.0) * r;
+                if r - x < 1E-6 {      // on the upper boundary, equivalent to the origin
+                    x = 0.0;
+                }
+                in_first_cell &= x < 1.0 - 1E-6;
+                *f = x;
+            }
+            if !in_first_cell {
+                continue;
+            }
+
+            if ion_types.last() == Some(symbol) {
+                *ions_per_type.last_mut().unwrap() += 1;
+            } else {
+                ion_types.push(symbol.clone());
+                ions_per_type.push(1);
+            }
+            pos_frac.push(new_frac);
+            if let Some(c) = self.pos.constraints.as_ref() {
+                constraints.push(c[iatom]);
+            }
+        }
+
+        if pos_frac.len() * ncells != symbols.len() {
+            bail!("[CHG]: {} of {} atoms found in the first primitive cell, the structure may not be a {}x{}x{} supercell.",
+                  pos_frac.len(), symbols.len(), n[0], n[1], n[2]);
+        }
+
+        pos.pos_cart = Poscar::convert_frac_to_cart(&pos_frac, &pos.cell);
+        pos.pos_frac = pos_frac;
+        pos.ion_types = ion_types;
+        pos.ions_per_type = ions_per_type;
+        pos.constraints = self.pos.constraints.as_ref().map(|_| constraints);
+
+        Ok(Self {
+            chgtype: self.chgtype,
+            pos,
+            ngrid: m,
+            chg,
+            aug: vec![],
+        })
+    }
+
+
+    /// Interpolate all the data sets onto a grid of `dims` by trilinear interpolation with
+    /// periodic boundary condition, e.g. to combine densities from runs with different
+    /// NGXF/NGYF/NGZF. The augmentation part is kept since it doesn't depend on the grid.
+    pub fn interpolate_to(&self, dims: [usize; 3]) -> Result<Self> {
+        if dims.contains(&0) {
+            bail!("[CHG]: Invalid grid dimensions to interpolate to: {:?}", dims);
+        }
+
+        let [nx, ny, nz] = dims;
+        let chg = self.chg.iter()
+            .map(|c| Array3::from_shape_fn((nx, ny, nz).f(), |(i, j, k)| {
+                trilinear(c, [i as f64 / nx as f64, j as f64 / ny as f64, k as f64 / nz as f64])
+            }))
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            chgtype: self.chgtype,
+            pos: self.pos.clone(),
+            ngrid: dims,
+            chg,
+            aug: self.aug.clone(),
+        })
+    }
+
+
+    /// Take one spin channel as a single data set, the augmentation part is kept for `total` and
+    /// `mag` only. All channels other than `total` require exactly two data sets, i.e. total and
+    /// magnetization densities of a spin-polarized calculation.
+    pub fn spin_channel(&self, channel: SpinChannel) -> Result<Self> {
+        if channel != SpinChannel::Total && self.chg.len() != 2 {
+            bail!("[CHG]: Spin channel `{:?}` requires a spin-polarized (ISPIN = 2) file with 2 data sets, but {} found.",
+                  channel, self.chg.len());
+        }
+
+        let (chg, aug) = match channel {
+            SpinChannel::Total => (self.chg[0].clone(), self.aug.first().cloned()),
+            SpinChannel::Mag   => (self.chg[1].clone(), self.aug.get(1).cloned()),
+            SpinChannel::Up    => ((&self.chg[0] + &self.chg[1]) * 0.5, None),
+            SpinChannel::Down  => ((&self.chg[0] - &self.chg[1]) * 0.5, None),
+        };
+
+        Ok(Self {
+            chgtype: self.chgtype,
+            pos: self.pos.clone(),
+            ngrid: self.ngrid,
+            chg: vec![chg],
+            aug: aug.into_iter().collect(),
+        })
+    }
+
+
+    /// Check whether `other` can be combined with `self` point by point, i.e. they have the same
+    /// type, lattice, grid dimensions and count of data sets. The error tells how they differ.
+    pub fn check_compatible(&self, other: &Self) -> Result<()> {
+        if self.chgtype != other.chgtype {
+            bail!("[CHG]: Different types of charge densities: {:?} != {:?}", self.chgtype, other.chgtype);
+        }
+        if self.ngrid != other.ngrid {
+            bail!("[CHG]: Different grid dimensions: {:?} != {:?}", self.ngrid, other.ngrid);
+        }
+        let cell_a = self.pos.clone().normalize().cell;
+        let cell_b = other.pos.clone().normalize().cell;
+        if !mat33_approx_eq(&cell_a, &cell_b) {
+            bail!("[CHG]: Different lattices: {:?} != {:?}", cell_a, cell_b);
+        }
+        if self.chg.len() != other.chg.len() {
+            bail!("[CHG]: Different counts of data sets: {} != {}", self.chg.len(), other.chg.len());
+        }
         Ok(())
     }
 
 
+    /// Point-wise envelope of two charge densities: at each grid point the value with larger
+    /// magnitude is kept, e.g. `max_abs(3, -5) = -5`. They must pass
+    /// [`ChargeDensity::check_compatible`]. The structure of `self` is kept and the augmentation
+    /// part is dropped.
+    pub fn max_abs(mut self, other: Self) -> Result<Self> {
+        self.check_compatible(&other)?;
+
+        self.chg.iter_mut().zip(other.chg.iter())
+            .for_each(|(x, y)| Zip::from(x).and(y).for_each(|a, b| if b.abs() > a.abs() { *a = *b }));
+        self.pos.comment = "Envelope of charge densities. Produced by rsgrad".to_string();
+        self.aug.clear();
+        Ok(self)
+    }
+
+
+    /// Point-wise absolute value of every data set, e.g. `|A - B|` as a map of where the density
+    /// changed. The augmentation part is dropped since its absolute value is meaningless.
+    pub fn abs(mut self) -> Self {
+        self.chg.iter_mut().for_each(|c| c.mapv_inplace(f64::abs));
+        self.aug.clear();
+        self
+    }
+
+
+    /// Multiply every data set by `factor`, e.g. to rescale the occupancy of a PARCHG. The
+    /// lattice, atoms and augmentation part are kept unchanged.
+    pub fn scale(mut self, factor: f64) -> Self {
+        self.chg.iter_mut().for_each(|c| *c *= factor);
+        self
+    }
+
+
+    /// Integral `∫ ρ dV` of the first grid over the cell, e.g. the number of electrons for a
+    /// CHGCAR. The cell volume is the absolute value of the lattice determinant, thus any cell
+    /// shape and handedness is fine.
+    pub fn integrate(&self) -> f64 {
+        let dv = self.pos.get_volume().abs() / self.ngrid.iter().product::<usize>() as f64;
+        self.chg[0].sum() * dv
+    }
+
+
+    /// Integral `∫ |ρ| dV` of the first grid over the cell, e.g. the amount of charge
+    /// redistribution of a charge density difference.
+    pub fn integrate_abs(&self) -> f64 {
+        let dv = self.pos.get_volume().abs() / self.ngrid.iter().product::<usize>() as f64;
+        self.chg[0].iter().map(|x| x.abs()).sum::<f64>() * dv
+    }
+
+
+    /// Subtract the mean value from every point of the first grid, thus it integrates to zero.
+    /// The removed mean is returned.
+    pub fn remove_mean(&mut self) -> f64 {
+        let mean = self.chg[0].mean().unwrap_or(0.0);
+        self.chg[0] -= mean;
+        mean
+    }
+
+
+    /// Fractional coordinates of the grid point with index `idx`.
+    pub fn grid_to_frac(&self, idx: [usize; 3]) -> [f64; 3] {
+        [
+            idx[0] as f64 / self.ngrid[0] as f64,
+            idx[1] as f64 / self.ngrid[1] as f64,
+            idx[2] as f64 / self.ngrid[2] as f64,
+        ]
+    }
+
+
+    /// Value of the first grid at fractional coordinate `frac`, by trilinear interpolation with
+    /// periodic boundary condition.
+    pub fn value_at_frac(&self, frac: [f64; 3]) -> f64 {
+        trilinear(&self.chg[0], frac)
+    }
+
+
+    /// Sample the first grid along the line from `start` to `end` (both in fractional
+    /// coordinates and included) with `nsample` evenly spaced points. The distances to `start`
+    /// in Angstrom and the interpolated values are returned.
+    pub fn line_profile(&self, start: [f64; 3], end: [f64; 3], nsample: usize) -> (Vec<f64>, Vec<f64>) {
+        let delta = [end[0] - start[0], end[1] - start[1], end[2] - start[2]];
+        let length = {
+            let r = frac_to_cart(delta, &self.pos.cell, self.pos.scale);
+            (r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt()
+        };
+
+        (0 .. nsample)
+            .map(|i| {
+                let t = if nsample > 1 { i as f64 / (nsample - 1) as f64 } else { 0.0 };
+                let frac = [
+                    start[0] + t * delta[0],
+                    start[1] + t * delta[1],
+                    start[2] + t * delta[2],
+                ];
+                (t * length, self.value_at_frac(frac))
+            })
+            .unzip()
+    }
+
+
+    /// Grid index and value of the maximum of the first grid.
+    pub fn max_point(&self) -> Option<([usize; 3], f64)> {
+        self.chg[0].indexed_iter()
+            .map(|((i, j, k), v)| ([i, j, k], *v))
+            .max_by(|x, y| x.1.total_cmp(&y.1))
+    }
+
+
+    /// Grid index and value of the minimum of the first grid.
+    pub fn min_point(&self) -> Option<([usize; 3], f64)> {
+        self.chg[0].indexed_iter()
+            .map(|((i, j, k), v)| ([i, j, k], *v))
+            .min_by(|x, y| x.1.total_cmp(&y.1))
+    }
+
+
+    /// Index and distance (in Angstrom) of the atom nearest to `frac`, periodic images are taken
+    /// into account. `None` is returned if there is no atom.
+    pub fn nearest_atom(&self, frac: [f64; 3]) -> Option<(usize, f64)> {
+        self.nearest_atom_with(&self.pos, frac)
+    }
+
+
+    /// Same as `nearest_atom`, but the atoms are taken from `pos` instead of `self.pos`, e.g. the
+    /// structure of one input of a sum, whose `pos` lists the atoms of all inputs. `pos` should
+    /// share the lattice of `self`.
+    pub fn nearest_atom_with(&self, pos: &Poscar, frac: [f64; 3]) -> Option<(usize, f64)> {
+        let cell = &pos.cell;
+        let scale = pos.scale;
+
+        pos.pos_frac.iter()
+            .enumerate()
+            .map(|(iatom, atom)| {
+                let d = [
+                    frac[0] - atom[0] - (frac[0] - atom[0]).round(),
+                    frac[1] - atom[1] - (frac[1] - atom[1]).round(),
+                    frac[2] - atom[2] - (frac[2] - atom[2]).round(),
+                ];
+
+                // The minimum image may not be the nearest one in skewed cells, search the neighbours.
+                let mut dist = f64::INFINITY;
+                for a in -1 ..= 1 {
+                    for b in -1 ..= 1 {
+                        for c in -1 ..= 1 {
+                            let image = [d[0] + a as f64, d[1] + b as f64, d[2] + c as f64];
+                            let r = frac_to_cart(image, cell, scale);
+                            dist = dist.min((r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt());
+                        }
+                    }
+                }
+
+                (iatom, dist)
+            })
+            .min_by(|x, y| x.1.total_cmp(&y.1))
+    }
+
+
+    /// Integrated charge of each atom by nearest-atom (Voronoi) partition of the first grid,
+    /// i.e. every grid point is assigned to its nearest atom, periodic images included, then the
+    /// density of the points of each atom is integrated.
+    ///
+    /// Note: this is a cheap approximation, not a Bader analysis, the boundaries between atoms
+    /// are planes in the middle of them regardless of the density and the atomic sizes.
+    pub fn voronoi_charges(&self) -> Vec<f64> {
+        self.voronoi_charges_with(&self.pos)
+    }
+
+
+    /// Same as `voronoi_charges`, but the grid is partitioned among the atoms of `pos` instead of
+    /// `self.pos`, see `nearest_atom_with`.
+    pub fn voronoi_charges_with(&self, pos: &Poscar) -> Vec<f64> {
+        let natoms = pos.pos_frac.len();
+        let dv = self.pos.get_volume().abs() / self.ngrid.iter().product::<usize>() as f64;
+        let [_, ny, nz] = self.ngrid;
+
+        (0 .. self.ngrid[0]).into_par_iter()
+            .map(|i| {
+                let mut charges = vec![0.0f64; natoms];
+                for j in 0 .. ny {
+                    for k in 0 .. nz {
+                        if let Some((iatom, _)) = self.nearest_atom_with(pos, self.grid_to_frac([i, j, k])) {
+                            charges[iatom] += self.chg[0][[i, j, k]] * dv;
+                        }
+                    }
+                }
+                charges
+            })
+            .reduce(|| vec![0.0f64; natoms], |mut a, b| {
+                a.iter_mut().zip(b).for_each(|(x, y)| *x += y);
+                a
+            })
+    }
+
+
+    /// Average every data set with its image under the symmetry operation `op`, i.e.
+    /// `rho'(f) = (rho(f) + rho(op(f))) / 2`, where `f` is the fractional coordinate.
+    ///
+    /// For an operation of order 2 (e.g. mirror, inversion or 2-fold rotation) the result is
+    /// exactly invariant under `op` when it maps grid points onto grid points, otherwise
+    /// trilinear interpolation is used and the symmetry holds within the interpolation error.
+    /// Whether `op` is compatible with the lattice is not checked. The augmentation part is kept
+    /// untouched.
+    pub fn symmetrize_grid(&self, op: &SymmetryOp) -> Result<Self> {
+        if op.determinant().abs() != 1 {
+            bail!("[CHG]: The determinant of rotation in symmetry operation must be 1 or -1, got {}.", op.determinant());
+        }
+
+        let mut ret = self.clone();
+        for (dst, src) in ret.chg.iter_mut().zip(self.chg.iter()) {
+            let image = Array3::from_shape_fn(src.raw_dim(), |(i, j, k)| {
+                trilinear(src, op.apply(self.grid_to_frac([i, j, k])))
+            });
+            *dst = (src + &image) * 0.5;
+        }
+
+        Ok(ret)
+    }
+
+
     // Read CHGCAR header to get POSCAR info
     fn read_poscar(txt: &str) -> Result<Poscar> {
         Poscar::from_txt(txt)
@@ -262,7 +1227,7 @@ impl fmt::Display for ChargeDensity {
                     })
                     .collect::<Vec<_>>()
             },
-            ChargeType::Locpot => {
+            ChargeType::Locpot | ChargeType::TrueDensity => {
                 self.chg.clone().into_par_iter()
                     .map(|charge| {
                         if charge.is_standard_layout() {
@@ -304,6 +1269,31 @@ impl fmt::Display for ChargeDensity {
 }
 
 
+// Header of NPY format version 1.0 for a C ordered little-endian float64 array, padded to align
+// the data at 64 bytes.
+fn npy_header(shape: [usize; 3]) -> Vec<u8> {
+    let mut dict = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+                           shape[0], shape[1], shape[2]);
+    let unpadded = 10 + dict.len() + 1;     // magic(6) + version(2) + header_len(2) + dict + '\n'
+    dict.extend(std::iter::repeat(' ').take((64 - unpadded % 64) % 64));
+    dict.push('\n');
+
+    let mut ret = b"\x93NUMPY\x01\x00".to_vec();
+    ret.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    ret.extend_from_slice(dict.as_bytes());
+    ret
+}
+
+
+fn frac_to_cart(frac: [f64; 3], cell: &Mat33<f64>, scale: f64) -> [f64; 3] {
+    let mut ret = [0.0f64; 3];
+    for (i, r) in ret.iter_mut().enumerate() {
+        *r = (frac[0] * cell[0][i] + frac[1] * cell[1][i] + frac[2] * cell[2][i]) * scale;
+    }
+    ret
+}
+
+
 fn mat33_approx_eq(ma: &Mat33<f64>, mb: &Mat33<f64>) -> bool {
     ma.iter().flatten()
         .zip(mb.iter().flatten())
@@ -460,6 +1450,48 @@ impl Sub for ChargeDensity {
 }
 
 
+impl Mul<f64> for ChargeDensity {
+    type Output=Self;
+
+    // Scale all the data sets by `rhs`. The augmentation part is dropped because it is kept as
+    // raw text, the same as `Add` and `Sub`.
+    fn mul(self, rhs: f64) -> Self::Output {
+        let mut ret = self.scale(rhs);
+        ret.aug.clear();
+        ret
+    }
+}
+
+
+// Test fixture shared by the tests of volumetric data: CHGCAR of a cubic cell with edge `a` and
+// one H atom at the origin, `chg` is the only data set.
+#[cfg(test)]
+pub(crate) fn cubic_density(a: f64, chg: Array3<f64>) -> ChargeDensity {
+    let pos = Poscar {
+        comment: "cubic cell".to_string(),
+        scale: 1.0,
+        cell: [[a, 0.0, 0.0], [0.0, a, 0.0], [0.0, 0.0, a]],
+        ion_types: vec!["H".to_string()],
+        ions_per_type: vec![1],
+        pos_cart: vec![[0.0; 3]],
+        pos_frac: vec![[0.0; 3]],
+        constraints: None,
+    };
+    let ngrid = {
+        let s = chg.shape();
+        [s[0], s[1], s[2]]
+    };
+
+    ChargeDensity {
+        chgtype: ChargeType::Chgcar,
+        pos,
+        ngrid,
+        chg: vec![chg],
+        aug: vec![],
+    }
+}
+
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -532,6 +1564,17 @@ Direct
 ";
 
 
+    #[test]
+    fn test_decode_with_lossy_title() {
+        let txt = ChargeDensity::decode_with_lossy_title(b"Li \xff\n 1.0\n".to_vec()).unwrap();
+        assert_eq!(txt, "Li \u{FFFD}\n 1.0\n");
+
+        let txt = ChargeDensity::decode_with_lossy_title(SAMPLE_CHGCAR.as_bytes().to_vec()).unwrap();
+        assert_eq!(txt, SAMPLE_CHGCAR);
+
+        assert!(ChargeDensity::decode_with_lossy_title(b"Li\n 1.0\xff\n".to_vec()).is_err());
+    }
+
     #[test]
     fn test_read_poscar() {
         ChargeDensity::read_poscar(SAMPLE_CHGCAR).unwrap();
@@ -574,6 +1617,566 @@ Direct
         assert_eq!(chg.aug.len(), 0);
     }
 
+    #[test]
+    fn test_scale() {
+        let chg = ChargeDensity::from_txt(SAMPLE_CHGCAR, ChargeType::Chgcar).unwrap();
+        let scaled = chg.clone().scale(2.0);
+        assert!((scaled.integrate() - 2.0 * chg.integrate()).abs() < 1E-10);
+        assert_eq!(scaled.chg[1], &chg.chg[1] * 2.0);
+        assert_eq!(scaled.pos.cell, chg.pos.cell);
+        assert_eq!(scaled.pos.pos_frac, chg.pos.pos_frac);
+        assert_eq!(scaled.aug, chg.aug);
+
+        assert!((chg * -0.5).aug.is_empty());
+    }
+
+    #[test]
+    fn test_dipole_moment() {
+        // A point charge of 2 electrons at fractional coordinate (0.3, 0, 0.5) in a 10 A cubic cell.
+        let dv = 1.0;   // 1000 A^3 / 1000 grid points
+        let mut grid = Array3::<f64>::zeros((10, 10, 10));
+        grid[[3, 0, 5]] = 2.0 / dv;
+        let chg = cubic_density(10.0, grid);
+
+        let approx_eq = |a: [f64; 3], b: [f64; 3]| a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1E-9);
+
+        let dipole = chg.dipole_moment();
+        assert!(approx_eq(dipole, [6.0, 0.0, 10.0]), "{:?}", dipole);
+
+        let dipole = chg.dipole_moment_with_origin([0.1, 0.0, 0.0]);
+        assert!(approx_eq(dipole, [4.0, 0.0, 10.0]), "{:?}", dipole);
+
+        // The charge is wrapped into the cell starting from origin
+        let dipole = chg.dipole_moment_with_origin([0.5, 0.0, 0.5]);
+        assert!(approx_eq(dipole, [16.0, 0.0, 0.0]), "{:?}", dipole);
+
+        // A left-handed cell has negative volume, the charge keeps its sign
+        let mut chg = chg;
+        chg.pos.cell[2] = [0.0, 0.0, -10.0];
+        let dipole = chg.dipole_moment();
+        assert!(approx_eq(dipole, [6.0, 0.0, -10.0]), "{:?}", dipole);
+    }
+
+    #[test]
+    fn test_extrema_nearest_atom() {
+        let mut grid = Array3::<f64>::zeros((10, 10, 10));
+        grid[[9, 0, 0]] = 3.0;
+        grid[[5, 5, 5]] = -1.0;
+        let chg = cubic_density(10.0, grid);
+
+        let (imax, vmax) = chg.max_point().unwrap();
+        assert_eq!(imax, [9, 0, 0]);
+        assert_eq!(vmax, 3.0);
+        assert_eq!(chg.grid_to_frac(imax), [0.9, 0.0, 0.0]);
+
+        // the nearest one is the periodic image of the atom at origin
+        let (iatom, dist) = chg.nearest_atom(chg.grid_to_frac(imax)).unwrap();
+        assert_eq!(iatom, 0);
+        assert!((dist - 1.0).abs() < 1E-9, "{}", dist);
+
+        let (imin, vmin) = chg.min_point().unwrap();
+        assert_eq!(imin, [5, 5, 5]);
+        assert_eq!(vmin, -1.0);
+        let (_, dist) = chg.nearest_atom(chg.grid_to_frac(imin)).unwrap();
+        assert!((dist - 75.0f64.sqrt()).abs() < 1E-9, "{}", dist);
+    }
+
+    #[test]
+    fn test_line_profile() {
+        // rho = 2 * x (in A) along x, sampled between grid points needs interpolation
+        let grid = Array3::from_shape_fn((4, 4, 4), |(i, _, _)| 2.0 * i as f64);
+        let chg = cubic_density(4.0, grid);
+
+        let (dist, values) = chg.line_profile([0.0, 0.3, 0.6], [0.75, 0.3, 0.6], 7);
+        assert_eq!(dist.len(), 7);
+        for (d, v) in dist.iter().zip(values.iter()) {
+            assert!((v - 2.0 * d).abs() < 1E-12, "{} {}", d, v);
+        }
+        assert!((dist[6] - 3.0).abs() < 1E-12);
+
+        // periodic wrapping: halfway between the last grid point and the first image
+        assert!((chg.value_at_frac([0.875, 0.0, 0.0]) - 3.0).abs() < 1E-12);
+        assert!((chg.value_at_frac([-0.125, 0.0, 0.0]) - 3.0).abs() < 1E-12);
+
+        let (dist, values) = chg.line_profile([0.25, 0.0, 0.0], [0.5, 0.0, 0.0], 1);
+        assert_eq!((dist, values), (vec![0.0], vec![2.0]));
+    }
+
+    #[test]
+    fn test_into_true_density() {
+        let chg = ChargeDensity::from_txt(SAMPLE_CHGCAR, ChargeType::Chgcar).unwrap();
+        let npoints = chg.ngrid.iter().product::<usize>() as f64;
+        let volume = chg.pos.get_volume();
+        let charge = chg.chg[0].sum() * volume / npoints;
+
+        let txt = chg.clone().into_true_density().to_string();
+        assert!(txt.starts_with("True density (e/A^3), not a VASP CHGCAR"));
+        assert!(!txt.contains("augmentation"));
+
+        let true_density = ChargeDensity::from_txt(&txt, ChargeType::TrueDensity).unwrap();
+        let integrated = true_density.chg[0].sum() * volume / npoints;
+        assert!((integrated - charge).abs() < 1E-8, "{} != {}", integrated, charge);
+
+        // Written to .npy the data is still labelled as a density
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("CHGSUM.npy");
+        chg.clone().into_true_density().to_npy(&path).unwrap();
+        let meta: serde_json::Value = serde_json::from_str(&fs::read_to_string(path.with_extension("json")).unwrap()).unwrap();
+        assert_eq!(meta["unit"], "e/A^3");
+        assert_eq!(meta["data_type"], "TrueDensity");
+
+        // The raw data in CHGCAR integrates to the same charge after divided by the volume
+        let raw = ChargeDensity::from_txt(SAMPLE_CHGCAR, ChargeType::Locpot).unwrap();
+        assert!((raw.chg[0].sum() / npoints - charge).abs() < 1E-8);
+    }
+
+    #[test]
+    fn test_fold_supercell() {
+        let primitive = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i * 12 + j * 4 + k) as f64);
+        let mut supercell = Array3::<f64>::zeros((4, 3, 4));
+        for ((i, j, k), v) in supercell.indexed_iter_mut() {
+            *v = primitive[[i % 2, j, k]];
+        }
+
+        let mut chg = cubic_density(3.0, supercell);
+        chg.pos.cell[0][0] = 6.0;
+        chg.pos.ions_per_type = vec![2];
+        chg.pos.pos_frac = vec![[0.0, 0.5, 0.5], [0.5, 0.5, 0.5]];
+        chg.pos.pos_cart = vec![[0.0, 1.5, 1.5], [3.0, 1.5, 1.5]];
+
+        let folded = chg.fold_supercell([2, 1, 1]).unwrap();
+        assert_eq!(folded.ngrid, [2, 3, 4]);
+        assert_eq!(folded.chg[0], primitive);
+        assert_eq!(folded.pos.cell, [[3.0, 0.0, 0.0], [0.0, 3.0, 0.0], [0.0, 0.0, 3.0]]);
+        assert_eq!(folded.pos.ions_per_type, vec![1]);
+        assert_eq!(folded.pos.pos_frac, vec![[0.0, 0.5, 0.5]]);
+        assert_eq!(folded.pos.pos_cart, vec![[0.0, 1.5, 1.5]]);
+
+        assert!(chg.fold_supercell([3, 1, 1]).is_err());
+        assert!(chg.fold_supercell([0, 1, 1]).is_err());
+        assert!(chg.fold_supercell([1, 3, 1]).is_err());
+    }
+
+    #[test]
+    fn test_to_npy() {
+        let grid = Array3::from_shape_fn((2, 3, 4).f(), |(i, j, k)| (i * 100 + j * 10 + k) as f64);
+        let chg = cubic_density(3.0, grid);
+
+        let dir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = dir.path().join("CHGSUM.npy");
+        chg.to_npy(&path).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[.. 8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+
+        let header = std::str::from_utf8(&bytes[10 .. 10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f8', 'fortran_order': False, 'shape': (2, 3, 4), }"));
+        assert!(header.ends_with('\n'));
+
+        let data = bytes[10 + header_len ..].chunks(8)
+            .map(|x| {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(x);
+                f64::from_le_bytes(b)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(data.len(), chg.ngrid.iter().product::<usize>());
+        assert_eq!(data[1], 1.0);         // [0, 0, 1]
+        assert_eq!(data[4], 10.0);        // [0, 1, 0]
+        assert_eq!(data[12], 100.0);      // [1, 0, 0]
+
+        let meta: serde_json::Value = serde_json::from_str(&fs::read_to_string(path.with_extension("json")).unwrap()).unwrap();
+        assert_eq!(meta["grid_shape"], serde_json::json!([2, 3, 4]));
+        assert_eq!(meta["species"], serde_json::json!(["H"]));
+    }
+
+    #[test]
+    fn test_to_cube_string() {
+        let chg = Array3::from_shape_fn((2, 3, 7), |(i, j, k)| 0.01 * (i * 21 + j * 7 + k) as f64 + 0.05);
+        let mut density = cubic_density(4.0, chg);
+        density.pos.pos_cart = vec![[1.0, 2.0, 3.0]];
+        density.pos.pos_frac = vec![[0.25, 0.5, 0.75]];
+
+        let cube = density.to_cube_string();
+        let lines = cube.lines().collect::<Vec<_>>();
+        let fields = |i: usize| lines[i].split_whitespace().map(|x| x.parse::<f64>().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(fields(2), vec![1.0, 0.0, 0.0, 0.0]);
+        let voxel = [fields(3), fields(4), fields(5)];
+        assert_eq!(voxel.iter().map(|v| v[0] as usize).collect::<Vec<_>>(), vec![2, 3, 7]);
+        assert!((voxel[0][1] - 2.0 / BOHR_IN_A).abs() < 1E-5);
+        assert!((voxel[2][3] - 4.0 / 7.0 / BOHR_IN_A).abs() < 1E-5);
+
+        let atom = fields(6);
+        assert_eq!(atom[0], 1.0);
+        assert!((atom[2] - 1.0 / BOHR_IN_A).abs() < 1E-5);
+        assert!((atom[4] - 3.0 / BOHR_IN_A).abs() < 1E-5);
+
+        // 7 values per row of z: one line with 6 values and one with the rest
+        let data = lines[7 ..].iter()
+            .flat_map(|l| l.split_whitespace().map(|x| x.parse::<f64>().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(data.len(), 2 * 3 * 7);
+        assert_eq!(lines.len(), 7 + 2 * 3 * 2);
+
+        let voxel_volume = voxel[0][1] * voxel[1][2] * voxel[2][3];
+        let integrated = data.iter().sum::<f64>() * voxel_volume;
+        assert!((integrated - density.integrate()).abs() / density.integrate() < 1E-5,
+                "{} != {}", integrated, density.integrate());
+    }
+
+    #[test]
+    fn test_cube_header_and_data() {
+        let chg = Array3::from_shape_fn((3, 4, 5), |(i, j, k)| 0.1 * (i * 20 + j * 5 + k) as f64 + 0.2);
+        let mut density = cubic_density(3.0, chg.clone());
+        density.pos.ion_types = vec!["H".to_string(), "O".to_string()];
+        density.pos.ions_per_type = vec![1, 1];
+        density.pos.pos_cart = vec![[0.0; 3], [1.5, 1.5, 1.5]];
+        density.pos.pos_frac = vec![[0.0; 3], [0.5, 0.5, 0.5]];
+
+        let cube = density.to_cube_string();
+        let lines = cube.lines().collect::<Vec<_>>();
+        let fields = |i: usize| lines[i].split_whitespace().map(|x| x.parse::<f64>().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(fields(2)[0], 2.0);
+        for (i, n) in [3.0, 4.0, 5.0].iter().enumerate() {
+            let v = fields(3 + i);
+            assert_eq!(v[0], *n);
+            assert!((v[1 + i] - 3.0 / n / BOHR_IN_A).abs() < 1E-5, "{:?}", v);
+        }
+        assert_eq!(fields(7)[0], 8.0);
+        assert!((fields(7)[2] - 1.5 / BOHR_IN_A).abs() < 1E-5);
+
+        // Data in e/Bohr^3, z runs fastest: the 8th value is at (0, 1, 2)
+        let data = lines[8 ..].iter()
+            .flat_map(|l| l.split_whitespace().map(|x| x.parse::<f64>().unwrap()))
+            .collect::<Vec<_>>();
+        let expected = chg[[0, 1, 2]] * BOHR_IN_A.powi(3);
+        assert!(((data[7] - expected) / expected).abs() < 1E-4, "{} != {}", data[7], expected);
+        assert!(((data[7] / BOHR_IN_A.powi(3) - chg[[0, 1, 2]]) / chg[[0, 1, 2]]).abs() < 1E-4);
+    }
+
+    #[test]
+    fn test_interpolate_to() {
+        use std::f64::consts::PI;
+        let f = |x: f64, y: f64, z: f64| (2.0 * PI * x).sin() + 0.5 * (2.0 * PI * y).cos() + 0.25 * (2.0 * PI * z).sin();
+        let sampled = |n: usize| Array3::from_shape_fn((n, n, n), |(i, j, k)| {
+            f(i as f64 / n as f64, j as f64 / n as f64, k as f64 / n as f64)
+        });
+
+        // Coarser grid points coincide with the fine ones, the values are exact
+        let coarse = cubic_density(3.0, sampled(8)).interpolate_to([4, 4, 2]).unwrap();
+        assert_eq!(coarse.ngrid, [4, 4, 2]);
+        for ((i, j, k), v) in coarse.chg[0].indexed_iter() {
+            assert!((v - f(i as f64 / 4.0, j as f64 / 4.0, k as f64 / 2.0)).abs() < 1E-12);
+        }
+
+        // Finer grid, including the points between the last and the first (periodic image) ones
+        let fine = cubic_density(3.0, sampled(16)).interpolate_to([24, 24, 24]).unwrap();
+        for idx in [[23, 0, 5], [1, 23, 23], [12, 7, 17], [0, 0, 0]] {
+            let frac = fine.grid_to_frac(idx);
+            let v = fine.chg[0][idx];
+            assert!((v - f(frac[0], frac[1], frac[2])).abs() < 0.05, "{:?}: {}", idx, v);
+        }
+
+        assert!(fine.interpolate_to([0, 4, 4]).is_err());
+    }
+
+    #[test]
+    fn test_integrate_uniform_density() {
+        let mut density = cubic_density(1.0, Array3::from_elem((3, 4, 5), 0.125));
+        density.pos.cell = [[4.0, 0.0, 0.0], [2.0, 3.0, 0.0], [1.0, 1.0, 5.0]];
+        density.pos.scale = 1.2;
+        let volume = 4.0 * 3.0 * 5.0 * 1.2f64.powi(3);
+        assert!((density.integrate() - 0.125 * volume).abs() < 1E-10);
+
+        // left-handed lattice has the same volume
+        density.pos.cell.swap(0, 1);
+        assert!((density.integrate() - 0.125 * volume).abs() < 1E-10);
+
+        density.chg[0][[0, 0, 0]] = -0.125;
+        assert!((density.integrate_abs() - 0.125 * volume).abs() < 1E-10);
+        assert!((density.integrate() - 0.125 * volume * 58.0 / 60.0).abs() < 1E-10);
+    }
+
+    #[test]
+    fn test_mul() {
+        let a = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i + j + k) as f64);
+        let scaled = cubic_density(3.0, a.clone()) * -2.0;
+        assert_eq!(scaled.chg[0], a * -2.0);
+        assert!(scaled.aug.is_empty());
+    }
+
+    #[test]
+    fn test_max_abs() {
+        let mut a = Array3::<f64>::zeros((2, 3, 4));
+        let mut b = Array3::<f64>::zeros((2, 3, 4));
+        a[[0, 1, 2]] = 3.0;
+        a[[1, 2, 3]] = -1.0;
+        b[[0, 1, 2]] = 2.0;
+        b[[1, 2, 3]] = -5.0;
+        b[[1, 0, 0]] = 0.5;
+
+        let envelope = cubic_density(3.0, a).max_abs(cubic_density(3.0, b)).unwrap();
+        assert_eq!(envelope.chg[0][[0, 1, 2]], 3.0);
+        assert_eq!(envelope.chg[0][[1, 2, 3]], -5.0);
+        assert_eq!(envelope.chg[0][[1, 0, 0]], 0.5);
+        assert_eq!(envelope.chg[0].iter().filter(|x| **x != 0.0).count(), 3);
+        assert_eq!(envelope.pos.ions_per_type, vec![1]);
+        assert_eq!(envelope.pos.pos_frac.len(), 1);
+
+        let other = cubic_density(3.0, Array3::<f64>::zeros((2, 3, 5)));
+        assert!(envelope.max_abs(other).is_err());
+    }
+
+    #[test]
+    fn test_abs_difference() {
+        let a = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i * 12 + j * 4 + k) as f64 * 0.1);
+        let b = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| ((i + j + k) % 3) as f64);
+
+        let ab = (cubic_density(2.0, a.clone()) - cubic_density(2.0, b.clone())).unwrap().abs();
+        let ba = (cubic_density(2.0, b) - cubic_density(2.0, a)).unwrap().abs();
+        assert_eq!(ab.chg, ba.chg);
+        assert!(ab.chg[0].iter().all(|x| *x >= 0.0));
+        assert!(ab.chg[0].iter().any(|x| *x > 0.0));
+
+        let expected = ab.chg[0].sum() * 8.0 / 24.0;
+        assert!((ab.integrate() - expected).abs() < 1E-12);
+        assert!((ab.integrate() - ba.integrate()).abs() < 1E-12);
+    }
+
+    #[test]
+    fn test_remove_mean() {
+        let grid = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i + j * k) as f64);
+        let mut chg = cubic_density(3.0, grid.clone());
+        let mean = chg.remove_mean();
+
+        assert!((mean - grid.mean().unwrap()).abs() < 1E-12);
+        assert!(chg.chg[0].sum().abs() < 1E-12);
+        assert!((chg.chg[0][[1, 2, 3]] - (7.0 - mean)).abs() < 1E-12);
+    }
+
+    #[test]
+    fn test_voronoi_charges() {
+        // Two H atoms mirrored by the plane x = 3/8, no grid point is equidistant to them.
+        let grid = Array3::<f64>::from_elem((4, 4, 4), 2.0 / 64.0);
+        let mut chg = cubic_density(4.0, grid);
+        chg.pos.ions_per_type = vec![2];
+        chg.pos.pos_frac = vec![[0.125, 0.0, 0.0], [0.625, 0.0, 0.0]];
+        chg.pos.pos_cart = vec![[0.5, 0.0, 0.0], [2.5, 0.0, 0.0]];
+
+        let charges = chg.voronoi_charges();
+        assert_eq!(charges.len(), 2);
+        assert!((charges[0] - 1.0).abs() < 1E-12, "{:?}", charges);
+        assert!((charges[1] - 1.0).abs() < 1E-12, "{:?}", charges);
+
+        // Move some density from the second atom's region to the first one's.
+        chg.chg[0][[0, 1, 1]] += 0.5;
+        chg.chg[0][[2, 1, 1]] -= 0.5;
+        let charges = chg.voronoi_charges();
+        assert!((charges[0] - 1.5).abs() < 1E-12, "{:?}", charges);
+        assert!((charges[1] - 0.5).abs() < 1E-12, "{:?}", charges);
+
+        // A left-handed cell has negative volume, the charges keep their sign
+        chg.pos.cell[2] = [0.0, 0.0, -4.0];
+        let charges = chg.voronoi_charges();
+        assert!((charges[0] - 1.5).abs() < 1E-12, "{:?}", charges);
+        assert!((charges[1] - 0.5).abs() < 1E-12, "{:?}", charges);
+    }
+
+    #[test]
+    fn test_parse_symmetry_op() {
+        let op = SymmetryOp::from_str("-1 0 0  0 1 0  0 0 1  0.5 0 0").unwrap();
+        assert_eq!(op.rotation, [[-1, 0, 0], [0, 1, 0], [0, 0, 1]]);
+        assert_eq!(op.translation, [0.5, 0.0, 0.0]);
+        assert_eq!(op, SymmetryOp::from_str("-1,0,0,0,1,0,0,0,1,0.5,0,0").unwrap());
+        assert_eq!(op.apply([0.25, 0.5, 0.75]), [0.25, 0.5, 0.75]);
+
+        assert!(SymmetryOp::from_str("1 0 0 0 1 0 0 0 1").is_err());
+        assert!(SymmetryOp::from_str("1 0 0 0 1 0 0 0 1.5 0 0 0").is_err());
+        assert!(SymmetryOp::from_str("1 0 0 0 1 0 0 0 2 0 0 0").is_err());
+    }
+
+    #[test]
+    fn test_symmetrize_grid() {
+        // A field symmetric under the mirror x -> -x, with some noise breaking the symmetry.
+        let n = 6;
+        let clean = Array3::from_shape_fn((n, n, n), |(i, j, k)| {
+            let x = (2.0 * std::f64::consts::PI * i as f64 / n as f64).cos();
+            1.0 + x + 0.1 * j as f64 + 0.01 * k as f64
+        });
+        let mut noisy = clean.clone();
+        noisy[[1, 2, 3]] += 1E-3;
+        noisy[[4, 0, 5]] -= 2E-3;
+
+        let chg = cubic_density(5.0, noisy);
+        let mirror = SymmetryOp::from_str("-1 0 0 0 1 0 0 0 1 0 0 0").unwrap();
+        let sym = chg.symmetrize_grid(&mirror).unwrap();
+
+        for ((i, j, k), v) in sym.chg[0].indexed_iter() {
+            assert!((v - sym.chg[0][[(n - i) % n, j, k]]).abs() < 1E-12);
+            assert!((v - clean[[i, j, k]]).abs() < 2E-3);
+        }
+        assert!((sym.chg[0][[1, 2, 3]] - clean[[1, 2, 3]] - 0.5E-3).abs() < 1E-12);
+        assert!((sym.chg[0][[2, 0, 5]] - clean[[2, 0, 5]] + 1E-3).abs() < 1E-12);
+
+        // Half grid shift needs interpolation
+        let shift = SymmetryOp::from_str("1 0 0 0 1 0 0 0 1 0 0 0.0833333333333333").unwrap();
+        let grid = Array3::from_shape_fn((n, n, n), |(_, _, k)| k as f64);
+        let sym = cubic_density(5.0, grid).symmetrize_grid(&shift).unwrap();
+        assert!((sym.chg[0][[0, 0, 1]] - 1.25).abs() < 1E-9);
+    }
+
+    #[test]
+    fn test_planar_average_streaming() {
+        let tmpdir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let path = tmpdir.path().join("CHGCAR");
+        fs::write(&path, SAMPLE_CHGCAR).unwrap();
+
+        for chgtype in [ChargeType::Chgcar, ChargeType::Locpot] {
+            let chg = ChargeDensity::from_txt(SAMPLE_CHGCAR, chgtype).unwrap();
+
+            for axis in [Axis::X, Axis::Y, Axis::Z] {
+                let expected = chg.planar_average(axis);
+                let streamed = ChargeDensity::planar_average_from_file(&path, chgtype, axis).unwrap();
+
+                assert_eq!(expected.len(), chg.ngrid[axis as usize]);
+                assert_eq!(expected.len(), streamed.len());
+                for (x, y) in expected.iter().zip(streamed.iter()) {
+                    assert!((x - y).abs() < 1E-12, "{:?} != {:?}", expected, streamed);
+                }
+            }
+        }
+
+        // uniform along z: each xy-plane has the same average
+        let mut grid = Array3::<f64>::zeros((2, 3, 4));
+        for ((i, j, _), v) in grid.indexed_iter_mut() {
+            *v = (i + 2 * j) as f64;
+        }
+        let avg = cubic_density(1.0, grid).planar_average(Axis::Z);
+        assert_eq!(avg, vec![2.5; 4]);
+    }
+
+    #[test]
+    fn test_planar_average_separable() {
+        // rho(i, j, k) = f(i) * g(j) * h(k), the average along z is mean(f) * mean(g) * h(k)
+        let f = [1.0, 3.0];
+        let g = [0.5, 1.0, 1.5];
+        let h = [0.0, 1.0, 4.0, 9.0, 16.0];
+        let grid = Array3::from_shape_fn((2, 3, 5), |(i, j, k)| f[i] * g[j] * h[k]);
+        let chg = cubic_density(3.0, grid);
+
+        let avg = chg.planar_average(Axis::Z);
+        assert_eq!(avg.len(), 5);
+        for (v, hk) in avg.iter().zip(h.iter()) {
+            assert!((v - 2.0 * 1.0 * hk).abs() < 1E-12);
+        }
+
+        let avg = chg.planar_average(Axis::X);
+        for (v, fi) in avg.iter().zip(f.iter()) {
+            assert!((v - fi * 1.0 * 6.0).abs() < 1E-12);
+        }
+    }
+
+    #[test]
+    fn test_spin_channel() {
+        let up   = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i + j + k) as f64);
+        let down = Array3::from_shape_fn((2, 3, 4), |(i, j, k)| (i * j * k) as f64 * 0.5);
+        let mut chg = cubic_density(2.0, &up + &down);
+        chg.chg.push(&up - &down);
+        chg.aug = vec!["total aug\n".to_string(), "mag aug\n".to_string()];
+
+        let total = chg.spin_channel(SpinChannel::Total).unwrap();
+        assert_eq!(total.chg, vec![&up + &down]);
+        assert_eq!(total.aug, vec!["total aug\n".to_string()]);
+
+        let mag = chg.spin_channel(SpinChannel::Mag).unwrap();
+        assert_eq!(mag.chg, vec![&up - &down]);
+        assert_eq!(mag.aug, vec!["mag aug\n".to_string()]);
+
+        let derived_up = chg.spin_channel(SpinChannel::Up).unwrap();
+        let derived_down = chg.spin_channel(SpinChannel::Down).unwrap();
+        assert_eq!(derived_up.chg.len(), 1);
+        assert!(derived_up.aug.is_empty());
+        assert!(derived_down.aug.is_empty());
+        for ((u, d), (eu, ed)) in derived_up.chg[0].iter().zip(derived_down.chg[0].iter())
+            .zip(up.iter().zip(down.iter())) {
+            assert!((u - eu).abs() < 1E-12);
+            assert!((d - ed).abs() < 1E-12);
+        }
+
+        let unpolarized = cubic_density(2.0, up);
+        assert!(unpolarized.spin_channel(SpinChannel::Total).is_ok());
+        assert!(unpolarized.spin_channel(SpinChannel::Up).is_err());
+        assert!(unpolarized.spin_channel(SpinChannel::Mag).is_err());
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let tmpdir = tempdir::TempDir::new("rsgrad_test").unwrap();
+        let plain = tmpdir.path().join("CHGCAR");
+        let gz = tmpdir.path().join("CHGCAR.gz");
+
+        let chg = ChargeDensity::from_txt(SAMPLE_CHGCAR, ChargeType::Chgcar).unwrap();
+        chg.to_file(&plain).unwrap();
+        chg.to_file(&gz).unwrap();
+        assert!(fs::read(&gz).unwrap().starts_with(&GZIP_MAGIC));
+        assert!(!fs::read(&plain).unwrap().starts_with(&GZIP_MAGIC));
+
+        let from_plain = ChargeDensity::from_file(&plain, ChargeType::Chgcar).unwrap();
+        let from_gz = ChargeDensity::from_file(&gz, ChargeType::Chgcar).unwrap();
+        assert_eq!(from_gz.ngrid, chg.ngrid);
+        assert_eq!(from_gz.chg, from_plain.chg);
+        assert_eq!(from_gz.aug, from_plain.aug);
+        assert_eq!(from_gz.pos.cell, from_plain.pos.cell);
+
+        assert_eq!(ChargeDensity::planar_average_from_file(&gz, ChargeType::Chgcar, Axis::Z).unwrap(),
+                   ChargeDensity::planar_average_from_file(&plain, ChargeType::Chgcar, Axis::Z).unwrap());
+
+        for path in [&plain, &gz] {
+            let (pos, ngrid) = ChargeDensity::read_header(path).unwrap();
+            assert_eq!(ngrid, chg.ngrid);
+            assert_eq!(pos.cell, from_plain.pos.cell);
+        }
+
+        let cube = tmpdir.path().join("CHGCAR.cube.gz");
+        assert_eq!(chg.to_file_auto(&cube).unwrap(), GridFormat::Cube);
+        let mut txt = String::new();
+        MultiGzDecoder::new(fs::File::open(&cube).unwrap()).read_to_string(&mut txt).unwrap();
+        assert_eq!(txt, chg.to_cube_string());
+    }
+
+    #[test]
+    fn test_grid_format_from_path() {
+        let format = |p: &str| GridFormat::from_path(p).unwrap();
+
+        assert_eq!(format("CHGSUM.vasp"),          (GridFormat::Vasp, false));
+        assert_eq!(format("run/CHGCAR"),           (GridFormat::Vasp, false));
+        assert_eq!(format("PARCHG.0010.ALLK"),     (GridFormat::Vasp, false));
+        assert_eq!(format("CHGCAR.gz"),            (GridFormat::Vasp, true));
+        assert_eq!(format("CHGSUM.vasp.GZ"),       (GridFormat::Vasp, true));
+        assert_eq!(format("CHGSUM.cube"),          (GridFormat::Cube, false));
+        assert_eq!(format("run/CHGSUM.Cube.gz"),   (GridFormat::Cube, true));
+        assert_eq!(format("CHGSUM.npy"),           (GridFormat::Npy,  false));
+        assert_eq!(format("cube.gz"),              (GridFormat::Vasp, true));
+        assert_eq!(format(".gz"),                  (GridFormat::Vasp, false));
+
+        assert!(GridFormat::from_path("CHGSUM.npy.gz").is_err());
+    }
+
+    #[test]
+    fn test_grid_spacing() {
+        let chg = ChargeDensity::from_txt(SAMPLE_CHGCAR, ChargeType::Chgcar).unwrap();
+        let spacing = chg.grid_spacing();
+        let (lengths, _) = chg.pos.get_cell_params();
+        assert!((spacing[0] - lengths[0] / 2.0).abs() < 1E-12);
+        assert!((spacing[1] - lengths[1] / 3.0).abs() < 1E-12);
+        assert!((spacing[2] - lengths[2] / 4.0).abs() < 1E-12);
+        assert!((spacing[0] - 1.4845362).abs() < 1E-6);
+    }
+
     #[test]
     #[ignore]
     fn test_from_file() {
@@ -0,0 +1,387 @@
+//! On-disk cache of parsed volumetric data.
+//!
+//! Parsing a text CHGCAR dominates the runtime of `chgsum` for large grids, the cache keeps the
+//! parsed [`ChargeDensity`] in a little-endian binary blob which is read back without any text
+//! parsing. Each source file has at most one entry, named after a stable hash of its canonical
+//! path. The canonical path, modification time, size and [`ChargeType`] of the source are stored
+//! in the blob and checked on reading, thus a changed source file never hits a stale entry, and
+//! the entry is overwritten by the new parse instead of piling up.
+use std::{
+    fs,
+    io::{
+        BufWriter,
+        Cursor,
+        Read,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{
+    Context,
+    bail,
+};
+use byteorder::{
+    LittleEndian,
+    ReadBytesExt,
+    WriteBytesExt,
+};
+use directories::BaseDirs;
+use log::{
+    info,
+    warn,
+};
+use ndarray::{
+    Array3,
+    ShapeBuilder,
+};
+
+use crate::{
+    Result,
+    Poscar,
+    ChargeDensity,
+    ChargeType,
+};
+
+
+// Bumped whenever the layout of the blob changes, the old entries are simply missed.
+const CACHE_MAGIC: &[u8; 8] = b"RSGCHG01";
+
+
+/// Default directory of the cache: `rsgrad` under the user's cache directory, e.g.
+/// `~/.cache/rsgrad` on Linux, or under the temporary directory if it is unknown.
+pub fn default_cache_dir() -> PathBuf {
+    BaseDirs::new()
+        .map(|d| d.cache_dir().to_path_buf())
+        .unwrap_or_else(std::env::temp_dir)
+        .join("rsgrad")
+}
+
+
+impl ChargeDensity {
+    /// Same as [`ChargeDensity::from_file`], but the parsed data is cached in `cache_dir` and
+    /// read back from there if the source file is unchanged, i.e. the same path, modification
+    /// time and size. Otherwise the entry of the path is overwritten.
+    ///
+    /// The cache is best-effort: a broken entry is re-parsed from the source and failing to
+    /// write an entry only warns.
+    pub fn from_file_cached(path: &(impl AsRef<Path> + ?Sized), chgtype: ChargeType, cache_dir: &Path) -> Result<Self> {
+        let path = path.as_ref();
+        let canonical = path.canonicalize()
+            .with_context(|| format!("[CHG]: Cannot access {:?}", path))?;
+        let key = cache_key(&canonical, chgtype)?;
+        let entry = cache_dir.join(format!("{:016x}.chg", fnv1a(canonical.to_string_lossy().as_bytes())));
+
+        if entry.is_file() {
+            match fs::read(&entry).map_err(Into::into).and_then(|bytes| Self::from_cache_bytes(&bytes, &key)) {
+                Ok(Some(chg)) => {
+                    info!("Read cached charge density of {:?} from {:?}", path, entry);
+                    return Ok(chg);
+                },
+                Ok(None) => (),
+                Err(e) => warn!("Broken cache entry {:?} ignored: {}", entry, e),
+            }
+        }
+
+        let chg = Self::from_file(path, chgtype)?;
+        if let Err(e) = chg.write_cache(&entry, &key) {
+            warn!("Failed to write the cache of {:?} to {:?}: {}", path, entry, e);
+        }
+        Ok(chg)
+    }
+
+
+    // Write to a temporary file then rename it, thus concurrent runs never see partial entries.
+    fn write_cache(&self, entry: &Path, key: &str) -> Result<()> {
+        if let Some(dir) = entry.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = entry.with_extension(format!("tmp{}", std::process::id()));
+
+        let mut f = BufWriter::new(fs::File::create(&tmp)?);
+        self.write_cache_bytes(&mut f, key)?;
+        f.flush()?;
+        drop(f);
+
+        fs::rename(&tmp, entry)?;
+        Ok(())
+    }
+
+
+    fn write_cache_bytes(&self, f: &mut impl Write, key: &str) -> Result<()> {
+        f.write_all(CACHE_MAGIC)?;
+        write_str(f, key)?;
+        f.write_u8(match self.chgtype {
+            ChargeType::Chgcar => 0,
+            ChargeType::Locpot => 1,
+            ChargeType::TrueDensity => 2,
+        })?;
+
+        let pos = &self.pos;
+        write_str(f, &pos.comment)?;
+        f.write_f64::<LittleEndian>(pos.scale)?;
+        for v in pos.cell.iter().flatten() {
+            f.write_f64::<LittleEndian>(*v)?;
+        }
+        f.write_u64::<LittleEndian>(pos.ion_types.len() as u64)?;
+        for (t, n) in pos.ion_types.iter().zip(pos.ions_per_type.iter()) {
+            write_str(f, t)?;
+            f.write_i32::<LittleEndian>(*n)?;
+        }
+        f.write_u64::<LittleEndian>(pos.pos_cart.len() as u64)?;
+        for v in pos.pos_cart.iter().chain(pos.pos_frac.iter()).flatten() {
+            f.write_f64::<LittleEndian>(*v)?;
+        }
+        match pos.constraints.as_ref() {
+            Some(c) => {
+                f.write_u8(1)?;
+                for v in c.iter().flatten() {
+                    f.write_u8(*v as u8)?;
+                }
+            },
+            None => f.write_u8(0)?,
+        }
+
+        for n in self.ngrid {
+            f.write_u64::<LittleEndian>(n as u64)?;
+        }
+        f.write_u64::<LittleEndian>(self.chg.len() as u64)?;
+        for chg in self.chg.iter() {
+            // Fortran order, the same as the text file and the parsed arrays
+            for v in chg.t().iter() {
+                f.write_f64::<LittleEndian>(*v)?;
+            }
+        }
+        f.write_u64::<LittleEndian>(self.aug.len() as u64)?;
+        for aug in self.aug.iter() {
+            write_str(f, aug)?;
+        }
+        Ok(())
+    }
+
+
+    // `None` if the entry is of another source or an outdated one, i.e. the source file changed,
+    // a hash collision or an outdated layout.
+    fn from_cache_bytes(bytes: &[u8], key: &str) -> Result<Option<Self>> {
+        let mut f = Cursor::new(bytes);
+
+        let mut magic = [0u8; 8];
+        f.read_exact(&mut magic)?;
+        if &magic != CACHE_MAGIC || read_str(&mut f)? != key {
+            return Ok(None);
+        }
+
+        let chgtype = match f.read_u8()? {
+            0 => ChargeType::Chgcar,
+            1 => ChargeType::Locpot,
+            2 => ChargeType::TrueDensity,
+            x => bail!("[CHG]: Invalid charge type {} in cache.", x),
+        };
+
+        let comment = read_str(&mut f)?;
+        let scale = f.read_f64::<LittleEndian>()?;
+        let mut cell = [[0.0; 3]; 3];
+        for row in cell.iter_mut() {
+            f.read_f64_into::<LittleEndian>(row)?;
+        }
+        let ntypes = read_len(&mut f)?;
+        let mut ion_types = Vec::with_capacity(ntypes);
+        let mut ions_per_type = Vec::with_capacity(ntypes);
+        for _ in 0 .. ntypes {
+            ion_types.push(read_str(&mut f)?);
+            ions_per_type.push(f.read_i32::<LittleEndian>()?);
+        }
+        let natoms = read_len(&mut f)?;
+        let read_vec3 = |f: &mut Cursor<&[u8]>| -> Result<Vec<[f64; 3]>> {
+            (0 .. natoms).map(|_| {
+                let mut v = [0.0; 3];
+                f.read_f64_into::<LittleEndian>(&mut v)?;
+                Ok(v)
+            }).collect()
+        };
+        let pos_cart = read_vec3(&mut f)?;
+        let pos_frac = read_vec3(&mut f)?;
+        let constraints = match f.read_u8()? {
+            0 => None,
+            _ => Some((0 .. natoms).map(|_| -> Result<[bool; 3]> {
+                Ok([f.read_u8()? != 0, f.read_u8()? != 0, f.read_u8()? != 0])
+            }).collect::<Result<Vec<_>>>()?),
+        };
+        let pos = Poscar { comment, scale, cell, ion_types, ions_per_type, pos_cart, pos_frac, constraints };
+
+        let mut ngrid = [0usize; 3];
+        for n in ngrid.iter_mut() {
+            *n = read_len(&mut f)?;
+        }
+        let ntotal = ngrid.iter()
+            .try_fold(1usize, |acc, n| acc.checked_mul(*n))
+            .filter(|n| n.saturating_mul(8) <= bytes.len())
+            .with_context(|| format!("[CHG]: Grid {:?} in cache is larger than the cache itself.", ngrid))?;
+        let nchg = read_len(&mut f)?;
+        let chg = (0 .. nchg).map(|_| {
+            let mut v = vec![0.0; ntotal];
+            f.read_f64_into::<LittleEndian>(&mut v)?;
+            Ok(Array3::from_shape_vec(ngrid.f(), v)?)
+        }).collect::<Result<Vec<_>>>()?;
+        let naug = read_len(&mut f)?;
+        let aug = (0 .. naug).map(|_| read_str(&mut f)).collect::<Result<Vec<_>>>()?;
+
+        if f.position() != bytes.len() as u64 {
+            bail!("[CHG]: Trailing bytes in cache.");
+        }
+
+        Ok(Some(Self { chgtype, pos, ngrid, chg, aug }))
+    }
+}
+
+
+// Identity of the source file, any change of the file changes its modification time or size.
+fn cache_key(canonical: &Path, chgtype: ChargeType) -> Result<String> {
+    let meta = fs::metadata(canonical)?;
+    let mtime = meta.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    Ok(format!("{:?}|{}|{}|{:?}", canonical, mtime, meta.len(), chgtype))
+}
+
+
+// 64-bit FNV-1a hash, unlike `DefaultHasher` it never changes across Rust releases, thus the
+// entry of a path keeps its name.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+
+fn write_str(f: &mut impl Write, s: &str) -> Result<()> {
+    f.write_u64::<LittleEndian>(s.len() as u64)?;
+    f.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+
+fn read_len(f: &mut Cursor<&[u8]>) -> Result<usize> {
+    let len = f.read_u64::<LittleEndian>()?;
+    if len > f.get_ref().len() as u64 {
+        bail!("[CHG]: Invalid length {} in cache.", len);
+    }
+    Ok(len as usize)
+}
+
+
+fn read_str(f: &mut Cursor<&[u8]>) -> Result<String> {
+    let mut buf = vec![0u8; read_len(f)?];
+    f.read_exact(&mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{
+        Duration,
+        SystemTime,
+    };
+    use tempdir::TempDir;
+    use crate::vasp_parsers::chg::cubic_density;
+
+    fn assert_same(a: &ChargeDensity, b: &ChargeDensity) {
+        assert_eq!(a.chgtype, b.chgtype);
+        assert_eq!(a.ngrid, b.ngrid);
+        assert_eq!(a.chg, b.chg);
+        assert_eq!(a.aug, b.aug);
+        assert_eq!(a.pos.comment, b.pos.comment);
+        assert_eq!(a.pos.scale, b.pos.scale);
+        assert_eq!(a.pos.cell, b.pos.cell);
+        assert_eq!(a.pos.ion_types, b.pos.ion_types);
+        assert_eq!(a.pos.ions_per_type, b.pos.ions_per_type);
+        assert_eq!(a.pos.pos_cart, b.pos.pos_cart);
+        assert_eq!(a.pos.pos_frac, b.pos.pos_frac);
+        assert_eq!(a.pos.constraints, b.pos.constraints);
+        for (x, y) in a.chg.iter().zip(b.chg.iter()) {
+            assert_eq!(x.is_standard_layout(), y.is_standard_layout());
+        }
+    }
+
+    // The shared fixture with every field of the structure filled, to check all of them survive
+    // the blob.
+    fn density(chgtype: ChargeType, offset: f64) -> ChargeDensity {
+        let grid = Array3::from_shape_fn((3, 4, 5), |(i, j, k)| (i * 20 + j * 5 + k) as f64 * 0.01 + offset);
+        let mut chg = cubic_density(3.0, grid.clone());
+        chg.chgtype = chgtype;
+        chg.chg.push(grid * -0.5);
+        chg.pos = Poscar {
+            comment: "cached cell".to_string(),
+            scale: 1.5,
+            cell: [[3.0, 0.0, 0.0], [0.1, 3.0, 0.0], [0.0, 0.2, 4.0]],
+            ion_types: vec!["H".to_string(), "O".to_string()],
+            ions_per_type: vec![2, 1],
+            pos_cart: vec![[0.0; 3], [1.5, 0.5, 0.5], [3.0, 1.0, 2.0]],
+            pos_frac: vec![[0.0; 3], [0.5, 0.1, 0.1], [0.9, 0.3, 0.5]],
+            constraints: Some(vec![[true, false, true], [false; 3], [true; 3]]),
+        }.normalize();
+        chg
+    }
+
+    #[test]
+    fn test_cached_read() {
+        let dir = TempDir::new("rsgrad_test").unwrap();
+        let cache = dir.path().join("cache");
+        let path = dir.path().join("CHGCAR");
+        density(ChargeType::Chgcar, 0.1).to_file(&path).unwrap();
+
+        let fresh = ChargeDensity::from_file(&path, ChargeType::Chgcar).unwrap();
+        let first = ChargeDensity::from_file_cached(&path, ChargeType::Chgcar, &cache).unwrap();
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 1);
+        let cached = ChargeDensity::from_file_cached(&path, ChargeType::Chgcar, &cache).unwrap();
+        assert_same(&fresh, &first);
+        assert_same(&fresh, &cached);
+
+        // The same file read as another type overwrites the entry
+        let locpot = ChargeDensity::from_file_cached(&path, ChargeType::Locpot, &cache).unwrap();
+        assert_same(&ChargeDensity::from_file(&path, ChargeType::Locpot).unwrap(), &locpot);
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 1);
+
+        // Broken entries are re-parsed and overwritten
+        for entry in fs::read_dir(&cache).unwrap() {
+            fs::write(entry.unwrap().path(), b"RSGCHG01broken").unwrap();
+        }
+        assert_same(&fresh, &ChargeDensity::from_file_cached(&path, ChargeType::Chgcar, &cache).unwrap());
+        assert_same(&fresh, &ChargeDensity::from_file_cached(&path, ChargeType::Chgcar, &cache).unwrap());
+    }
+
+    #[test]
+    fn test_cache_invalidation() {
+        let dir = TempDir::new("rsgrad_test").unwrap();
+        let cache = dir.path().join("cache");
+        let path = dir.path().join("CHGCAR");
+        let mtime = SystemTime::now() - Duration::from_secs(3600);
+
+        density(ChargeType::Chgcar, 0.1).to_file(&path).unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+        let old = ChargeDensity::from_file_cached(&path, ChargeType::Chgcar, &cache).unwrap();
+
+        // Same size, only the mtime tells the change
+        let len = fs::metadata(&path).unwrap().len();
+        density(ChargeType::Chgcar, 0.2).to_file(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(mtime + Duration::from_secs(1)).unwrap();
+
+        let new = ChargeDensity::from_file_cached(&path, ChargeType::Chgcar, &cache).unwrap();
+        assert_same(&ChargeDensity::from_file(&path, ChargeType::Chgcar).unwrap(), &new);
+        assert_ne!(old.chg, new.chg);
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
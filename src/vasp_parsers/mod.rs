@@ -4,4 +4,5 @@ pub mod kpoints;
 pub mod procar;
 pub mod potcar;
 pub mod chg;
+pub mod chg_cache;
 pub mod wavecar;
@@ -280,6 +280,15 @@ impl Poscar {
     }
 
 
+    /// Element symbol of each atom, e.g. `["Li", "Li", "O"]` for `Li2O`.
+    pub fn get_atom_symbols(&self) -> Vec<String> {
+        self.ion_types.iter()
+            .zip(self.ions_per_type.iter())
+            .flat_map(|(t, n)| std::iter::repeat(t.clone()).take(*n as usize))
+            .collect()
+    }
+
+
     pub fn get_ntypes(&self) -> i32 {
         self.ion_types.len() as i32
     }
@@ -1,7 +1,13 @@
 use std::{
-    io::prelude::*,
+    io::{
+        prelude::*,
+        BufReader,
+    },
     path::Path,
-    fs::read_to_string,
+    fs::{
+        File,
+        read_to_string,
+    },
 };
 
 use anyhow::{
@@ -16,10 +22,37 @@ use crate::settings::FunctionalPath;
 
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum FunctionalType {
     PAW_PBE,
     PAW_LDA,
+    PAW_GGA,
+    PAW_PBE_GW,
+    PAW_LDA_GW,
+    US_GGA,
+    US_LDA,
+}
+
+
+impl FunctionalType {
+    pub const ALL: [FunctionalType; 7] = [
+        Self::PAW_PBE,
+        Self::PAW_LDA,
+        Self::PAW_GGA,
+        Self::PAW_PBE_GW,
+        Self::PAW_LDA_GW,
+        Self::US_GGA,
+        Self::US_LDA,
+    ];
+
+    /// Functionals whose path is optional in the settings file.
+    pub const OPTIONAL: [FunctionalType; 5] = [
+        Self::PAW_GGA,
+        Self::PAW_PBE_GW,
+        Self::PAW_LDA_GW,
+        Self::US_GGA,
+        Self::US_LDA,
+    ];
 }
 
 
@@ -28,6 +61,11 @@ impl std::fmt::Display for FunctionalType {
         let s = match self {
             FunctionalType::PAW_PBE => "PAW_PBE",
             FunctionalType::PAW_LDA => "PAW_LDA",
+            FunctionalType::PAW_GGA => "PAW_GGA",
+            FunctionalType::PAW_PBE_GW => "PAW_PBE_GW",
+            FunctionalType::PAW_LDA_GW => "PAW_LDA_GW",
+            FunctionalType::US_GGA => "US_GGA",
+            FunctionalType::US_LDA => "US_LDA",
         };
         write!(f, "{}", s)
     }
@@ -39,11 +77,18 @@ impl std::str::FromStr for FunctionalType {
 
     fn from_str(s: &str) -> Result<Self> {
         let ret = match s {
-            "PAW_PBE" | "paw_pbe" => Self::PAW_PBE,
-            "PAW_LDA" | "paw_lda" => Self::PAW_LDA,
+            "PAW_PBE" | "paw_pbe" | "PBE" | "pbe" => Self::PAW_PBE,
+            "PAW_LDA" | "paw_lda" | "LDA" | "lda" => Self::PAW_LDA,
+            "PAW_GGA" | "paw_gga" | "GGA" | "gga" => Self::PAW_GGA,
+            "PAW_PBE_GW" | "paw_pbe_gw" | "PBE_GW" | "pbe_gw" => Self::PAW_PBE_GW,
+            "PAW_LDA_GW" | "paw_lda_gw" | "LDA_GW" | "lda_gw" => Self::PAW_LDA_GW,
+            "US_GGA" | "us_gga" => Self::US_GGA,
+            "US_LDA" | "us_lda" => Self::US_LDA,
             _ => {
                 bail!(r#"``{}` cannot be converted into FunctionalType.
-Available functionals are `PAW_PBE`(or `paw_pbe`) and `PAW_LDA`(or `paw_lda`)."#, s);
+Available functionals are `PAW_PBE`(or `paw_pbe`, `PBE`), `PAW_LDA`(or `paw_lda`, `LDA`),
+`PAW_GGA`(or `paw_gga`, `GGA`), `PAW_PBE_GW`(or `paw_pbe_gw`, `PBE_GW`), `PAW_LDA_GW`(or
+`paw_lda_gw`, `LDA_GW`), `US_GGA`(or `us_gga`) and `US_LDA`(or `us_lda`)."#, s);
             }
         };
         Ok(ret)
@@ -51,6 +96,45 @@ Available functionals are `PAW_PBE`(or `paw_pbe`) and `PAW_LDA`(or `paw_lda`)."#
 }
 
 
+/// The first two lines of a POTCAR, enough to tell which potential it is without reading the
+/// whole file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PotcarHeader {
+    pub titel: String,      // First line, e.g. 'PAW_PBE Fe_pv 02Aug2007'
+    pub valence: f64,       // Number of valence electrons, i.e. ZVAL
+}
+
+
+impl PotcarHeader {
+    /// Read the header of `POTCAR` in `dir`, the compressed `POTCAR.z`, `POTCAR.Z` or `POTCAR.gz`
+    /// is read if the plain one does not exist.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let path = dir.join("POTCAR");
+        let reader: Box<dyn BufRead> = if path.is_file() {
+            Box::new(BufReader::new(File::open(&path)?))
+        } else {
+            let fname = ["POTCAR.z", "POTCAR.Z", "POTCAR.gz"].iter()
+                .map(|x| dir.join(x))
+                .find(|p| p.is_file())
+                .with_context(|| format!("No POTCAR found in {:?}", dir))?;
+            Box::new(BufReader::new(GzDecoder::new(File::open(fname)?)))
+        };
+
+        let mut lines = reader.lines();
+        let titel = lines.next()
+            .with_context(|| format!("Empty POTCAR in {:?}", dir))??
+            .trim()
+            .to_string();
+        let valence = lines.next()
+            .with_context(|| format!("Incomplete POTCAR in {:?}", dir))??;
+        let valence = valence.trim().parse::<f64>()
+            .with_context(|| format!("Invalid valence {:?} in POTCAR of {:?}", valence.trim(), dir))?;
+
+        Ok(Self { titel, valence })
+    }
+}
+
+
 #[derive(Clone, Debug)]
 pub struct AtomicPotcar {
     pub symbol: String,                 // Element symbol, H, He, Li, Be, B, C ...
@@ -66,39 +150,28 @@ impl AtomicPotcar {
                        specific_type: &str,
                        prefix: &FunctionalPath) -> Result<Self> {
         let titel = symbol.to_string() + specific_type;
+        let dir = prefix.resolve_potcars(&[&titel], *functional)?.remove(0);
+        Self::from_dir(&dir, symbol, functional, specific_type)
+    }
 
-        //let titel = prefix.aliases.as_ref()
-            //.and_then(|tok| { tok.get(&titel).cloned() })
-            //.unwrap_or(titel);
-        let titel = if let Some(alias) = prefix.aliases.as_ref().and_then(|tok| tok.get(&titel)) {
-            info!("Found functional alias: \"{}\" -> \"{}\".", titel, alias);
-            alias.clone()
-        } else {
-            titel
-        };
-
-        let path = {
-            let mut _ret = match functional {
-                FunctionalType::PAW_PBE => prefix.paw_pbe.to_path_buf(),
-                FunctionalType::PAW_LDA => prefix.paw_lda.to_path_buf(),
-            };
-            _ret.push(&titel);
-            _ret.push("POTCAR");
 
-            _ret.canonicalize().unwrap()
-        };
-
-        info!("Reading POTCAR from {:?}", &path);
+    // Read the POTCAR in `dir` resolved by `FunctionalPath::resolve_potcars`, the compressed
+    // `POTCAR.z`, `POTCAR.Z` or `POTCAR.gz` is read if the plain one does not exist.
+    fn from_dir(dir: &Path,
+                symbol: &str,
+                functional: &FunctionalType,
+                specific_type: &str) -> Result<Self> {
+        let path = dir.join("POTCAR");
 
         let content = if path.is_file() {
+            info!("Reading POTCAR from {:?}", &path);
             read_to_string(&path)?
         } else {
-            let fname = vec![
-                path.with_extension(".z"),
-                path.with_extension(".Z"),
-                path.with_extension(".gz"),
-            ].into_iter().find(|p| p.is_file())
-                .context(format!("No suitable POTCAR found for element {}", symbol))?;
+            let fname = ["POTCAR.z", "POTCAR.Z", "POTCAR.gz"].iter()
+                .map(|x| dir.join(x))
+                .find(|p| p.is_file())
+                .with_context(|| format!("No suitable POTCAR found for element {}", symbol))?;
+            info!("Reading POTCAR from {:?}", &fname);
 
             let bytes = std::fs::read(fname)?;
             let mut gz = GzDecoder::new(&bytes[..]);
@@ -126,16 +199,30 @@ pub struct Potcar {
 
 
 impl Potcar {
+    /// Concatenate the POTCARs of `symbols` in order, `specific_types` are the valence
+    /// annotations appended to the symbols, e.g. `_sv`, empty for the default ones. The aliases
+    /// in `prefix` are applied, and every element is checked to have a POTCAR before any of them
+    /// is read, the missing ones are reported at once.
     pub fn from_config(symbols: &[String],
                        functional: &FunctionalType,
                        specific_types: &[String],
                        prefix: &FunctionalPath) -> Result<Self> {
-        let mut inner = Vec::<AtomicPotcar>::new();
-
-        for (sym, spec) in symbols.iter().zip(specific_types.iter()) {
-            inner.push(AtomicPotcar::from_config(sym, functional, spec, prefix)?);
+        if symbols.len() != specific_types.len() {
+            bail!("The count of symbols ({}) doesn't match the count of valence annotations ({}).",
+                  symbols.len(), specific_types.len());
         }
 
+        let titels = symbols.iter().zip(specific_types.iter())
+            .map(|(sym, spec)| sym.to_string() + spec)
+            .collect::<Vec<_>>();
+        let titels = titels.iter().map(String::as_str).collect::<Vec<_>>();
+        let dirs = prefix.resolve_potcars(&titels, *functional)?;
+
+        let inner = dirs.iter()
+            .zip(symbols.iter().zip(specific_types.iter()))
+            .map(|(dir, (sym, spec))| AtomicPotcar::from_dir(dir, sym, functional, spec))
+            .collect::<Result<Vec<_>>>()?;
+
         Ok(Self { inner })
     }
 
@@ -161,6 +248,67 @@ impl Potcar {
 mod tests {
     use super::*;
     use crate::settings::Settings;
+    use flate2::{
+        write::GzEncoder,
+        Compression,
+    };
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_potcar_header() {
+        let dir = TempDir::new("rsgrad_test").unwrap();
+        let content = "  PAW_PBE Fe_pv 02Aug2007\n 14.0000000000000000\n parameters from PSCTR are:\n";
+
+        let plain = dir.path().join("Fe_pv");
+        std::fs::create_dir(&plain).unwrap();
+        std::fs::write(plain.join("POTCAR"), content).unwrap();
+        let header = PotcarHeader::from_dir(&plain).unwrap();
+        assert_eq!(header.titel, "PAW_PBE Fe_pv 02Aug2007");
+        assert_eq!(header.valence, 14.0);
+
+        let gz = dir.path().join("Fe");
+        std::fs::create_dir(&gz).unwrap();
+        let mut encoder = GzEncoder::new(File::create(gz.join("POTCAR.gz")).unwrap(), Compression::default());
+        encoder.write_all(content.replace("Fe_pv", "Fe").as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(PotcarHeader::from_dir(&gz).unwrap().titel, "PAW_PBE Fe 02Aug2007");
+
+        let empty = dir.path().join("Fe_sv");
+        std::fs::create_dir(&empty).unwrap();
+        assert!(PotcarHeader::from_dir(&empty).is_err());
+        std::fs::write(empty.join("POTCAR"), "  PAW_PBE Fe_sv 23Dec2003\n").unwrap();
+        assert!(PotcarHeader::from_dir(&empty).is_err());
+    }
+
+    #[test]
+    fn test_potcar_concatenation() {
+        let potcar = |titel: &str| format!("  PAW_PBE {} 02Aug2007\n 1.0\n End of Dataset\n", titel);
+        let (_dir, mut prefix) = crate::settings::potential_dirs(&[
+            ("potpaw_PBE/Fe_pv/POTCAR", &potcar("Fe_pv")),
+            ("potpaw_PBE/H/POTCAR", &potcar("H")),
+        ]);
+        std::fs::create_dir(prefix.paw_pbe.join("O")).unwrap();
+        let mut encoder = GzEncoder::new(File::create(prefix.paw_pbe.join("O").join("POTCAR.Z")).unwrap(), Compression::default());
+        encoder.write_all(potcar("O").as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        prefix.aliases = Some(crate::settings::Aliases {
+            common: std::collections::HashMap::from([("Fe".to_string(), "Fe_pv".to_string())]),
+            functional: std::collections::HashMap::new(),
+        });
+        let strings = |x: &[&str]| x.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let pot = Potcar::from_config(&strings(&["H", "Fe", "O"]), &FunctionalType::PAW_PBE, &strings(&["", "", ""]), &prefix).unwrap();
+        assert_eq!(pot.to_txt(), potcar("H") + &potcar("Fe_pv") + &potcar("O"));
+        assert_eq!(pot.inner.iter().map(|x| x.symbol.as_str()).collect::<Vec<_>>(), vec!["H", "Fe", "O"]);
+
+        // All missing ones are reported before reading any of them
+        let msg = Potcar::from_config(&strings(&["Li", "O", "N"]), &FunctionalType::PAW_PBE, &strings(&["", "", ""]), &prefix)
+            .err().unwrap().to_string();
+        assert!(msg.contains("Li -> ") && msg.contains("N -> ") && !msg.contains("O -> "), "{}", msg);
+        assert!(Potcar::from_config(&strings(&["H", "O"]), &FunctionalType::PAW_LDA, &strings(&["", ""]), &prefix).is_err());
+        assert!(Potcar::from_config(&strings(&["H", "O"]), &FunctionalType::PAW_PBE, &strings(&[""]), &prefix).is_err());
+    }
 
     #[test]
     #[ignore]
@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use rsgrad::{
+    Result,
+    ChargeDensity,
+    ChargeType,
+    types::Axis,
+};
+use tempdir::TempDir;
+
+
+macro_rules! get_fpath_in_current_dir {
+    ($fname:expr) => {{
+        let mut path = PathBuf::from(file!());
+        path.pop();
+        path.push($fname);
+        path
+    }}
+}
+
+
+#[test]
+fn test_read_invalid_utf8_title() -> Result<()> {
+    let fname = get_fpath_in_current_dir!("CHGCAR.invalid_utf8_title");
+    assert!(std::fs::read_to_string(&fname).is_err());
+
+    let chg = ChargeDensity::from_file(&fname, ChargeType::Chgcar)?;
+    assert_eq!(chg.pos.comment, "Li bulk \u{FFFD}\u{FFFD} generated");
+    assert_eq!(chg.ngrid, [2, 3, 4]);
+    assert_eq!(chg.chg.len(), 1);
+
+    let avg = ChargeDensity::planar_average_from_file(&fname, ChargeType::Chgcar, Axis::Z)?;
+    assert_eq!(avg.len(), 4);
+
+    // The lossy title is preserved on writing
+    let dir = TempDir::new("rsgrad_test")?;
+    let out = dir.path().join("CHGCAR");
+    chg.to_file(&out)?;
+    let txt = std::fs::read_to_string(&out)?;
+    assert_eq!(txt.lines().next().unwrap().trim(), "Li bulk \u{FFFD}\u{FFFD} generated");
+
+    Ok(())
+}